@@ -0,0 +1,91 @@
+//! Bonds that hold neighboring cells together.
+//!
+//! Every cycle each cell decides which of its neighbors it wants to be bonded to. A bond forms
+//! between two cells that both want it and holds until neither does, or until the cells are torn
+//! apart by one of them moving away. Cells joined by bonds, directly or through other cells, form
+//! a group, and a group moves as one when enough of its cells try to move the same way.
+
+use rand::Rng;
+
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Adhesion {
+    /// Whether cells can bond at all.
+    pub enabled: bool,
+    /// The fraction of a group's cells that have to try to move the same way for the group to move.
+    pub quorum: f64,
+}
+
+impl Default for Adhesion {
+    fn default() -> Self {
+        Adhesion {
+            enabled: false,
+            quorum: 0.5,
+        }
+    }
+}
+
+impl Adhesion {
+    /// The direction a group of `size` cells moves in, given how many of them tried to move in each
+    /// direction, or `None` if not enough of them agree.
+    ///
+    /// Ties are broken at random. The random number generator is only used to break a tie.
+    pub fn vote<R: Rng>(&self, votes: &[usize; 6], size: usize, rng: &mut R) -> Option<usize> {
+        let most = *votes.iter().max().unwrap();
+        if most == 0 || (most as f64) < self.quorum * size as f64 {
+            return None;
+        }
+        let top: Vec<usize> = (0..votes.len()).filter(|&d| votes[d] == most).collect();
+        Some(if top.len() == 1 {
+            top[0]
+        } else {
+            top[rng.gen_range(0, top.len())]
+        })
+    }
+}
+
+/// Cells joined by bonds, directly or through other cells. A cell without bonds is a group of its
+/// own.
+#[derive(Debug, Clone, Default)]
+pub struct Groups {
+    /// The group of the cell on each tile, or `None` where there is no cell.
+    pub of: Vec<Option<usize>>,
+    /// The tiles of the cells in each group. Groups are numbered in the order of their first
+    /// tile.
+    pub members: Vec<Vec<usize>>,
+}
+
+impl Groups {
+    /// Finds the groups given the tiles the cell on each tile is bonded to, with `None` for tiles
+    /// without a cell. Bonds must go both ways.
+    pub fn find(bonds: &[Option<[Option<usize>; 6]>]) -> Groups {
+        let mut groups = Groups {
+            of: vec![None; bonds.len()],
+            members: Vec::new(),
+        };
+        let mut queue = VecDeque::new();
+        for start in 0..bonds.len() {
+            if bonds[start].is_none() || groups.of[start].is_some() {
+                continue;
+            }
+            let group = groups.members.len();
+            let mut members = Vec::new();
+            groups.of[start] = Some(group);
+            queue.push_back(start);
+            while let Some(i) = queue.pop_front() {
+                members.push(i);
+                for n in bonds[i].iter().flat_map(|links| links.iter().filter_map(|&n| n)) {
+                    if groups.of[n].is_none() {
+                        groups.of[n] = Some(group);
+                        queue.push_back(n);
+                    }
+                }
+            }
+            members.sort();
+            groups.members.push(members);
+        }
+        groups
+    }
+}
@@ -1,6 +1,9 @@
-use rand::{Rng, Rand, Isaac64Rng};
-use mli::{MateRand, Stateless, Mutate};
-use mli_mep::Mep;
+use rand::{Rng, Isaac64Rng};
+use mli::Stateless;
+
+use std::cmp;
+use std::collections::BTreeSet;
+use std::str::FromStr;
 
 // 0.0, 0.5, -0.5, 1.0, -1.0, 2.0, -2.0, MAX, MIN
 pub const CONST_INPUTS: usize = 9;
@@ -8,15 +11,30 @@ pub const CONST_INPUTS: usize = 9;
 pub const STATIC_INPUTS: usize = 1 + 7 * 8 + 6;
 pub const TOTAL_MEMORY: usize = 4;
 pub const TOTAL_INPUTS: usize = CONST_INPUTS + STATIC_INPUTS + TOTAL_MEMORY;
-// Coefficients (8 * 6), Movement(7), Mate(13), Turn(6), Explode, Suicide
-pub const STATIC_OUTPUTS: usize = 8 * 6 + 7 + 13 + 6 + 1 + 1;
+// Coefficients (8 * 6), Movement(7), Mate(13), Turn(6), Explode, Suicide, Mate consent(6),
+// Bond(6)
+pub const STATIC_OUTPUTS: usize = 8 * 6 + 7 + 13 + 6 + 1 + 1 + CONSENT_OUTPUTS + BOND_OUTPUTS;
+/// Outputs brains from before mates had to consent don't have. They come right before the bond
+/// outputs.
+pub const CONSENT_OUTPUTS: usize = 6;
+/// Outputs brains from before cells could bond don't have. They come last among the static
+/// outputs.
+pub const BOND_OUTPUTS: usize = 6;
+/// The outputs added to brains after they were first saved, in order, each with the constant
+/// input older brains get them from.
+const ADDED_OUTPUTS: [(usize, usize); 2] = [
+    // Constant input 3 is 1, so the brain consents to every mate.
+    (CONSENT_OUTPUTS, 3),
+    // Constant input 0 is 0, so the brain never bonds.
+    (BOND_OUTPUTS, 0),
+];
 pub const TOTAL_OUTPUTS: usize = STATIC_OUTPUTS + TOTAL_MEMORY;
 pub const DEFAULT_MUTATE_LAMBDA: usize = 128;
 pub const DEFAULT_CROSSOVER_POINTS: usize = 1;
 pub const INTERNAL_INSTRUCTIONS: usize = 4096;
 const MUTATE_PROBABILITY: f64 = 1.0;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Ins {
     _NOP,
     _ADD,
@@ -34,7 +52,109 @@ pub enum Ins {
     _LN,
     _SIN,
     _SQT,
-    MAX,
+    _ABS,
+    _MIN,
+    _MAX,
+    _TANH,
+    _COS,
+    /// The second operand if the first is at least 1, else 0.
+    _IF,
+    _NEG,
+    /// Division giving 0 instead of NaN or infinity.
+    _PDIV,
+    /// Logarithm giving 0 instead of NaN or infinity.
+    _PLOG,
+}
+
+/// Every instruction in encoding order.
+pub const INSTRUCTIONS: [Ins; 25] = [
+    Ins::_NOP,
+    Ins::_ADD,
+    Ins::_SUB,
+    Ins::_MUL,
+    Ins::_DIV,
+    Ins::_MOD,
+    Ins::_GRT,
+    Ins::_LES,
+    Ins::_AND,
+    Ins::_OR,
+    Ins::_POW,
+    Ins::_EXP,
+    Ins::_LOG,
+    Ins::_LN,
+    Ins::_SIN,
+    Ins::_SQT,
+    Ins::_ABS,
+    Ins::_MIN,
+    Ins::_MAX,
+    Ins::_TANH,
+    Ins::_COS,
+    Ins::_IF,
+    Ins::_NEG,
+    Ins::_PDIV,
+    Ins::_PLOG,
+];
+
+/// The instructions brains were built from before the instruction set could be configured.
+const ORIGINAL_INSTRUCTIONS: usize = 16;
+
+impl Ins {
+    /// Whether the instruction ignores its second operand.
+    pub fn unary(&self) -> bool {
+        matches!(
+            *self,
+            Ins::_NOP | Ins::_EXP | Ins::_LN | Ins::_SIN | Ins::_SQT | Ins::_ABS | Ins::_TANH |
+                Ins::_COS | Ins::_NEG
+        )
+    }
+
+    /// The name used for the instruction in configuration.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Ins::_NOP => "nop",
+            Ins::_ADD => "add",
+            Ins::_SUB => "sub",
+            Ins::_MUL => "mul",
+            Ins::_DIV => "div",
+            Ins::_MOD => "mod",
+            Ins::_GRT => "grt",
+            Ins::_LES => "les",
+            Ins::_AND => "and",
+            Ins::_OR => "or",
+            Ins::_POW => "pow",
+            Ins::_EXP => "exp",
+            Ins::_LOG => "log",
+            Ins::_LN => "ln",
+            Ins::_SIN => "sin",
+            Ins::_SQT => "sqt",
+            Ins::_ABS => "abs",
+            Ins::_MIN => "min",
+            Ins::_MAX => "max",
+            Ins::_TANH => "tanh",
+            Ins::_COS => "cos",
+            Ins::_IF => "if",
+            Ins::_NEG => "neg",
+            Ins::_PDIV => "pdiv",
+            Ins::_PLOG => "plog",
+        }
+    }
+}
+
+impl FromStr for Ins {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        INSTRUCTIONS
+            .iter()
+            .find(|i| i.name() == s)
+            .cloned()
+            .ok_or_else(|| format!("unknown instruction {:?}", s))
+    }
+}
+
+/// Gives 0 in place of NaN or infinity.
+fn protect(x: f64) -> f64 {
+    if x.is_finite() { x } else { 0.0 }
 }
 
 impl<'a> Stateless<'a, (f64, f64), f64> for Ins {
@@ -56,71 +176,564 @@ impl<'a> Stateless<'a, (f64, f64), f64> for Ins {
             Ins::_LN => a.ln(),
             Ins::_SIN => a.sin(),
             Ins::_SQT => a.sqrt(),
-            Ins::MAX => unreachable!(),
+            Ins::_ABS => a.abs(),
+            Ins::_MIN => a.min(b),
+            Ins::_MAX => a.max(b),
+            Ins::_TANH => a.tanh(),
+            Ins::_COS => a.cos(),
+            Ins::_IF => if a >= 1.0 { b } else { 0.0 },
+            Ins::_NEG => -a,
+            Ins::_PDIV => protect(a / b),
+            Ins::_PLOG => protect(a.log(b)),
         }
     }
 }
 
-impl Rand for Ins {
-    fn rand<R: Rng>(rng: &mut R) -> Self {
-        use std::mem;
-        unsafe { mem::transmute(rng.gen_range::<u8>(0, Ins::MAX as u8)) }
+/// The instructions brains are built from and how often each is picked.
+///
+/// Instructions are picked with probability proportional to their weight, both when a brain is
+/// first made and when an instruction is mutated. Instructions already in a brain keep working
+/// whether or not they are in the set.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct InstructionSet {
+    /// Instructions with a positive weight in encoding order.
+    weights: Vec<(Ins, f64)>,
+}
+
+impl InstructionSet {
+    /// Fails if a weight is negative or not finite, or no instruction has a positive weight.
+    pub fn new<I: IntoIterator<Item = (Ins, f64)>>(weights: I) -> Result<Self, String> {
+        let mut weights: Vec<(Ins, f64)> = weights.into_iter().collect();
+        if let Some(&(ins, w)) = weights.iter().find(|&&(_, w)| !(w >= 0.0 && w.is_finite())) {
+            return Err(format!("invalid weight {} for instruction {}", w, ins.name()));
+        }
+        weights.retain(|&(_, w)| w > 0.0);
+        weights.sort_by_key(|&(ins, _)| ins as usize);
+        weights.dedup_by_key(|&mut (ins, _)| ins);
+        if weights.is_empty() {
+            return Err("the instruction set is empty".to_string());
+        }
+        Ok(InstructionSet { weights: weights })
+    }
+
+    /// Parses instruction names mapped to weights.
+    pub fn from_names<'a, I: IntoIterator<Item = (&'a String, &'a f64)>>(
+        names: I,
+    ) -> Result<Self, String> {
+        let mut weights = Vec::new();
+        for (name, &w) in names {
+            weights.push((name.parse()?, w));
+        }
+        InstructionSet::new(weights)
+    }
+
+    /// The instructions in the set along with their weights.
+    pub fn weights(&self) -> &[(Ins, f64)] {
+        &self.weights
+    }
+
+    /// Picks an instruction.
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> Ins {
+        let first = self.weights[0].1;
+        if self.weights.iter().all(|&(_, w)| w == first) {
+            // Sampled exactly as before the set was configurable so old runs can be reproduced.
+            return self.weights[rng.gen_range::<u8>(0, self.weights.len() as u8) as usize].0;
+        }
+        let total: f64 = self.weights.iter().map(|&(_, w)| w).sum();
+        let mut pick = rng.gen_range(0.0, total);
+        for &(ins, w) in &self.weights {
+            if pick < w {
+                return ins;
+            }
+            pick -= w;
+        }
+        self.weights[self.weights.len() - 1].0
     }
 }
 
-impl<R> Mutate<R> for Ins
-where
-    R: Rng,
-{
-    fn mutate(&mut self, rng: &mut R) {
-        *self = rng.gen()
+impl Default for InstructionSet {
+    /// The original instructions, all equally likely.
+    fn default() -> Self {
+        let original = INSTRUCTIONS[..ORIGINAL_INSTRUCTIONS].iter().map(|&i| (i, 1.0));
+        InstructionSet::new(original).unwrap()
     }
 }
 
+/// One instruction of a `Program`.
 #[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct Brain {
-    pub mep: Mep<Ins>,
-    pub memory: [f64; TOTAL_MEMORY],
+pub struct Op {
+    pub instruction: Ins,
+    /// Operands address the inputs first and then the results of earlier instructions, so
+    /// `first - inputs` is the index of an instruction when `first >= inputs`.
+    pub first: usize,
+    pub second: usize,
 }
 
-impl Brain {
-    pub fn new(rng: &mut Isaac64Rng) -> Self {
-        Brain {
-            mep: Mep::new(
-                TOTAL_INPUTS,
-                TOTAL_OUTPUTS,
-                INTERNAL_INSTRUCTIONS,
-                DEFAULT_MUTATE_LAMBDA,
-                DEFAULT_CROSSOVER_POINTS,
-                rng,
-            ),
-            memory: [0.0; TOTAL_MEMORY],
+/// A multi expression program over `Ins`.
+///
+/// Brains used to be an `mli_mep::Mep`. This has the same fields in the same order so it encodes
+/// identically, and it mates and mutates exactly the same way so runs carry on as they did.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Program {
+    pub program: Vec<Op>,
+    pub mutate_lambda: usize,
+    pub crossover_points: usize,
+    pub inputs: usize,
+    /// The last `outputs` instructions produce the outputs.
+    pub outputs: usize,
+}
+
+impl Program {
+    /// The index of the instruction at `address`, or `None` if it addresses an input.
+    pub fn instruction(&self, address: usize) -> Option<usize> {
+        address.checked_sub(self.inputs)
+    }
+
+    /// Crosses the program with another at a random number of points.
+    fn mate<R: Rng>(&self, other: &Program, rng: &mut R) -> Program {
+        assert_eq!(self.inputs, other.inputs);
+        assert_eq!(self.outputs, other.outputs);
+        let len = cmp::min(self.program.len(), other.program.len());
+        let points = if rng.gen_range::<i32>(0, 2) == 0 {
+            self.crossover_points
+        } else {
+            other.crossover_points
+        };
+        let most = cmp::min((len / 2).saturating_add(2), points.saturating_add(2));
+        let cuts: BTreeSet<usize> = (0..rng.gen_range(1, most))
+            .map(|_| rng.gen_range(0, len))
+            .chain(Some(len))
+            .collect();
+        let mut program = Vec::with_capacity(len);
+        let mut start = 0;
+        // Take the pieces between cuts from each parent in turn.
+        for (n, &end) in cuts.iter().enumerate() {
+            let parent = if n % 2 == 0 { self } else { other };
+            program.extend_from_slice(&parent.program[start..end]);
+            start = end;
+        }
+        let between = |rng: &mut R, a: usize, b: usize| {
+            rng.gen_range(cmp::min(a, b), cmp::max(a, b).saturating_add(1))
+        };
+        Program {
+            program: program,
+            mutate_lambda: between(rng, self.mutate_lambda, other.mutate_lambda),
+            crossover_points: between(rng, self.crossover_points, other.crossover_points),
+            inputs: self.inputs,
+            outputs: self.outputs,
         }
     }
 
-    pub fn mutate(&mut self, rng: &mut Isaac64Rng) {
-        if rng.gen_range(0.0, 1.0) < MUTATE_PROBABILITY {
-            self.mep.mutate(rng);
+    /// Mutates the mutation parameters and a random number of operations, picking new
+    /// instructions from `instructions`.
+    fn mutate<R: Rng>(&mut self, instructions: &InstructionSet, rng: &mut R) {
+        // The lambda from before this mutation applies throughout it.
+        let lambda = self.mutate_lambda.saturating_add(1);
+        let step = |rng: &mut R, x: usize| if rng.gen_range::<i32>(0, 2) == 0 {
+            x.saturating_add(1)
+        } else {
+            x.saturating_sub(1)
+        };
+        if rng.gen_range(0, lambda) == 0 {
+            self.mutate_lambda = step(rng, self.mutate_lambda);
+        }
+        if rng.gen_range(0, lambda) == 0 {
+            self.crossover_points = step(rng, self.crossover_points);
+        }
+
+        let len = self.program.len();
+        loop {
+            // Picking past the end stops mutating.
+            let choice = rng.gen_range(0, len.saturating_add(lambda));
+            if choice >= len {
+                break;
+            }
+            // Outputs can use any internal instruction and the rest only what comes before them.
+            let operands = if choice >= len - self.outputs {
+                self.inputs + len - self.outputs
+            } else {
+                choice + self.inputs
+            };
+            let op = &mut self.program[choice];
+            match rng.gen_range::<i32>(0, 3) {
+                0 => op.instruction = instructions.sample(rng),
+                1 => op.first = rng.gen_range(0, operands),
+                _ => op.second = rng.gen_range(0, operands),
+            }
+        }
+    }
+}
+
+impl Op {
+    /// The addresses the result depends on.
+    pub fn operands(&self) -> Vec<usize> {
+        if self.instruction.unary() {
+            vec![self.first]
+        } else {
+            vec![self.first, self.second]
+        }
+    }
+}
+
+/// A `Program` cut down to the instructions that affect an output.
+///
+/// Inputs and results live in one list of registers: the inputs come first and each kept
+/// instruction writes to the register after the previous one. Running it gives exactly the same
+/// outputs as running every instruction of the program, since every kept instruction is processed
+/// on the same operands in the same order.
+#[derive(Clone, Debug, Default)]
+struct Compiled {
+    /// Instructions with operands given as registers.
+    ops: Vec<(Ins, usize, usize)>,
+    /// The register holding each output.
+    outputs: Vec<usize>,
+    registers: Vec<f64>,
+}
+
+impl Compiled {
+    fn new(program: &Program) -> Compiled {
+        let len = program.program.len();
+        let mut live = vec![false; len];
+        for l in &mut live[len - program.outputs..] {
+            *l = true;
+        }
+        // Operands always come before the instruction using them.
+        for i in (0..len).rev() {
+            if live[i] {
+                for address in program.program[i].operands() {
+                    if let Some(j) = program.instruction(address) {
+                        live[j] = true;
+                    }
+                }
+            }
+        }
+
+        let mut ops = Vec::new();
+        // The register of every address, valid for inputs and live instructions.
+        let mut registers: Vec<usize> = (0..program.inputs).collect();
+        for (i, op) in program.program.iter().enumerate() {
+            if !live[i] {
+                registers.push(0);
+                continue;
+            }
+            let first = registers[op.first];
+            match op.instruction {
+                // Passing a value on doesn't need a register of its own.
+                Ins::_NOP => registers.push(first),
+                instruction => {
+                    // The second operand of a unary instruction is ignored, so any register will
+                    // do.
+                    let second = if instruction.unary() {
+                        first
+                    } else {
+                        registers[op.second]
+                    };
+                    ops.push((instruction, first, second));
+                    registers.push(program.inputs + ops.len() - 1);
+                }
+            }
+        }
+        Compiled {
+            outputs: registers[program.inputs + len - program.outputs..].to_vec(),
+            registers: vec![0.0; program.inputs + ops.len()],
+            ops: ops,
+        }
+    }
+
+    fn process(&mut self, inputs: &[f64], outputs: &mut [f64]) {
+        assert_eq!(inputs.len() + self.ops.len(), self.registers.len());
+        assert_eq!(outputs.len(), self.outputs.len());
+        let (inputs_len, registers) = (inputs.len(), &mut self.registers);
+        registers[..inputs_len].copy_from_slice(inputs);
+        for (i, &(ref instruction, first, second)) in self.ops.iter().enumerate() {
+            registers[inputs_len + i] = instruction.process((registers[first], registers[second]));
+        }
+        for (output, &register) in outputs.iter_mut().zip(&self.outputs) {
+            *output = registers[register];
         }
     }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Brain {
+    program: Program,
+    pub memory: [f64; TOTAL_MEMORY],
+    /// Built when the brain is made, or on first use after it is loaded.
+    #[serde(skip)]
+    compiled: Option<Compiled>,
+}
+
+impl Brain {
+    pub fn new(instructions: &InstructionSet, rng: &mut Isaac64Rng) -> Self {
+        // Generated the same way as `Mep::new` used to.
+        let (inputs, internal) = (TOTAL_INPUTS, INTERNAL_INSTRUCTIONS);
+        let program = (0..internal + TOTAL_OUTPUTS)
+            .map(|i| {
+                let operands = if i < internal { i + inputs } else { internal + inputs };
+                Op {
+                    instruction: instructions.sample(rng),
+                    first: rng.gen_range(0, operands),
+                    second: rng.gen_range(0, operands),
+                }
+            })
+            .collect();
+        let program = Program {
+            program: program,
+            mutate_lambda: DEFAULT_MUTATE_LAMBDA,
+            crossover_points: DEFAULT_CROSSOVER_POINTS,
+            inputs: inputs,
+            outputs: TOTAL_OUTPUTS,
+        };
+        Brain::from_parts(program, [0.0; TOTAL_MEMORY])
+    }
 
-    pub fn mate(&self, other: &Self, rng: &mut Isaac64Rng) -> Self {
+    pub fn from_parts(program: Program, memory: [f64; TOTAL_MEMORY]) -> Self {
         let mut b = Brain {
-            mep: self.mep.mate(&other.mep, rng),
-            memory: [0.0; TOTAL_MEMORY],
+            program: program,
+            memory: memory,
+            compiled: None,
         };
-        // Perform unit mutations on offspring.
-        b.mutate(rng);
+        b.compile();
         b
     }
 
-    pub fn divide(&self, rng: &mut Isaac64Rng) -> Self {
-        let mut b = Brain {
-            mep: self.mep.clone(),
-            memory: [0.0; TOTAL_MEMORY],
+    /// Gives a brain from an older version the outputs added since. They consent to every mate
+    /// and never bond, so the brain behaves as it always did.
+    pub fn upgrade(&mut self) {
+        let program = &mut self.program;
+        let missing = (0..ADDED_OUTPUTS.len()).find(|&first| {
+            let added: usize = ADDED_OUTPUTS[first..].iter().map(|&(n, _)| n).sum();
+            program.outputs + added == TOTAL_OUTPUTS
+        });
+        let first = match missing {
+            Some(first) => first,
+            None => return,
         };
+        for &(outputs, input) in &ADDED_OUTPUTS[first..] {
+            let op = Op {
+                instruction: Ins::_NOP,
+                first: input,
+                second: input,
+            };
+            let at = program.program.len() - TOTAL_MEMORY;
+            for _ in 0..outputs {
+                program.program.insert(at, op.clone());
+            }
+        }
+        program.outputs = TOTAL_OUTPUTS;
+        self.compile();
+    }
+
+    fn compile(&mut self) {
+        self.compiled = Some(Compiled::new(&self.program));
+    }
+
+    /// The instructions of the brain.
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    /// Computes the outputs for the given inputs.
+    pub fn process(&mut self, inputs: &[f64], outputs: &mut [f64]) {
+        if self.compiled.is_none() {
+            self.compile();
+        }
+        self.compiled.as_mut().unwrap().process(inputs, outputs);
+    }
+
+    pub fn mate(
+        &self,
+        other: &Self,
+        instructions: &InstructionSet,
+        rng: &mut Isaac64Rng,
+    ) -> Self {
+        Brain::offspring(self.program.mate(&other.program, rng), instructions, rng)
+    }
+
+    pub fn divide(&self, instructions: &InstructionSet, rng: &mut Isaac64Rng) -> Self {
+        Brain::offspring(self.program.clone(), instructions, rng)
+    }
+
+    fn offspring(
+        mut program: Program,
+        instructions: &InstructionSet,
+        rng: &mut Isaac64Rng,
+    ) -> Self {
         // Perform unit mutations on offspring.
-        b.mutate(rng);
-        b
+        if rng.gen_range(0.0, 1.0) < MUTATE_PROBABILITY {
+            program.mutate(instructions, rng);
+        }
+        Brain::from_parts(program, [0.0; TOTAL_MEMORY])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bincode;
+    use mli::{MateRand, Mutate};
+    use mli_mep::Mep;
+    use rand::SeedableRng;
+    use std::f64;
+
+    /// The `Mep` a program stands in for, to check against.
+    fn mep(program: &Program) -> Mep<Ins> {
+        let bytes = bincode::serialize(program, bincode::Infinite).unwrap();
+        bincode::deserialize(&bytes).unwrap()
+    }
+
+    /// Lets `Mep::mutate` pick instructions from a set.
+    struct Sampler<'a> {
+        rng: &'a mut Isaac64Rng,
+        instructions: &'a InstructionSet,
+    }
+
+    impl<'a> Rng for Sampler<'a> {
+        fn next_u32(&mut self) -> u32 {
+            self.rng.next_u32()
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            self.rng.next_u64()
+        }
+
+        fn next_f32(&mut self) -> f32 {
+            self.rng.next_f32()
+        }
+
+        fn next_f64(&mut self) -> f64 {
+            self.rng.next_f64()
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            self.rng.fill_bytes(dest)
+        }
+    }
+
+    impl<'a> Mutate<Sampler<'a>> for Ins {
+        fn mutate(&mut self, rng: &mut Sampler<'a>) {
+            *self = rng.instructions.sample(rng.rng)
+        }
+    }
+
+    #[test]
+    fn mating_and_mutation_match_mep() {
+        let mut rng = Isaac64Rng::from_seed(&[5, 6, 7, 8]);
+        let instructions =
+            InstructionSet::new(vec![(Ins::_ADD, 1.0), (Ins::_IF, 2.0), (Ins::_PDIV, 0.5)])
+                .unwrap();
+        let a = Brain::new(&instructions, &mut rng).program;
+        let mut b = Brain::new(&instructions, &mut rng).program;
+        // Cross over more and mutate more than new brains do.
+        b.crossover_points = 6;
+        b.mutate_lambda = 2;
+        for _ in 0..16 {
+            let mut theirs = rng;
+            let mut child = a.mate(&b, &mut rng);
+            child.mutate(&instructions, &mut rng);
+            let mut expected = mep(&a).mate(&mep(&b), &mut theirs);
+            expected.mutate(&mut Sampler {
+                rng: &mut theirs,
+                instructions: &instructions,
+            });
+            assert_eq!(
+                bincode::serialize(&child, bincode::Infinite).unwrap(),
+                bincode::serialize(&expected, bincode::Infinite).unwrap()
+            );
+            assert_eq!(rng.next_u64(), theirs.next_u64());
+            b = child;
+        }
+    }
+
+    #[test]
+    fn compiled_matches_mep() {
+        let mut rng = Isaac64Rng::from_seed(&[4, 3, 2, 1]);
+        let specials = [0.0, -0.0, 1.0, f64::MAX, f64::MIN, f64::INFINITY, f64::NAN];
+        let instructions = InstructionSet::new(INSTRUCTIONS.iter().map(|&i| (i, 1.0))).unwrap();
+        let mut brains = vec![
+            Brain::new(&instructions, &mut rng),
+            Brain::new(&instructions, &mut rng),
+        ];
+        for _ in 0..8 {
+            let child = brains[0]
+                .mate(&brains[1], &instructions, &mut rng)
+                .divide(&instructions, &mut rng);
+            brains.push(child);
+        }
+        for brain in &mut brains {
+            for _ in 0..8 {
+                let inputs: Vec<f64> = (0..TOTAL_INPUTS)
+                    .map(|_| if rng.gen() {
+                        specials[rng.gen_range(0, specials.len())]
+                    } else {
+                        rng.gen_range(-10.0, 10.0)
+                    })
+                    .collect();
+                let expected: Vec<u64> =
+                    mep(&brain.program).process(&inputs[..]).map(f64::to_bits).collect();
+                let mut outputs = [0.0; TOTAL_OUTPUTS];
+                brain.process(&inputs, &mut outputs);
+                let actual: Vec<u64> = outputs.iter().map(|o| o.to_bits()).collect();
+                assert_eq!(actual, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn old_brains_consent_to_everyone_and_never_bond() {
+        let mut rng = Isaac64Rng::from_seed(&[7, 7]);
+        let mut brain = Brain::new(&InstructionSet::default(), &mut rng);
+        let bonds = STATIC_OUTPUTS - BOND_OUTPUTS..STATIC_OUTPUTS;
+        let consent = bonds.start - CONSENT_OUTPUTS..bonds.start;
+        // Take the newer outputs back out to get brains like those from before them.
+        for &removed in &[BOND_OUTPUTS, CONSENT_OUTPUTS + BOND_OUTPUTS] {
+            let mut program = brain.program().clone();
+            let end = program.program.len() - TOTAL_MEMORY;
+            program.program.drain(end - removed..end);
+            program.outputs -= removed;
+            let mut old = Brain::from_parts(program, [0.0; TOTAL_MEMORY]);
+            old.upgrade();
+
+            let mut inputs: Vec<f64> =
+                (0..TOTAL_INPUTS).map(|_| rng.gen_range(-2.0, 2.0)).collect();
+            inputs[0] = 0.0;
+            inputs[3] = 1.0;
+            let mut expected = [0.0; TOTAL_OUTPUTS];
+            brain.process(&inputs, &mut expected);
+            let mut actual = [0.0; TOTAL_OUTPUTS];
+            old.process(&inputs, &mut actual);
+            assert!(actual[bonds.clone()].iter().all(|&o| o == 0.0));
+            let kept = if removed > BOND_OUTPUTS {
+                assert!(actual[consent.clone()].iter().all(|&o| o == 1.0));
+                consent.start
+            } else {
+                bonds.start
+            };
+            for i in (0..TOTAL_OUTPUTS).filter(|&i| i < kept || i >= bonds.end) {
+                assert_eq!(actual[i].to_bits(), expected[i].to_bits());
+            }
+        }
+    }
+
+    #[test]
+    fn instruction_set() {
+        let mut rng = Isaac64Rng::from_seed(&[1, 2, 3, 4]);
+        let set = InstructionSet::new(vec![(Ins::_PDIV, 3.0), (Ins::_IF, 1.0), (Ins::_ADD, 0.0)])
+            .unwrap();
+        let picks: Vec<Ins> = (0..1000).map(|_| set.sample(&mut rng)).collect();
+        let pdivs = picks.iter().filter(|&&i| i == Ins::_PDIV).count();
+        assert_eq!(picks.len() - pdivs, picks.iter().filter(|&&i| i == Ins::_IF).count());
+        assert!(pdivs > 700 && pdivs < 800);
+        assert!(InstructionSet::new(vec![(Ins::_ADD, 0.0)]).is_err());
+        assert!(InstructionSet::new(vec![(Ins::_ADD, f64::NAN)]).is_err());
+
+        // Finite operands never give NaN or infinity, whatever else they are.
+        let specials = [0.0, -0.0, -1.0, 1.0, f64::MAX, f64::MIN];
+        for &a in &specials {
+            for &b in &specials {
+                assert!(Ins::_PDIV.process((a, b)).is_finite());
+                assert!(Ins::_PLOG.process((a, b)).is_finite());
+            }
+        }
     }
 }
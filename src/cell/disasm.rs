@@ -0,0 +1,323 @@
+//! Readable listings of what a brain computes.
+//!
+//! Only the instructions an output actually depends on are listed, as expressions over named
+//! inputs. An instruction used by more than one of those is given a name like `t42`, after its
+//! index in the program, and listed once ahead of the outputs so the listing stays as long as the
+//! program at worst. Directions are relative to the way the cell is facing.
+
+use super::brain::{Brain, Ins, Program, CONST_INPUTS, TOTAL_MEMORY};
+use fluid::{NAMES as FLUIDS, TOTAL_FLUIDS};
+
+use std::io::{self, Write};
+
+const CONSTANTS: [&str; CONST_INPUTS] = ["0", "0.5", "-0.5", "1", "-1", "2", "-2", "MAX", "MIN"];
+
+/// The name of input `i` in the order `Cell::decide` passes them.
+pub fn input_name(i: usize) -> String {
+    if i < CONST_INPUTS {
+        return CONSTANTS[i].to_string();
+    }
+    let i = i - CONST_INPUTS;
+    if i == 0 {
+        return "inhale".to_string();
+    }
+    let i = i - 1;
+    if i < TOTAL_FLUIDS {
+        return format!("fluid[self][{}]", FLUIDS[i]);
+    }
+    let i = i - TOTAL_FLUIDS;
+    if i < 6 * TOTAL_FLUIDS {
+        return format!("fluid[{}][{}]", i / TOTAL_FLUIDS, FLUIDS[i % TOTAL_FLUIDS]);
+    }
+    let i = i - 6 * TOTAL_FLUIDS;
+    if i < 6 {
+        return format!("neighbor[{}]", i);
+    }
+    format!("memory[{}]", i - 6)
+}
+
+/// The name of output `i` in the order `Cell::decide` reads them.
+pub fn output_name(i: usize) -> String {
+    if i < 6 * TOTAL_FLUIDS {
+        return format!("coef[{}][{}]", i / TOTAL_FLUIDS, FLUIDS[i % TOTAL_FLUIDS]);
+    }
+    let i = i - 6 * TOTAL_FLUIDS;
+    let named = [
+        ("move_attempt", 1),
+        ("move_dir", 6),
+        ("mate_attempt", 1),
+        ("mate_dir", 6),
+        ("spawn_dir", 6),
+        ("turn_dir", 6),
+        ("explode_attempt", 1),
+        ("suicide_attempt", 1),
+        ("mate_consent", 6),
+        ("bond", 6),
+        ("next_memory", TOTAL_MEMORY),
+    ];
+    let mut start = 0;
+    for &(name, len) in &named {
+        if i < start + len {
+            return if len == 1 {
+                name.to_string()
+            } else {
+                format!("{}[{}]", name, i - start)
+            };
+        }
+        start += len;
+    }
+    format!("output[{}]", i)
+}
+
+struct Listing<'a> {
+    program: &'a Program,
+    /// How many times each instruction is used by the instructions that contribute to an output.
+    uses: Vec<usize>,
+}
+
+impl<'a> Listing<'a> {
+    fn new(brain: &'a Brain) -> Listing<'a> {
+        let program = brain.program();
+        let len = program.program.len();
+        let mut uses = vec![0; len];
+        let mut seen = vec![false; len];
+        let mut stack: Vec<usize> = (len - program.outputs..len).collect();
+        while let Some(i) = stack.pop() {
+            if seen[i] {
+                continue;
+            }
+            seen[i] = true;
+            for address in program.program[i].operands() {
+                if let Some(j) = program.instruction(skip_nops(program, address)) {
+                    uses[j] += 1;
+                    stack.push(j);
+                }
+            }
+        }
+        Listing {
+            program: program,
+            uses: uses,
+        }
+    }
+
+    fn named(&self, i: usize) -> bool {
+        self.uses[i] > 1
+    }
+
+    /// Writes the expression at `address`. Named instructions are referred to by name unless it is
+    /// the one at `address`, and instructions that only pass on their first operand are left out.
+    fn expression(&self, address: usize) -> String {
+        // Builds the tree without recursing, since chains of instructions can be as long as the
+        // program.
+        enum Step {
+            Visit(usize, bool),
+            Build(usize),
+        }
+        let mut stack = vec![Step::Visit(address, true)];
+        // The expressions of the operands visited so far.
+        let mut built: Vec<String> = Vec::new();
+        while let Some(step) = stack.pop() {
+            match step {
+                Step::Visit(address, expand) => {
+                    let i = match self.program.instruction(address) {
+                        Some(i) => i,
+                        None => {
+                            built.push(input_name(address));
+                            continue;
+                        }
+                    };
+                    let op = &self.program.program[i];
+                    if let Ins::_NOP = op.instruction {
+                        stack.push(Step::Visit(op.first, false));
+                    } else if self.named(i) && !expand {
+                        built.push(format!("t{}", i));
+                    } else {
+                        stack.push(Step::Build(i));
+                        if !op.instruction.unary() {
+                            stack.push(Step::Visit(op.second, false));
+                        }
+                        stack.push(Step::Visit(op.first, false));
+                    }
+                }
+                Step::Build(i) => {
+                    let op = &self.program.program[i];
+                    let b = if op.instruction.unary() {
+                        String::new()
+                    } else {
+                        built.pop().unwrap()
+                    };
+                    let a = built.pop().unwrap();
+                    built.push(format(op.instruction, a, b));
+                }
+            }
+        }
+        built.pop().unwrap()
+    }
+}
+
+/// Writes an instruction applied to the expressions of its operands. `b` is ignored by
+/// instructions that only take one.
+fn format(instruction: Ins, a: String, b: String) -> String {
+    match instruction {
+        Ins::_NOP => a,
+        Ins::_ADD => format!("({} + {})", a, b),
+        Ins::_SUB => format!("({} - {})", a, b),
+        Ins::_MUL => format!("({} * {})", a, b),
+        Ins::_DIV => format!("({} / {})", a, b),
+        Ins::_MOD => format!("({} % {})", a, b),
+        Ins::_GRT => format!("({} > {})", a, b),
+        Ins::_LES => format!("({} < {})", a, b),
+        Ins::_AND => format!("({} and {})", a, b),
+        Ins::_OR => format!("({} or {})", a, b),
+        Ins::_POW => format!("pow({}, {})", a, b),
+        Ins::_EXP => format!("exp({})", a),
+        Ins::_LOG => format!("log({}, {})", a, b),
+        Ins::_LN => format!("ln({})", a),
+        Ins::_SIN => format!("sin({})", a),
+        Ins::_SQT => format!("sqrt({})", a),
+        Ins::_ABS => format!("abs({})", a),
+        Ins::_MIN => format!("min({}, {})", a, b),
+        Ins::_MAX => format!("max({}, {})", a, b),
+        Ins::_TANH => format!("tanh({})", a),
+        Ins::_COS => format!("cos({})", a),
+        Ins::_IF => format!("if({}, {})", a, b),
+        Ins::_NEG => format!("(-{})", a),
+        Ins::_PDIV => format!("pdiv({}, {})", a, b),
+        Ins::_PLOG => format!("plog({}, {})", a, b),
+    }
+}
+
+/// Follows `address` through any instructions that only pass their first operand on.
+fn skip_nops(program: &Program, mut address: usize) -> usize {
+    while let Some(i) = program.instruction(address) {
+        match program.program[i].instruction {
+            Ins::_NOP => address = program.program[i].first,
+            _ => break,
+        }
+    }
+    address
+}
+
+/// Writes every output of the brain as an expression tree.
+pub fn disassemble<W: Write>(brain: &Brain, w: &mut W) -> io::Result<()> {
+    let listing = Listing::new(brain);
+    let program = listing.program;
+    let len = program.program.len();
+    let internal = len - program.outputs;
+    let used = listing.uses[..internal].iter().filter(|&&u| u > 0).count();
+    writeln!(
+        w,
+        "# {} of {} internal instructions contribute to the outputs",
+        used,
+        internal
+    )?;
+    for i in (0..internal).filter(|&i| listing.named(i)) {
+        writeln!(
+            w,
+            "t{} = {}",
+            i,
+            listing.expression(i + program.inputs)
+        )?;
+    }
+    for (n, i) in (internal..len).enumerate() {
+        writeln!(
+            w,
+            "{} = {}",
+            output_name(n),
+            listing.expression(i + program.inputs)
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cell::brain::{Op, CONST_INPUTS, TOTAL_INPUTS, TOTAL_OUTPUTS};
+
+    fn op(instruction: Ins, first: usize, second: usize) -> Op {
+        Op {
+            instruction: instruction,
+            first: first,
+            second: second,
+        }
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(input_name(0), "0");
+        assert_eq!(input_name(CONST_INPUTS), "inhale");
+        assert_eq!(input_name(CONST_INPUTS + 1), "fluid[self][food]");
+        assert_eq!(input_name(CONST_INPUTS + 1 + TOTAL_FLUIDS + 1), "fluid[0][a]");
+        assert_eq!(input_name(TOTAL_INPUTS - TOTAL_MEMORY - 1), "neighbor[5]");
+        assert_eq!(input_name(TOTAL_INPUTS - 1), "memory[3]");
+        assert_eq!(output_name(0), "coef[0][food]");
+        assert_eq!(output_name(6 * TOTAL_FLUIDS), "move_attempt");
+        assert_eq!(output_name(6 * TOTAL_FLUIDS + 1), "move_dir[0]");
+        assert_eq!(output_name(TOTAL_OUTPUTS - 1), "next_memory[3]");
+    }
+
+    #[test]
+    fn listing() {
+        let inhale = CONST_INPUTS;
+        let one = 3;
+        // Instruction `i` is at address `TOTAL_INPUTS + i`.
+        let at = |i| TOTAL_INPUTS + i;
+        let program = Program {
+            program: vec![
+                op(Ins::_ADD, inhale, one),
+                op(Ins::_NOP, at(0), 0),
+                op(Ins::_MUL, at(1), at(1)),
+                op(Ins::_SIN, inhale, 0),
+                op(Ins::_SUB, at(2), at(0)),
+                op(Ins::_MUL, at(2), at(2)),
+            ],
+            mutate_lambda: 1,
+            crossover_points: 1,
+            inputs: TOTAL_INPUTS,
+            outputs: 2,
+        };
+        let brain = Brain::from_parts(program, [0.0; TOTAL_MEMORY]);
+        let mut out = Vec::new();
+        disassemble(&brain, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "# 2 of 4 internal instructions contribute to the outputs\n\
+             t0 = (inhale + 1)\n\
+             t2 = (t0 * t0)\n\
+             coef[0][food] = (t2 - t0)\n\
+             coef[0][a] = (t2 * t2)\n"
+        );
+    }
+
+    #[test]
+    fn long_chains() {
+        let len = 100000;
+        // Every instruction adds one to the one before it.
+        let program = Program {
+            program: (0..len)
+                .map(|i| {
+                    if i == 0 {
+                        op(Ins::_NEG, CONST_INPUTS, 0)
+                    } else {
+                        op(Ins::_ADD, TOTAL_INPUTS + i - 1, 3)
+                    }
+                })
+                .collect(),
+            mutate_lambda: 1,
+            crossover_points: 1,
+            inputs: TOTAL_INPUTS,
+            outputs: 1,
+        };
+        let brain = Brain::from_parts(program, [0.0; TOTAL_MEMORY]);
+        let mut out = Vec::new();
+        disassemble(&brain, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let expected = format!(
+            "coef[0][food] = {}(-inhale){}\n",
+            "(".repeat(len - 1),
+            " + 1)".repeat(len - 1)
+        );
+        assert!(out.ends_with(&expected));
+    }
+}
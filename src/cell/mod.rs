@@ -1,51 +1,13 @@
-mod brain;
+pub mod brain;
+pub mod disasm;
 
 use rand::{Isaac64Rng, Rng};
-use itertools::Itertools;
-use fluid::{NORMAL_DIFFUSION, RELATIVE_CELL_DIFFUSION, TOTAL_FLUIDS};
-
-use std::cmp::Ordering::*;
+use fluid::{FluidParams, TOTAL_FLUIDS};
+use hex::DIRECTIONS;
+pub use hex::Direction;
 
 const INITIAL_INHALE: usize = 2000;
 
-enum_from_primitive! {
-    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
-    pub enum Direction {
-        UpRight,
-        UpLeft,
-        Left,
-        DownLeft,
-        DownRight,
-        Right,
-    }
-}
-
-impl Direction {
-    pub fn delta(&self, even_y: bool) -> (isize, isize) {
-        use self::Direction::*;
-        match *self {
-            UpRight => if even_y { (1, -1) } else { (0, -1) },
-            UpLeft => if even_y { (0, -1) } else { (-1, -1) },
-            Left => (-1, 0),
-            DownLeft => if even_y { (0, 1) } else { (-1, 1) },
-            DownRight => if even_y { (1, 1) } else { (0, 1) },
-            Right => (1, 0),
-        }
-    }
-
-    pub fn flip(&self) -> Direction {
-        use self::Direction::*;
-        match *self {
-            UpRight => DownLeft,
-            UpLeft => DownRight,
-            Left => Right,
-            DownLeft => UpRight,
-            DownRight => UpLeft,
-            Right => Left,
-        }
-    }
-}
-
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Choice {
     // If the mate and spawn direction are the same, cause a divide.
@@ -60,6 +22,48 @@ pub enum Choice {
 pub struct Decision {
     pub choice: Choice,
     pub coefficients: [[f64; TOTAL_FLUIDS]; 6],
+    /// Whether the cell would mate with the neighbor in each direction, in the order of
+    /// `DIRECTIONS`.
+    pub consent: [bool; 6],
+    /// Whether the cell wants to be bonded to the neighbor in each direction, in the order of
+    /// `DIRECTIONS`.
+    pub bonds: [bool; 6],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Parents {
+    /// Spawned at random, or from before cells were tracked.
+    None,
+    Divided(u64),
+    /// The cell that spawned the child comes first.
+    Mated(u64, u64),
+}
+
+/// Where a cell came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lineage {
+    /// Unique among every cell a grid has ever had.
+    pub id: u64,
+    pub parents: Parents,
+    /// The cycle the cell was born in.
+    pub birth: u64,
+}
+
+impl Lineage {
+    fn parents(&self) -> [Option<u64>; 2] {
+        match self.parents {
+            Parents::None => [None, None],
+            Parents::Divided(parent) => [Some(parent), None],
+            Parents::Mated(a, b) => [Some(a), Some(b)],
+        }
+    }
+
+    /// Whether one of the cells is a parent of the other or they have a parent in common.
+    pub fn kin(&self, other: &Lineage) -> bool {
+        let (mine, theirs) = (self.parents(), other.parents());
+        mine.contains(&Some(other.id)) || theirs.contains(&Some(self.id)) ||
+            mine.iter().any(|p| p.is_some() && theirs.contains(p))
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -68,47 +72,95 @@ pub struct Cell {
     pub suicide: bool,
     pub brain: brain::Brain,
     turn: usize,
+    pub lineage: Lineage,
+    /// Whether the cell is bonded to the neighbor in each direction, in the order of `DIRECTIONS`.
+    /// The neighbor is bonded back.
+    pub bonds: [bool; 6],
 }
 
 impl Cell {
-    pub fn new(rng: &mut Isaac64Rng) -> Self {
+    pub fn new(
+        id: u64,
+        birth: u64,
+        instructions: &brain::InstructionSet,
+        rng: &mut Isaac64Rng,
+    ) -> Self {
+        Cell {
+            inhale: INITIAL_INHALE,
+            suicide: false,
+            brain: brain::Brain::new(instructions, rng),
+            turn: rng.gen_range(0, 6),
+            lineage: Lineage {
+                id: id,
+                parents: Parents::None,
+                birth: birth,
+            },
+            bonds: [false; 6],
+        }
+    }
+
+    /// A new cell with the given brain.
+    pub fn with_brain(brain: brain::Brain, id: u64, birth: u64, rng: &mut Isaac64Rng) -> Self {
         Cell {
             inhale: INITIAL_INHALE,
             suicide: false,
-            brain: brain::Brain::new(rng),
+            brain: brain,
             turn: rng.gen_range(0, 6),
+            lineage: Lineage {
+                id: id,
+                parents: Parents::None,
+                birth: birth,
+            },
+            bonds: [false; 6],
         }
     }
+
+    /// Rebuilds a cell from previously saved state.
+    pub fn from_parts(
+        inhale: usize,
+        suicide: bool,
+        brain: brain::Brain,
+        turn: usize,
+        lineage: Lineage,
+        bonds: [bool; 6],
+    ) -> Self {
+        Cell {
+            inhale: inhale,
+            suicide: suicide,
+            brain: brain,
+            turn: turn,
+            lineage: lineage,
+            bonds: bonds,
+        }
+    }
+
     pub fn color(&self) -> [f32; 4] {
         [1.0, 1.0, 1.0, 1.0]
     }
 
+    /// The direction with the largest choice, or the way the cell is facing if every choice is NaN.
     fn choose_direction(&self, choices: [f64; 6]) -> Direction {
-        choices[..]
-            .iter()
-            .cloned()
-            .zip(
-                [
-                    Direction::UpRight,
-                    Direction::UpLeft,
-                    Direction::Left,
-                    Direction::DownLeft,
-                    Direction::DownRight,
-                    Direction::Right,
-                ].iter()
-                    .cycle()
-                    .skip(self.turn)
-                    .take(6),
-            )
-            .max_by(|n0, n1| if n0.0 < n1.0 { Less } else { Greater })
-            .unwrap()
-            .1
-            .clone()
+        DIRECTIONS[(argmax(&choices).unwrap_or(0) + self.turn) % 6]
     }
 
-    pub fn decide(&mut self, fluids: [&[f64; TOTAL_FLUIDS]; 7], cells: &[bool; 6]) -> Decision {
-        use mli::Stateless;
-        use std::f64::{MAX, MIN};
+    /// Runs the brain and turns its outputs into a decision.
+    ///
+    /// Brains can output anything, including NaN and infinity, so every output has a policy:
+    ///
+    /// - A coefficient that isn't a normal number leaves the diffusion as if no cell were there.
+    /// - Move, mate, spawn and turn directions go to the largest output. NaN never wins, the
+    ///   first of equal outputs does, and the cell keeps facing the same way if all are NaN.
+    /// - Of the move, mate, explode and suicide attempts the largest positive one is chosen. NaN
+    ///   is never chosen.
+    /// - A cell consents to mate with a neighbor when its consent output for that direction is
+    ///   positive, so NaN never consents. Bond outputs work the same way.
+    /// - Memory that isn't finite is stored as 0.
+    pub fn decide(
+        &mut self,
+        fluids: [&[f64; TOTAL_FLUIDS]; 7],
+        cells: &[bool; 6],
+        params: &FluidParams,
+    ) -> Decision {
         let nc = |n: bool| if n { 1.0 } else { 0.0 };
         let inputs = [
             0.0,
@@ -118,8 +170,8 @@ impl Cell {
             -1.0,
             2.0,
             -2.0,
-            MAX,
-            MIN,
+            f64::MAX,
+            f64::MIN,
             self.inhale as f64,
             fluids[0][0],
             fluids[0][1],
@@ -189,7 +241,9 @@ impl Cell {
             self.brain.memory[3],
         ];
 
-        let mut compute = self.brain.mep.process(&inputs[..]);
+        let mut outputs = [0.0; brain::TOTAL_OUTPUTS];
+        self.brain.process(&inputs[..], &mut outputs);
+        let mut compute = outputs.iter().cloned();
 
         let mut coefficients = [[0.0; TOTAL_FLUIDS]; 6];
         for da in &mut coefficients {
@@ -226,27 +280,35 @@ impl Cell {
 
         let suicide_attempt = compute.next().unwrap();
 
+        let mut consent_directions = [0f64; 6];
+        for f in &mut consent_directions {
+            *f = compute.next().unwrap();
+        }
+
+        let mut bond_directions = [0f64; 6];
+        for f in &mut bond_directions {
+            *f = compute.next().unwrap();
+        }
+
         // Handle turn immediately so they can turn to stimuli.
-        if let Some(dir) = turn_directions
-            .iter()
-            .cloned()
-            .enumerate()
-            .max_by(|n0, n1| if n0.1 < n1.1 { Less } else { Greater })
-            .map(|n| n.0)
-        {
+        if let Some(dir) = argmax(&turn_directions) {
             // Shift the turn direction by how much we have turned so it is relative.
             self.turn = (dir + self.turn) % 6;
         }
 
-        self.brain.memory.iter_mut().set_from(compute);
+        let mut consent = [false; 6];
+        let mut bonds = [false; 6];
+        for (i, (&c, &b)) in consent_directions.iter().zip(&bond_directions).enumerate() {
+            consent[(i + self.turn) % 6] = c > 0.0;
+            bonds[(i + self.turn) % 6] = b > 0.0;
+        }
+
+        for (m, v) in self.brain.memory.iter_mut().zip(compute) {
+            *m = if v.is_finite() { v } else { 0.0 };
+        }
+        let attempts = [move_attempt, mate_attempt, explode_attempt, suicide_attempt];
         Decision {
-            choice: match [move_attempt, mate_attempt, explode_attempt, suicide_attempt]
-                .iter()
-                .cloned()
-                .enumerate()
-                .filter(|p| p.1 > 0.0)
-                .max_by(|n0, n1| if n0.1 < n1.1 { Less } else { Greater })
-                .map(|n| n.0) {
+            choice: match argmax(&attempts).filter(|&i| attempts[i] > 0.0) {
                 Some(0) => Choice::Move(self.choose_direction(move_directions)),
                 Some(1) => {
                     Choice::Divide {
@@ -273,6 +335,7 @@ impl Cell {
                 _ => Choice::Nothing,
             },
             coefficients: {
+                let normal_diffusion = params.normal_diffusion();
                 let mut ncoef = [[0.0; TOTAL_FLUIDS]; 6];
                 // Handle normal fluids.
                 for (i, fa) in ncoef.iter_mut().enumerate() {
@@ -282,9 +345,10 @@ impl Cell {
                         if j < 4 {
                             let f = coefficients[i][j];
                             if f.is_normal() {
-                                NORMAL_DIFFUSION[j] * (sig(f) * RELATIVE_CELL_DIFFUSION[j] + 1.0)
+                                normal_diffusion[j] *
+                                    (sig(f) * params.relative_cell_diffusion[j] + 1.0)
                             } else {
-                                NORMAL_DIFFUSION[j]
+                                normal_diffusion[j]
                             }
                         // Handle signal fluids.
                         } else {
@@ -299,26 +363,55 @@ impl Cell {
                 }
                 ncoef
             },
+            consent: consent,
+            bonds: bonds,
         }
     }
 
-    pub fn mate(&mut self, other: &Cell, rng: &mut Isaac64Rng) -> Cell {
+    /// Produces a child with the given id, born on cycle `birth`.
+    pub fn mate(
+        &mut self,
+        other: &Cell,
+        id: u64,
+        birth: u64,
+        instructions: &brain::InstructionSet,
+        rng: &mut Isaac64Rng,
+    ) -> Cell {
         self.inhale /= 2;
         Cell {
             inhale: self.inhale,
             suicide: false,
-            brain: self.brain.mate(&other.brain, rng),
+            brain: self.brain.mate(&other.brain, instructions, rng),
             turn: self.turn,
+            lineage: Lineage {
+                id: id,
+                parents: Parents::Mated(self.lineage.id, other.lineage.id),
+                birth: birth,
+            },
+            bonds: [false; 6],
         }
     }
 
-    pub fn divide(&mut self, rng: &mut Isaac64Rng) -> Cell {
+    /// Produces a child with the given id, born on cycle `birth`.
+    pub fn divide(
+        &mut self,
+        id: u64,
+        birth: u64,
+        instructions: &brain::InstructionSet,
+        rng: &mut Isaac64Rng,
+    ) -> Cell {
         self.inhale /= 2;
         Cell {
             inhale: self.inhale,
             suicide: false,
-            brain: self.brain.divide(rng),
+            brain: self.brain.divide(instructions, rng),
             turn: self.turn,
+            lineage: Lineage {
+                id: id,
+                parents: Parents::Divided(self.lineage.id),
+                birth: birth,
+            },
+            bonds: [false; 6],
         }
     }
 }
@@ -327,3 +420,14 @@ impl Cell {
 fn sig(v: f64) -> f64 {
     2.0 / (1.0 + (-v).exp()) - 1.0
 }
+
+/// The index of the largest value, ignoring NaN. The first of equal values wins.
+fn argmax(values: &[f64]) -> Option<usize> {
+    let mut best: Option<usize> = None;
+    for (i, &v) in values.iter().enumerate() {
+        if !v.is_nan() && best.map_or(true, |b| v > values[b]) {
+            best = Some(i);
+        }
+    }
+    best
+}
@@ -0,0 +1,531 @@
+use adhesion::Adhesion;
+use cell::brain::{InstructionSet, Ins};
+use clap::ArgMatches;
+use conflict::Conflicts;
+use fluid::FluidParams;
+use grid::{Boundary, Grid, Mating};
+use recording;
+use save::compression::Compression;
+use save::snapshot::{Retention, Snapshots};
+use rand::{Isaac64Rng, SeedableRng};
+use serde_json;
+use terrain;
+use toml;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+const GRID_WIDTH: usize = 192 * 5 / 2;
+const GRID_HEIGHT: usize = 125 * 5 / 2;
+const DEFAULT_CONSUMPTION: f64 = 0.04;
+const SPAWN_DENSITY: f64 = 0.000005;
+const DEFAULT_INHALE_MINIMUM: usize = 500;
+const DEFAULT_INHALE_CAP: usize = 10000;
+const DEFAULT_MOVEMENT_COST: usize = 0;
+const DEFAULT_DIVIDE_COST: usize = 5;
+
+const DEFAULT_EXPLODE_REQUIREMENT: usize = 2100;
+const DEFAULT_EXPLODE_AMOUNT: f64 = 0.5;
+
+const DEFAULT_DEATH_RELEASE_COEFFICIENT: f64 = 0.5;
+
+const DEFAULT_SEED: [u64; 4] = [2, 5, 3, 12454];
+const DEFAULT_SNAPSHOT_DIR: &str = "snapshots";
+const LINEAGE_LOG: &str = "lineage.log";
+const DEFAULT_STATS_INTERVAL: u64 = 100;
+const DEFAULT_GENOME_COPIES: usize = 10;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Toml(toml::de::Error),
+    Json(serde_json::Error),
+    Arg(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => write!(f, "{}", e),
+            Error::Toml(ref e) => write!(f, "{}", e),
+            Error::Json(ref e) => write!(f, "{}", e),
+            Error::Arg(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+/// Everything needed to start a run. Any field missing from a config file keeps its default.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub width: usize,
+    pub height: usize,
+    pub consumption: f64,
+    /// Expected spawns per tile per cycle.
+    pub spawn_density: f64,
+    pub inhale_minimum: usize,
+    pub inhale_cap: usize,
+    pub movement_cost: usize,
+    pub divide_cost: usize,
+    pub explode_requirement: usize,
+    pub explode_amount: f64,
+    pub death_release_coefficient: f64,
+    /// What happens at the edges of a new grid.
+    pub boundary: Boundary,
+    /// How cells trying to enter the same hex are settled.
+    pub conflicts: Conflicts,
+    /// Whether a new grid returns movement and divide costs to the food where they were paid.
+    pub recycle_costs: bool,
+    /// Whether mates of cells in a new grid have to consent.
+    pub mating: Mating,
+    /// Whether cells in a new grid can bond and how bonded groups move.
+    pub adhesion: Adhesion,
+    /// PNG mask of the terrain of a new grid. Without one the grid is open everywhere.
+    pub terrain: Option<String>,
+    /// Seeds a new grid. A loaded grid continues from the seed it was saved with.
+    pub seed: Vec<u64>,
+    /// Threads to run cycles on, or `0` for one per CPU.
+    pub threads: usize,
+    /// Directory snapshots are saved to and loaded from.
+    pub snapshot_dir: String,
+    pub retention: Retention,
+    /// Compression of newly written snapshots.
+    pub compression: Compression,
+    /// Whether to log every birth and death to `lineage.log` in the snapshot directory.
+    pub lineage: bool,
+    /// File to record population statistics to, as CSV if it ends in `.csv` and JSON Lines
+    /// otherwise.
+    pub stats: Option<String>,
+    /// How many cycles apart stats are sampled.
+    pub stats_every: u64,
+    /// File to log cell events to, as JSON Lines if it ends in `.jsonl` and bincode otherwise.
+    pub events: Option<String>,
+    /// Genome files to seed a new grid with.
+    pub genomes: Vec<String>,
+    /// How many cells to place for each genome.
+    pub genome_copies: usize,
+    pub fluid: FluidParams,
+    /// Whether to report the first tile whose fluids go bad, see `grid::Checker`. On by default in
+    /// debug builds.
+    pub check_fluids: bool,
+    /// Whether to report the first cycle that changes the energy on the grid by something other
+    /// than what its ledger accounts for, see `ledger::Audit`.
+    pub check_energy: bool,
+    /// Instructions new brains are built from by name, each with the weight it is picked with.
+    pub instructions: BTreeMap<String, f64>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            width: GRID_WIDTH,
+            height: GRID_HEIGHT,
+            consumption: DEFAULT_CONSUMPTION,
+            spawn_density: SPAWN_DENSITY,
+            inhale_minimum: DEFAULT_INHALE_MINIMUM,
+            inhale_cap: DEFAULT_INHALE_CAP,
+            movement_cost: DEFAULT_MOVEMENT_COST,
+            divide_cost: DEFAULT_DIVIDE_COST,
+            explode_requirement: DEFAULT_EXPLODE_REQUIREMENT,
+            explode_amount: DEFAULT_EXPLODE_AMOUNT,
+            death_release_coefficient: DEFAULT_DEATH_RELEASE_COEFFICIENT,
+            boundary: Boundary::default(),
+            conflicts: Conflicts::default(),
+            recycle_costs: false,
+            mating: Mating::default(),
+            adhesion: Adhesion::default(),
+            terrain: None,
+            seed: DEFAULT_SEED.to_vec(),
+            threads: 0,
+            snapshot_dir: DEFAULT_SNAPSHOT_DIR.to_string(),
+            retention: Retention::default(),
+            compression: Compression::default(),
+            lineage: true,
+            stats: None,
+            stats_every: DEFAULT_STATS_INTERVAL,
+            events: None,
+            genomes: Vec::new(),
+            genome_copies: DEFAULT_GENOME_COPIES,
+            fluid: FluidParams::default(),
+            check_fluids: cfg!(debug_assertions),
+            check_energy: false,
+            instructions: InstructionSet::default()
+                .weights()
+                .iter()
+                .map(|&(ins, w)| (ins.name().to_string(), w))
+                .collect(),
+        }
+    }
+}
+
+impl Config {
+    /// Loads a config file. Files ending in `.json` are read as JSON and everything else as TOML.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, Error> {
+        let path = path.as_ref();
+        let mut contents = String::new();
+        File::open(path)?.read_to_string(&mut contents)?;
+        if path.extension().map_or(false, |e| e == "json") {
+            serde_json::from_str(&contents).map_err(Error::Json)
+        } else {
+            toml::from_str(&contents).map_err(Error::Toml)
+        }
+    }
+
+    /// Loads the file given by `--config` (if any) and then applies any parameters given on the
+    /// command line on top of it.
+    pub fn from_matches(matches: &ArgMatches) -> Result<Config, Error> {
+        let mut config = match matches.value_of("config") {
+            Some(path) => Config::load(path)?,
+            None => Config::default(),
+        };
+        arg(matches, "width", &mut config.width)?;
+        arg(matches, "height", &mut config.height)?;
+        arg(matches, "consumption", &mut config.consumption)?;
+        arg(matches, "spawn-density", &mut config.spawn_density)?;
+        arg(matches, "inhale-minimum", &mut config.inhale_minimum)?;
+        arg(matches, "inhale-cap", &mut config.inhale_cap)?;
+        arg(matches, "movement-cost", &mut config.movement_cost)?;
+        arg(matches, "divide-cost", &mut config.divide_cost)?;
+        arg(matches, "explode-requirement", &mut config.explode_requirement)?;
+        arg(matches, "explode-amount", &mut config.explode_amount)?;
+        arg(
+            matches,
+            "death-release-coefficient",
+            &mut config.death_release_coefficient,
+        )?;
+        arg(matches, "boundary", &mut config.boundary)?;
+        arg(matches, "conflicts", &mut config.conflicts.policy)?;
+        arg(matches, "conflict-penalty", &mut config.conflicts.penalty)?;
+        if let Some(path) = matches.value_of("terrain") {
+            config.terrain = Some(path.to_string());
+        }
+        arg(matches, "threads", &mut config.threads)?;
+        arg(matches, "snapshots", &mut config.snapshot_dir)?;
+        arg(matches, "keep", &mut config.retention.keep_last)?;
+        arg(matches, "keep-daily", &mut config.retention.keep_daily)?;
+        arg(matches, "compression", &mut config.compression)?;
+        if matches.is_present("no-lineage") {
+            config.lineage = false;
+        }
+        if let Some(path) = matches.value_of("stats") {
+            config.stats = Some(path.to_string());
+        }
+        arg(matches, "stats-every", &mut config.stats_every)?;
+        if let Some(path) = matches.value_of("events") {
+            config.events = Some(path.to_string());
+        }
+        if let Some(paths) = matches.values_of("genome") {
+            config.genomes = paths.map(String::from).collect();
+        }
+        arg(matches, "genome-copies", &mut config.genome_copies)?;
+        if matches.is_present("check-fluids") {
+            config.check_fluids = true;
+        }
+        if matches.is_present("check-energy") {
+            config.check_energy = true;
+        }
+        if matches.is_present("recycle-costs") {
+            config.recycle_costs = true;
+        }
+        arg(matches, "mating", &mut config.mating)?;
+        if matches.is_present("adhesion") {
+            config.adhesion.enabled = true;
+        }
+        arg(matches, "quorum", &mut config.adhesion.quorum)?;
+        if let Some(seed) = matches.value_of("seed") {
+            config.seed = seed.split(',')
+                .map(|s| s.trim().parse::<u64>())
+                .collect::<Result<_, _>>()
+                .map_err(|e| {
+                    Error::Arg(format!("invalid value \"{}\" for --seed: {}", seed, e))
+                })?;
+        }
+        if let Some(list) = matches.value_of("instructions") {
+            config.instructions = parse_instructions(list).map_err(|e| {
+                Error::Arg(format!("invalid value \"{}\" for --instructions: {}", list, e))
+            })?;
+        }
+        if config.width == 0 || config.height == 0 {
+            return Err(Error::Arg("the grid must be at least 1x1".to_string()));
+        }
+        if !(config.adhesion.quorum >= 0.0 && config.adhesion.quorum <= 1.0) {
+            return Err(Error::Arg("the adhesion quorum must be between 0 and 1".to_string()));
+        }
+        if config.stats_every == 0 {
+            return Err(Error::Arg(
+                "stats can't be sampled every 0 cycles; leave out the stats file to turn them off"
+                    .to_string(),
+            ));
+        }
+        if config.retention.keep_last == 0 {
+            return Err(Error::Arg(
+                "at least 1 snapshot must be kept or every snapshot would be pruned as soon as \
+                 it's written"
+                    .to_string(),
+            ));
+        }
+        config.instruction_set().map_err(Error::Arg)?;
+        Ok(config)
+    }
+
+    pub fn spawn_rate(&self) -> f64 {
+        self.spawn_density * self.width as f64 * self.height as f64
+    }
+
+    pub fn snapshots(&self) -> Snapshots {
+        Snapshots::new(
+            &self.snapshot_dir[..],
+            self.retention.clone(),
+            self.compression,
+        )
+    }
+
+    /// Where the lineage log is kept, whether or not it is being recorded.
+    pub fn lineage_log(&self) -> PathBuf {
+        Path::new(&self.snapshot_dir).join(LINEAGE_LOG)
+    }
+
+    pub fn recording(&self) -> recording::Options {
+        recording::Options {
+            lineage: if self.lineage {
+                Some(self.lineage_log())
+            } else {
+                None
+            },
+            stats: self.stats.as_ref().map(PathBuf::from),
+            stats_every: self.stats_every,
+            events: self.events.as_ref().map(PathBuf::from),
+            check_fluids: self.check_fluids,
+            check_energy: self.check_energy,
+        }
+    }
+
+    pub fn instruction_set(&self) -> Result<InstructionSet, String> {
+        InstructionSet::from_names(&self.instructions)
+    }
+
+    pub fn rng(&self) -> Isaac64Rng {
+        Isaac64Rng::from_seed(&self.seed)
+    }
+
+    /// Names the settings for new grids that were changed from their defaults but that `grid`
+    /// was saved with differently. A loaded grid keeps its own settings.
+    pub fn ignored_by(&self, grid: &Grid) -> Vec<&'static str> {
+        let default = Config::default();
+        let mut ignored = Vec::new();
+        if self.boundary != default.boundary && self.boundary != grid.boundary {
+            ignored.push("boundary");
+        }
+        if self.conflicts != default.conflicts && self.conflicts != grid.conflicts {
+            ignored.push("conflicts");
+        }
+        if self.recycle_costs != default.recycle_costs && self.recycle_costs != grid.recycle_costs {
+            ignored.push("recycle_costs");
+        }
+        if self.mating != default.mating && self.mating != grid.mating {
+            ignored.push("mating");
+        }
+        if self.adhesion != default.adhesion && self.adhesion != grid.adhesion {
+            ignored.push("adhesion");
+        }
+        ignored
+    }
+
+    pub fn new_grid(&self, rng: &mut Isaac64Rng) -> Result<Grid, terrain::Error> {
+        let mut grid = Grid::new(
+            self.width,
+            self.height,
+            self.consumption,
+            self.spawn_rate(),
+            self.inhale_minimum,
+            self.inhale_cap,
+            self.movement_cost,
+            self.divide_cost,
+            self.explode_requirement,
+            self.death_release_coefficient,
+            self.explode_amount,
+            self.boundary,
+            self.fluid.clone(),
+            // Checked by `from_matches`.
+            self.instruction_set().unwrap(),
+            rng,
+        );
+        grid.conflicts = self.conflicts.clone();
+        grid.recycle_costs = self.recycle_costs;
+        grid.mating = self.mating;
+        grid.adhesion = self.adhesion.clone();
+        if let Some(ref path) = self.terrain {
+            grid.set_terrain(&terrain::load_mask(path, self.width, self.height)?);
+        }
+        Ok(grid)
+    }
+}
+
+/// Parses a comma separated list of instructions, each optionally followed by `=WEIGHT`.
+fn parse_instructions(list: &str) -> Result<BTreeMap<String, f64>, String> {
+    let mut instructions = BTreeMap::new();
+    for item in list.split(',') {
+        let mut parts = item.trim().splitn(2, '=');
+        let name = parts.next().unwrap();
+        name.parse::<Ins>()?;
+        let weight = match parts.next() {
+            Some(w) => w.parse().map_err(|e| format!("invalid weight \"{}\": {}", w, e))?,
+            None => 1.0,
+        };
+        instructions.insert(name.to_string(), weight);
+    }
+    Ok(instructions)
+}
+
+fn arg<T>(matches: &ArgMatches, name: &str, target: &mut T) -> Result<(), Error>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    if let Some(v) = matches.value_of(name) {
+        *target = v.parse().map_err(|e| {
+            Error::Arg(format!("invalid value \"{}\" for --{}: {}", v, name, e))
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use conflict::Policy;
+    use std::env;
+    use std::fs;
+    use std::io::Write;
+    use std::process;
+
+    /// Writes `contents` to a temporary file with the given extension and loads it.
+    fn load(extension: &str, contents: &str) -> Result<Config, Error> {
+        let path = env::temp_dir().join(format!("evomata-config-{}.{}", process::id(), extension));
+        File::create(&path).unwrap().write_all(contents.as_bytes()).unwrap();
+        let config = Config::load(&path);
+        fs::remove_file(&path).unwrap();
+        config
+    }
+
+    fn from_args(args: &[&str]) -> Result<Config, Error> {
+        let args = ["evomata11"].iter().chain(args);
+        Config::from_matches(&::app().get_matches_from_safe(args).unwrap())
+    }
+
+    #[test]
+    fn toml_and_json() {
+        let toml = load(
+            "toml",
+            "width = 12\nboundary = \"wall\"\n\n[conflicts]\npolicy = \"auction\"\n",
+        ).unwrap();
+        let json = load(
+            "json",
+            r#"{"width": 12, "boundary": "wall", "conflicts": {"policy": "auction"}}"#,
+        ).unwrap();
+        for config in &[toml, json] {
+            assert_eq!(config.width, 12);
+            assert_eq!(config.boundary, Boundary::Wall);
+            assert_eq!(config.conflicts.policy, Policy::Auction);
+            // Everything else keeps its default.
+            assert_eq!(config.height, GRID_HEIGHT);
+            assert_eq!(config.conflicts.penalty, Conflicts::default().penalty);
+        }
+    }
+
+    #[test]
+    fn unknown_fields_are_refused() {
+        assert!(load("toml", "widht = 12\n").is_err());
+        assert!(load("toml", "[conflicts]\npenalty = 1\npolicyy = \"random\"\n").is_err());
+        assert!(load("json", r#"{"adhesion": {"quorom": 0.5}}"#).is_err());
+    }
+
+    #[test]
+    fn arguments_override_the_config_file() {
+        let path = env::temp_dir().join(format!("evomata-config-args-{}.toml", process::id()));
+        File::create(&path)
+            .unwrap()
+            .write_all(b"width = 12\nheight = 7\nboundary = \"wall\"\n")
+            .unwrap();
+        let config = from_args(&[
+            "--config",
+            path.to_str().unwrap(),
+            "--width",
+            "30",
+            "--boundary",
+            "absorbing",
+        ]);
+        fs::remove_file(&path).unwrap();
+        let config = config.unwrap();
+        assert_eq!((config.width, config.height), (30, 7));
+        assert_eq!(config.boundary, Boundary::Absorbing);
+    }
+
+    #[test]
+    fn invalid_settings_are_refused() {
+        for args in &[
+            &["--width", "0"][..],
+            &["--quorum", "1.5"],
+            &["--keep", "0"],
+            &["--stats-every", "0"],
+            &["--seed", "1,x"],
+            &["--instructions", "add,bogus"],
+            &["--instructions", "add=heavy"],
+        ] {
+            match from_args(args) {
+                Err(Error::Arg(_)) => {}
+                r => panic!("{:?} gave {:?}", args, r),
+            }
+        }
+        let config = from_args(&["--seed", "1, 2", "--stats-every", "5"]).unwrap();
+        assert_eq!(config.seed, [1, 2]);
+        assert_eq!(config.stats_every, 5);
+    }
+
+    #[test]
+    fn instruction_lists() {
+        let parsed = parse_instructions("add, mul=2.5,nop=0").unwrap();
+        let expected: BTreeMap<String, f64> =
+            [("add", 1.0), ("mul", 2.5), ("nop", 0.0)]
+                .iter()
+                .map(|&(n, w)| (n.to_string(), w))
+                .collect();
+        assert_eq!(parsed, expected);
+        assert!(parse_instructions("add,,mul").is_err());
+        assert!(parse_instructions("ADD").is_err());
+        assert!(parse_instructions("add=").is_err());
+    }
+
+    #[test]
+    fn settings_a_loaded_grid_ignores() {
+        let mut config = Config {
+            width: 4,
+            height: 3,
+            ..Config::default()
+        };
+        let mut grid = config.new_grid(&mut config.rng()).unwrap();
+        assert!(config.ignored_by(&grid).is_empty());
+        // Set to what the grid already has, or left at defaults that differ from the grid's.
+        config.boundary = Boundary::Wall;
+        grid.boundary = Boundary::Wall;
+        grid.mating = Mating::Consent;
+        assert!(config.ignored_by(&grid).is_empty());
+        config.conflicts.policy = Policy::Random;
+        config.recycle_costs = true;
+        config.adhesion.enabled = true;
+        assert_eq!(
+            config.ignored_by(&grid),
+            ["conflicts", "recycle_costs", "adhesion"]
+        );
+    }
+}
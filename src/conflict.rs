@@ -0,0 +1,132 @@
+//! Settling which cell gets a hex when several try to enter it in the same cycle.
+//!
+//! Every cell that tries to move or spawn into a hex contends for it. At most one of them wins,
+//! and every other contender loses and pays the `penalty`.
+
+use rand::Rng;
+
+use std::fmt;
+use std::str::FromStr;
+
+/// How the winner of a contested hex is picked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Policy {
+    /// Nobody wins when more than one cell tries to move in or more than one tries to spawn in.
+    /// A lone move goes ahead of a lone spawn.
+    Reject,
+    /// A contender picked at random wins.
+    Random,
+    /// The contender with the most inhale wins.
+    Inhale,
+    /// Contenders bid the inhale they have above the inhale minimum. The highest bid wins and pays
+    /// the second highest bid.
+    Auction,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy::Reject
+    }
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match *self {
+                Policy::Reject => "reject",
+                Policy::Random => "random",
+                Policy::Inhale => "inhale",
+                Policy::Auction => "auction",
+            }
+        )
+    }
+}
+
+impl FromStr for Policy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(Policy::Reject),
+            "random" => Ok(Policy::Random),
+            "inhale" => Ok(Policy::Inhale),
+            "auction" => Ok(Policy::Auction),
+            _ => Err("expected one of reject, random, inhale or auction".to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Conflicts {
+    pub policy: Policy,
+    /// Inhale taken from every contender that loses.
+    pub penalty: usize,
+}
+
+/// A cell trying to enter a hex.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Contender {
+    /// Whether it is trying to spawn a child there rather than move there.
+    pub spawn: bool,
+    pub inhale: usize,
+}
+
+/// The index of the winning contender, if any, along with the inhale it pays for winning.
+///
+/// Ties are broken at random. The random number generator is only used to break a tie or by
+/// `Policy::Random` when there is more than one contender.
+pub fn resolve<R: Rng>(
+    policy: Policy,
+    contenders: &[Contender],
+    inhale_minimum: usize,
+    rng: &mut R,
+) -> Option<(usize, usize)> {
+    if policy == Policy::Reject {
+        let only = |spawn: bool| {
+            let mut of_kind = (0..contenders.len()).filter(|&i| contenders[i].spawn == spawn);
+            match (of_kind.next(), of_kind.next()) {
+                (Some(i), None) => Some(i),
+                _ => None,
+            }
+        };
+        return only(false).or_else(|| only(true)).map(|i| (i, 0));
+    }
+    match contenders.len() {
+        0 => return None,
+        1 => return Some((0, 0)),
+        _ => {}
+    }
+    let bids: Vec<usize> = match policy {
+        Policy::Reject => unreachable!(),
+        Policy::Random => return Some((rng.gen_range(0, contenders.len()), 0)),
+        Policy::Inhale => contenders.iter().map(|c| c.inhale).collect(),
+        Policy::Auction => {
+            contenders
+                .iter()
+                .map(|c| c.inhale.saturating_sub(inhale_minimum))
+                .collect()
+        }
+    };
+    let highest = *bids.iter().max().unwrap();
+    let top: Vec<usize> = (0..bids.len()).filter(|&i| bids[i] == highest).collect();
+    let winner = if top.len() == 1 {
+        top[0]
+    } else {
+        top[rng.gen_range(0, top.len())]
+    };
+    let price = match policy {
+        Policy::Auction => {
+            (0..bids.len())
+                .filter(|&i| i != winner)
+                .map(|i| bids[i])
+                .max()
+                .unwrap()
+        }
+        _ => 0,
+    };
+    Some((winner, price))
+}
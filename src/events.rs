@@ -0,0 +1,253 @@
+//! Things that happen to individual cells, for offline analysis of their behaviour.
+//!
+//! A grid only collects events while its `events` buffer is set, and whoever set it takes them
+//! out after every cycle. A `Writer` appends them to a file as JSON Lines if its name ends in
+//! `.jsonl` and as bincode encoded `Event`s otherwise.
+
+use bincode;
+use serde_json;
+
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Why a cell died.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeathCause {
+    Suicide,
+    /// Kill fluid went above `kill_fluid_upper_threshold`.
+    KillHigh,
+    /// Kill fluid went below `kill_fluid_lower_threshold`.
+    KillLow,
+    /// Inhale dropped below `inhale_minimum`.
+    InhaleMinimum,
+    /// Ran out of inhale with no food to eat.
+    Starvation,
+    /// Moved over an absorbing edge.
+    LeftWorld,
+    /// Its tile was made impassable by new terrain.
+    Buried,
+}
+
+/// The number of `DeathCause`s.
+pub const DEATH_CAUSES: usize = 7;
+
+/// Something that happened to a cell, at `x`, `y` on the cycle `cycle`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Event {
+    Death {
+        cycle: u64,
+        id: u64,
+        x: usize,
+        y: usize,
+        cause: DeathCause,
+    },
+    /// The cell moved to the tile `to`.
+    Move {
+        cycle: u64,
+        id: u64,
+        x: usize,
+        y: usize,
+        to: (usize, usize),
+    },
+    /// The cell tried to move to the tile `to` but lost it to another of the `contenders` cells
+    /// that tried to move or spawn there.
+    MoveConflict {
+        cycle: u64,
+        id: u64,
+        x: usize,
+        y: usize,
+        to: (usize, usize),
+        contenders: usize,
+    },
+    /// The cell divided, placing its child `child` on the tile `to`.
+    Divide {
+        cycle: u64,
+        id: u64,
+        x: usize,
+        y: usize,
+        child: u64,
+        to: (usize, usize),
+    },
+    /// The cell mated with `mate`, placing their child `child` on the tile `to`.
+    Mate {
+        cycle: u64,
+        id: u64,
+        x: usize,
+        y: usize,
+        mate: u64,
+        child: u64,
+        to: (usize, usize),
+    },
+    /// The cell exploded, adding `amount` of kill fluid to its tile. The amount is negative when
+    /// the explosion lowers kill fluid.
+    Explode {
+        cycle: u64,
+        id: u64,
+        x: usize,
+        y: usize,
+        amount: f64,
+    },
+    /// The cell tried to spawn a child on the tile `to` but lost it to another of the
+    /// `contenders` cells that tried to move or spawn there.
+    SpawnConflict {
+        cycle: u64,
+        id: u64,
+        x: usize,
+        y: usize,
+        to: (usize, usize),
+        contenders: usize,
+    },
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Bincode(bincode::Error),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => write!(f, "{}", e),
+            Error::Bincode(ref e) => write!(f, "{}", e),
+            Error::Json(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<bincode::Error> for Error {
+    fn from(e: bincode::Error) -> Self {
+        Error::Bincode(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Json(e)
+    }
+}
+
+/// Appends events to a file.
+pub struct Writer {
+    file: BufWriter<File>,
+    json: bool,
+}
+
+impl Writer {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Writer, Error> {
+        let path = path.as_ref();
+        Ok(Writer {
+            file: BufWriter::new(OpenOptions::new().append(true).create(true).open(path)?),
+            json: path.extension().map_or(false, |e| e == "jsonl"),
+        })
+    }
+
+    pub fn write(&mut self, events: &[Event]) -> Result<(), Error> {
+        for event in events {
+            if self.json {
+                serde_json::to_writer(&mut self.file, event)?;
+                writeln!(self.file)?;
+            } else {
+                bincode::serialize_into(&mut self.file, event, bincode::Infinite)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::io::{BufRead, BufReader};
+    use std::process;
+
+    fn events() -> Vec<Event> {
+        vec![
+            Event::Move {
+                cycle: 4,
+                id: 0,
+                x: 0,
+                y: 0,
+                to: (1, 0),
+            },
+            Event::Divide {
+                cycle: 4,
+                id: 1,
+                x: 2,
+                y: 2,
+                child: 5,
+                to: (1, 2),
+            },
+            Event::Death {
+                cycle: 5,
+                id: 3,
+                x: 0,
+                y: 1,
+                cause: DeathCause::KillHigh,
+            },
+        ]
+    }
+
+    /// Writes the events in two batches, as a grid would over two cycles.
+    fn write(path: &Path) {
+        let events = events();
+        let mut writer = Writer::open(path).unwrap();
+        writer.write(&events[..2]).unwrap();
+        writer.write(&events[2..]).unwrap();
+        writer.flush().unwrap();
+    }
+
+    #[test]
+    fn json_lines() {
+        let path = env::temp_dir().join(format!("evomata-events-{}.jsonl", process::id()));
+        let _ = fs::remove_file(&path);
+        write(&path);
+        let lines: Vec<String> = BufReader::new(File::open(&path).unwrap())
+            .lines()
+            .map(|l| l.unwrap())
+            .collect();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            lines,
+            [
+                r#"{"move":{"cycle":4,"id":0,"x":0,"y":0,"to":[1,0]}}"#,
+                r#"{"divide":{"cycle":4,"id":1,"x":2,"y":2,"child":5,"to":[1,2]}}"#,
+                r#"{"death":{"cycle":5,"id":3,"x":0,"y":1,"cause":"kill_high"}}"#,
+            ]
+        );
+        let read: Vec<Event> = lines.iter().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(read, events());
+    }
+
+    #[test]
+    fn bincode() {
+        let path = env::temp_dir().join(format!("evomata-events-{}.events", process::id()));
+        let _ = fs::remove_file(&path);
+        write(&path);
+        let mut f = BufReader::new(File::open(&path).unwrap());
+        let mut read: Vec<Event> = Vec::new();
+        while !f.fill_buf().unwrap().is_empty() {
+            read.push(bincode::deserialize_from(&mut f, bincode::Infinite).unwrap());
+        }
+        fs::remove_file(&path).unwrap();
+        assert_eq!(read, events());
+    }
+}
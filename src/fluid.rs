@@ -1,27 +1,102 @@
-// As this goes up, so does simulation accuracy, but the reaction-diffusion stuff slows down.
-pub const ACCURACY: f64 = 10.0;
 pub const TOTAL_FLUIDS: usize = 8;
-pub const NORMAL_DIFFUSION: [f64; TOTAL_FLUIDS] = [
-    0.0004 * ACCURACY,
-    1.0,
-    0.5,
-    4.0 * ACCURACY,
-    SIGNAL_FLUID_DIFFUSION * ACCURACY,
-    SIGNAL_FLUID_DIFFUSION * ACCURACY,
-    SIGNAL_FLUID_DIFFUSION * ACCURACY,
-    SIGNAL_FLUID_DIFFUSION * ACCURACY,
+/// The name of each fluid by index.
+pub const NAMES: [&str; TOTAL_FLUIDS] = [
+    "food",
+    "a",
+    "b",
+    "kill",
+    "signal0",
+    "signal1",
+    "signal2",
+    "signal3",
 ];
-pub const RELATIVE_CELL_DIFFUSION: [f64; TOTAL_FLUIDS] = [0.5, 0.5, 0.5, 0.75, 1.0, 1.0, 1.0, 1.0];
-pub const KILL_FLUID_NORMAL: f64 = 0.05;
-pub const KILL_FLUID_DECAY: f64 = 0.15 * ACCURACY;
-pub const KILL_FLUID_UPPER_THRESHOLD: f64 = 0.052;
-pub const KILL_FLUID_LOWER_THRESHOLD: f64 = 0.048;
-pub const SIGNAL_FLUID_PRODUCTION: f64 = 0.5;
-pub const SIGNAL_FLUID_DIFFUSION: f64 = 0.06;
-pub const SIGNAL_FLUID_DECAY: f64 = 0.3 * ACCURACY;
-pub const B_FOOD_RATE: f64 = 0.0002 * ACCURACY;
+/// Whether each fluid should never be negative. Chemical B starts out as noise on both sides of 0,
+/// food is made in proportion to B and cells can put out negative signals, so only chemical A and
+/// kill fluid stay positive.
+pub const NON_NEGATIVE: [bool; TOTAL_FLUIDS] =
+    [false, true, false, true, false, false, false, false];
 
-const TIMESTEP: f64 = 0.2 / ACCURACY;
+/// Parameters of the reaction-diffusion model.
+///
+/// Rates documented as scaled are multiplied by `accuracy` before use, so changing `accuracy`
+/// changes the timestep without changing the behavior being approximated.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FluidParams {
+    /// As this goes up, so does simulation accuracy, but the reaction-diffusion stuff slows down.
+    pub accuracy: f64,
+    /// Timestep per cycle, divided by `accuracy`.
+    pub timestep: f64,
+    /// Gray-Scott feed rate of chemical A.
+    pub feed_rate: f64,
+    /// Gray-Scott kill rate of chemical B.
+    pub kill_rate: f64,
+    /// Diffusion of food, scaled.
+    pub food_diffusion: f64,
+    pub a_diffusion: f64,
+    pub b_diffusion: f64,
+    /// Diffusion of kill fluid, scaled.
+    pub kill_fluid_diffusion: f64,
+    /// Diffusion of signal fluids, scaled.
+    pub signal_fluid_diffusion: f64,
+    /// How much a cell can change the diffusion of each fluid relative to normal.
+    pub relative_cell_diffusion: [f64; TOTAL_FLUIDS],
+    pub kill_fluid_normal: f64,
+    /// Rate kill fluid returns to normal, scaled.
+    pub kill_fluid_decay: f64,
+    pub kill_fluid_upper_threshold: f64,
+    pub kill_fluid_lower_threshold: f64,
+    pub signal_fluid_production: f64,
+    /// Rate signal fluids decay, scaled.
+    pub signal_fluid_decay: f64,
+    /// Food produced per unit of chemical B, scaled.
+    pub b_food_rate: f64,
+}
+
+impl Default for FluidParams {
+    fn default() -> Self {
+        FluidParams {
+            accuracy: 10.0,
+            timestep: 0.2,
+            feed_rate: 0.029,
+            kill_rate: 0.057,
+            food_diffusion: 0.0004,
+            a_diffusion: 1.0,
+            b_diffusion: 0.5,
+            kill_fluid_diffusion: 4.0,
+            signal_fluid_diffusion: 0.06,
+            relative_cell_diffusion: [0.5, 0.5, 0.5, 0.75, 1.0, 1.0, 1.0, 1.0],
+            kill_fluid_normal: 0.05,
+            kill_fluid_decay: 0.15,
+            kill_fluid_upper_threshold: 0.052,
+            kill_fluid_lower_threshold: 0.048,
+            signal_fluid_production: 0.5,
+            signal_fluid_decay: 0.3,
+            b_food_rate: 0.0002,
+        }
+    }
+}
+
+impl FluidParams {
+    /// The diffusion coefficients of a tile when no cell is changing them.
+    pub fn normal_diffusion(&self) -> [f64; TOTAL_FLUIDS] {
+        let signal = self.signal_fluid_diffusion * self.accuracy;
+        [
+            self.food_diffusion * self.accuracy,
+            self.a_diffusion,
+            self.b_diffusion,
+            self.kill_fluid_diffusion * self.accuracy,
+            signal,
+            signal,
+            signal,
+            signal,
+        ]
+    }
+
+    pub fn timestep(&self) -> f64 {
+        self.timestep / self.accuracy
+    }
+}
 
 #[derive(Serialize, Deserialize)]
 pub enum DiffusionType {
@@ -45,39 +120,48 @@ impl Solution {
         }
     }
 
-    pub fn react_deltas(&self) -> [f64; TOTAL_FLUIDS] {
+    /// The change of each fluid due to reactions. `food_rate` multiplies `params.b_food_rate`.
+    pub fn react_deltas(&self, params: &FluidParams, food_rate: f64) -> [f64; TOTAL_FLUIDS] {
         let a = self.fluids[1];
         let b = self.fluids[2];
         let kill = self.fluids[3];
-        let f = 0.029;
-        let k = 0.057;
+        let f = params.feed_rate;
+        let k = params.kill_rate;
+        let signal_decay = params.signal_fluid_decay * params.accuracy;
         [
-            B_FOOD_RATE * b,
+            food_rate * params.b_food_rate * params.accuracy * b,
             -a * b * b + f * (1.0 - a),
             a * b * b - (k + f) * b,
-            KILL_FLUID_DECAY * (KILL_FLUID_NORMAL - kill),
-            -SIGNAL_FLUID_DECAY * self.fluids[4],
-            -SIGNAL_FLUID_DECAY * self.fluids[5],
-            -SIGNAL_FLUID_DECAY * self.fluids[6],
-            -SIGNAL_FLUID_DECAY * self.fluids[7],
+            params.kill_fluid_decay * params.accuracy * (params.kill_fluid_normal - kill),
+            -signal_decay * self.fluids[4],
+            -signal_decay * self.fluids[5],
+            -signal_decay * self.fluids[6],
+            -signal_decay * self.fluids[7],
         ]
     }
 
-    pub fn diffuse_from(&mut self, other: &Solution, dtype: DiffusionType, direction: usize) {
+    /// Adds what diffuses from this solution towards `direction` to a neighbor's `diffuse`.
+    pub fn diffuse_into(
+        &self,
+        diffuse: &mut [f64; TOTAL_FLUIDS],
+        dtype: DiffusionType,
+        direction: usize,
+        params: &FluidParams,
+    ) {
         // Handle normal fluids.
         for i in 0..4 {
-            self.diffuse[i] += other.fluids[i] * other.coefficients[direction][i] / 6.0;
+            diffuse[i] += self.fluids[i] * self.coefficients[direction][i] / 6.0;
         }
         // Handle signal fluids.
         match dtype {
             DiffusionType::DynSignals => {
                 for i in 4..TOTAL_FLUIDS {
-                    self.diffuse[i] += other.fluids[i] * other.coefficients[direction][i] / 6.0;
+                    diffuse[i] += self.fluids[i] * self.coefficients[direction][i] / 6.0;
                 }
             }
             DiffusionType::FlatSignals => {
                 for i in 4..TOTAL_FLUIDS {
-                    self.diffuse[i] += SIGNAL_FLUID_PRODUCTION * other.coefficients[direction][i] /
+                    diffuse[i] += params.signal_fluid_production * self.coefficients[direction][i] /
                         6.0;
                 }
             }
@@ -93,18 +177,20 @@ impl Solution {
         acc / 6.0
     }
 
-    pub fn end_cycle(&mut self) {
-        let reacts = self.react_deltas();
+    pub fn end_cycle(&mut self, params: &FluidParams, food_rate: f64) {
+        let reacts = self.react_deltas(params, food_rate);
+        let timestep = params.timestep();
+        let normal_diffusion = params.normal_diffusion();
         // Handle normal fluids.
         for i in 0..4 {
-            self.fluids[i] += TIMESTEP *
+            self.fluids[i] += timestep *
                 (reacts[i] + self.diffuse[i] - self.coefficient_sum(i) * self.fluids[i]);
             self.diffuse[i] = 0.0;
         }
         // Handle signal fluids.
         for i in 4..TOTAL_FLUIDS {
-            self.fluids[i] += TIMESTEP *
-                (reacts[i] + self.diffuse[i] - NORMAL_DIFFUSION[i] * self.fluids[i]);
+            self.fluids[i] += timestep *
+                (reacts[i] + self.diffuse[i] - normal_diffusion[i] * self.fluids[i]);
             self.diffuse[i] = 0.0;
         }
     }
@@ -0,0 +1,290 @@
+//! Standalone genome files for moving a brain between grids.
+//!
+//! A genome file starts with `MAGIC` and a little endian `u32` format version, followed by the
+//! bincode encoded `Genome`. Whenever the encoding of `Genome` or `Brain` changes, `VERSION` must
+//! be bumped.
+
+use bincode;
+use cell::{Cell, Lineage};
+use cell::brain::Brain;
+use grid::Grid;
+
+use std::cmp::Reverse;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+pub const MAGIC: [u8; 8] = *b"EVOGENE\x0b";
+pub const VERSION: u32 = 4;
+/// Where genomes are exported to unless told otherwise.
+pub const DEFAULT_DIR: &str = "genomes";
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Bincode(bincode::Error),
+    NotAGenome,
+    /// The file was written by a newer build.
+    Newer(u32),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => write!(f, "{}", e),
+            Error::Bincode(ref e) => write!(f, "{}", e),
+            Error::NotAGenome => write!(f, "not a genome file"),
+            Error::Newer(v) => {
+                write!(
+                    f,
+                    "genome format version {} is newer than this build supports ({})",
+                    v,
+                    VERSION
+                )
+            }
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<bincode::Error> for Error {
+    fn from(e: bincode::Error) -> Self {
+        Error::Bincode(e)
+    }
+}
+
+/// The brain of a cell along with where it came from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Genome {
+    /// The cell the brain was taken from.
+    pub lineage: Lineage,
+    /// The cycle the brain was taken on.
+    pub cycle: u64,
+    /// The cell's inhale when the brain was taken.
+    pub inhale: usize,
+    /// The brain with its memory cleared, as it would be in a newborn cell.
+    pub brain: Brain,
+}
+
+impl Genome {
+    pub fn new(cell: &Cell, cycle: u64) -> Genome {
+        let mut brain = cell.brain.clone();
+        brain.memory = Default::default();
+        Genome {
+            lineage: cell.lineage,
+            cycle: cycle,
+            inhale: cell.inhale,
+            brain: brain,
+        }
+    }
+
+    /// The name the genome is exported under.
+    pub fn file_name(&self) -> String {
+        format!("c{}.genome", self.lineage.id)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let mut f = BufWriter::new(File::create(path)?);
+        f.write_all(&MAGIC)?;
+        bincode::serialize_into(&mut f, &VERSION, bincode::Infinite)?;
+        bincode::serialize_into(&mut f, self, bincode::Infinite)?;
+        f.flush()?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Genome, Error> {
+        let mut f = BufReader::new(File::open(path)?);
+        let mut magic = [0; 8];
+        f.read_exact(&mut magic).map_err(|_| Error::NotAGenome)?;
+        if magic != MAGIC {
+            return Err(Error::NotAGenome);
+        }
+        let version: u32 = bincode::deserialize_from(&mut f, bincode::Infinite)?;
+        if version > VERSION {
+            return Err(Error::Newer(version));
+        }
+        let mut genome: Genome = bincode::deserialize_from(&mut f, bincode::Infinite)?;
+        // Brains from before version 3 had no consent outputs and those from before version 4 had
+        // no bond outputs.
+        genome.brain.upgrade();
+        Ok(genome)
+    }
+
+    /// Saves the genome into `dir` under its `file_name`, creating `dir` if needed.
+    pub fn export<P: AsRef<Path>>(&self, dir: P) -> Result<PathBuf, Error> {
+        fs::create_dir_all(dir.as_ref())?;
+        let path = dir.as_ref().join(self.file_name());
+        self.save(&path)?;
+        Ok(path)
+    }
+}
+
+/// What to rank cells by when picking the best ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rank {
+    /// Oldest first.
+    Age,
+    /// Most inhale first.
+    Inhale,
+}
+
+impl FromStr for Rank {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "age" => Ok(Rank::Age),
+            "inhale" => Ok(Rank::Inhale),
+            _ => Err("expected age or inhale".to_string()),
+        }
+    }
+}
+
+/// The genomes of the top `n` cells on the grid by `rank`, best first. Ties go to the lower id.
+pub fn top(grid: &Grid, n: usize, rank: Rank) -> Vec<Genome> {
+    let mut cells: Vec<&Cell> = grid.tiles
+        .iter()
+        .filter_map(|t| t.cell.as_deref())
+        .collect();
+    match rank {
+        Rank::Age => cells.sort_by_key(|c| (c.lineage.birth, c.lineage.id)),
+        Rank::Inhale => cells.sort_by_key(|c| (Reverse(c.inhale), c.lineage.id)),
+    }
+    cells.iter().take(n).map(|c| Genome::new(c, grid.cycle)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cell::Parents;
+    use cell::brain::{InstructionSet, BOND_OUTPUTS, TOTAL_MEMORY, TOTAL_OUTPUTS};
+    use fluid::FluidParams;
+    use grid::Boundary;
+    use rand::{Isaac64Rng, SeedableRng};
+    use std::env;
+    use std::process;
+
+    fn cell(id: u64, birth: u64, inhale: usize) -> Cell {
+        let mut rng = Isaac64Rng::from_seed(&[id]);
+        let mut cell = Cell::new(id, birth, &InstructionSet::default(), &mut rng);
+        cell.inhale = inhale;
+        cell
+    }
+
+    fn encode(brain: &Brain) -> Vec<u8> {
+        bincode::serialize(brain, bincode::Infinite).unwrap()
+    }
+
+    fn temp(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("evomata-{}-{}.genome", name, process::id()))
+    }
+
+    /// Writes `genome` with the header of `version`.
+    fn write(path: &Path, version: u32, genome: &Genome) {
+        let mut f = File::create(path).unwrap();
+        f.write_all(&MAGIC).unwrap();
+        bincode::serialize_into(&mut f, &version, bincode::Infinite).unwrap();
+        bincode::serialize_into(&mut f, genome, bincode::Infinite).unwrap();
+    }
+
+    #[test]
+    fn export_and_load() {
+        let mut c = cell(3, 10, 1234);
+        c.lineage.parents = Parents::Divided(1);
+        c.brain.memory = [1.0; TOTAL_MEMORY];
+        let genome = Genome::new(&c, 20);
+        assert_eq!(genome.brain.memory, [0.0; TOTAL_MEMORY]);
+
+        let dir = env::temp_dir().join(format!("evomata-genomes-{}", process::id()));
+        let path = genome.export(&dir).unwrap();
+        assert_eq!(path, dir.join("c3.genome"));
+        let loaded = Genome::load(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(loaded.lineage, c.lineage);
+        assert_eq!((loaded.cycle, loaded.inhale), (20, 1234));
+        assert_eq!(encode(&loaded.brain), encode(&genome.brain));
+    }
+
+    #[test]
+    fn older_brains_are_upgraded() {
+        let c = cell(4, 0, 500);
+        let mut program = c.brain.program().clone();
+        let end = program.program.len() - TOTAL_MEMORY;
+        program.program.drain(end - BOND_OUTPUTS..end);
+        program.outputs -= BOND_OUTPUTS;
+        let mut genome = Genome::new(&c, 0);
+        genome.brain = Brain::from_parts(program, [0.0; TOTAL_MEMORY]);
+
+        let path = temp("old");
+        write(&path, 3, &genome);
+        let loaded = Genome::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.brain.program().outputs, TOTAL_OUTPUTS);
+        assert_eq!(
+            loaded.brain.program().program.len(),
+            c.brain.program().program.len()
+        );
+    }
+
+    #[test]
+    fn other_files_are_refused() {
+        let genome = Genome::new(&cell(5, 0, 500), 0);
+        let path = temp("refused");
+        write(&path, VERSION + 1, &genome);
+        match Genome::load(&path) {
+            Err(Error::Newer(v)) => assert_eq!(v, VERSION + 1),
+            r => panic!("loaded a newer genome: {:?}", r),
+        }
+        for contents in &[&b"EVOGENE"[..], b"EVOMATA\x0b\x0b\x00\x00\x00"] {
+            File::create(&path).unwrap().write_all(contents).unwrap();
+            match Genome::load(&path) {
+                Err(Error::NotAGenome) => {}
+                r => panic!("loaded {:?}: {:?}", contents, r),
+            }
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn top_ranks_cells() {
+        let mut rng = Isaac64Rng::from_seed(&[2]);
+        let mut g = Grid::new(
+            3,
+            2,
+            0.04,
+            0.0,
+            500,
+            10000,
+            0,
+            5,
+            2100,
+            0.5,
+            0.5,
+            Boundary::Wall,
+            FluidParams::default(),
+            InstructionSet::default(),
+            &mut rng,
+        );
+        g.cycle = 50;
+        // Id, birth and inhale.
+        let cells = [(7, 30, 900), (2, 10, 600), (5, 10, 3000), (9, 40, 900), (1, 20, 100)];
+        for (tile, &(id, birth, inhale)) in g.tiles.iter_mut().zip(&cells) {
+            tile.cell = Some(Box::new(cell(id, birth, inhale)));
+        }
+        let ids = |genomes: Vec<Genome>| -> Vec<u64> {
+            assert!(genomes.iter().all(|g| g.cycle == 50));
+            genomes.iter().map(|g| g.lineage.id).collect()
+        };
+        assert_eq!(ids(top(&g, 3, Rank::Age)), [2, 5, 1]);
+        assert_eq!(ids(top(&g, 4, Rank::Inhale)), [5, 7, 9, 2]);
+        assert_eq!(ids(top(&g, 10, Rank::Age)), [2, 5, 1, 7, 9]);
+        assert!(top(&g, 0, Rank::Inhale).is_empty());
+    }
+}
@@ -1,11 +1,19 @@
+use adhesion::{Adhesion, Groups};
 use super::cell::*;
+use super::cell::brain::InstructionSet;
+use conflict::{self, Conflicts, Contender};
 use super::fluid::*;
+use terrain::Terrain;
+use events::{DeathCause, Event, DEATH_CAUSES};
+use ledger::{Audit, Ledger};
+use hex::{self, Direction, Offset, DIRECTIONS};
 use itertools::Itertools;
-use std::mem;
-use rand::{Isaac64Rng, Rng};
+use std::cmp;
+use std::fmt;
+use std::str::FromStr;
+use rand::{Isaac64Rng, Rng, SeedableRng};
 use noise::{Brownian2, perlin2};
-use num_cpus;
-use crossbeam;
+use rayon::prelude::*;
 
 const KILL_FLUID_COLOR_NORMAL: f64 = 0.009;
 // const SIGNAL_FLUID_SQRT_NORMAL: f64 = 5.0;
@@ -18,6 +26,38 @@ struct Mate {
     source: (usize, usize),
 }
 
+/// The cell on `source` winning the empty hex `target` for the cycle.
+#[derive(Debug, Clone)]
+struct Claim {
+    target: usize,
+    source: usize,
+    /// Inhale the cell pays for winning.
+    price: usize,
+    /// The mate of a cell spawning a child, which is `target` when it divides, or `None` if the
+    /// cell is moving.
+    spawn: Option<usize>,
+}
+
+/// The cells of a bonded group that move one hex together.
+#[derive(Debug, Clone)]
+struct GroupMove {
+    /// The tile of every cell that moves along with the tile it moves to, in order of the first.
+    cells: Vec<(usize, usize)>,
+}
+
+/// What a cell did at the end of a cycle that changed the energy on the grid.
+#[derive(Debug, Default)]
+struct Upkeep {
+    /// The id of the cell and why it died, if it did.
+    death: Option<(u64, DeathCause)>,
+    /// The inhale the cell died with.
+    died: usize,
+    /// Whether it used up a unit of inhale for lack of food.
+    burned: bool,
+    /// Whether it ate food it couldn't store.
+    wasted: bool,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct Delta {
     movement_attempts: Vec<(usize, usize)>,
@@ -30,28 +70,38 @@ pub struct Hex {
     pub cell: Option<Box<Cell>>,
     pub decision: Option<Decision>,
     delta: Delta,
+    pub terrain: Terrain,
 }
 
-struct GridCont(*mut Grid);
-
-unsafe impl Sync for GridCont {}
-unsafe impl Send for GridCont {}
-
 impl Hex {
-    pub fn color(&self) -> [f32; 4] {
+    pub fn new(solution: Solution, cell: Option<Box<Cell>>) -> Hex {
+        Hex {
+            solution: solution,
+            cell: cell,
+            decision: None,
+            delta: Delta {
+                movement_attempts: Vec::with_capacity(6),
+                mate_attempts: Vec::with_capacity(6),
+            },
+            terrain: Terrain::Open,
+        }
+    }
+
+    pub fn color(&self, params: &FluidParams) -> [f32; 4] {
         let signal = self.signal_color();
-        let primary = self.primary_color();
+        let primary = self.primary_color(params);
+        let terrain = self.terrain.color();
         [
-            signal[0] + primary[0],
-            signal[1] + primary[1],
-            signal[2] + primary[2],
+            signal[0] + primary[0] + terrain[0],
+            signal[1] + primary[1] + terrain[1],
+            signal[2] + primary[2] + terrain[2],
             1.0,
         ]
     }
 
-    pub fn primary_color(&self) -> [f32; 4] {
-        let killf = ((self.solution.fluids[3] - KILL_FLUID_NORMAL) / KILL_FLUID_COLOR_NORMAL) as
-            f32;
+    pub fn primary_color(&self, params: &FluidParams) -> [f32; 4] {
+        let killf = ((self.solution.fluids[3] - params.kill_fluid_normal) /
+                         KILL_FLUID_COLOR_NORMAL) as f32;
         [
             killf.abs(),
             (self.solution.fluids[0] / FOOD_FLUID_COLOR_NORMAL) as f32,
@@ -79,7 +129,8 @@ impl Hex {
     }
 
     pub fn pure_color(&self) -> [f32; 4] {
-        let mut ocolors = [0.0, 0.0, 0.0, 1.0];
+        let terrain = self.terrain.color();
+        let mut ocolors = [terrain[0], terrain[1], terrain[2], 1.0];
         let signal_colors = [
             [0.7, 0.0, 0.0],
             [0.0, 0.7, 0.0],
@@ -97,6 +148,191 @@ impl Hex {
     }
 }
 
+/// What lies past the edges of the grid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Boundary {
+    /// The edges wrap around to the opposite side.
+    Toroidal,
+    /// Nothing crosses the edges. Cells see the wall as occupied and bump into it.
+    Wall,
+    /// Fluids and cells that leave over an edge are lost, and cells see nothing past it.
+    Absorbing,
+    /// The edges act as mirrors. Fluids stay in like with a wall, and a cell moving into the edge
+    /// bounces back the way it came.
+    Reflecting,
+}
+
+impl Default for Boundary {
+    fn default() -> Self {
+        Boundary::Toroidal
+    }
+}
+
+impl Boundary {
+    /// The hex one step from `(x, y)` in `direction`, or `None` if that is past the edge.
+    pub fn neighbor(
+        self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        direction: Direction,
+    ) -> Option<(usize, usize)> {
+        match self {
+            Boundary::Toroidal => Some(hex::neighbor(x, y, width, height, direction)),
+            _ => {
+                Offset::new(x as isize, y as isize)
+                    .neighbor(direction)
+                    .within(width, height)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Boundary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match *self {
+                Boundary::Toroidal => "toroidal",
+                Boundary::Wall => "wall",
+                Boundary::Absorbing => "absorbing",
+                Boundary::Reflecting => "reflecting",
+            }
+        )
+    }
+}
+
+impl FromStr for Boundary {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "toroidal" => Ok(Boundary::Toroidal),
+            "wall" => Ok(Boundary::Wall),
+            "absorbing" => Ok(Boundary::Absorbing),
+            "reflecting" => Ok(Boundary::Reflecting),
+            _ => Err("expected one of toroidal, wall, absorbing or reflecting".to_string()),
+        }
+    }
+}
+
+/// Who a cell can mate with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mating {
+    /// Any neighbor, whether it wants to or not. The cell spawning the child pays for it alone.
+    Free,
+    /// Only a neighbor that consents to it in the same cycle. Both pay half the cost.
+    Consent,
+}
+
+impl Default for Mating {
+    fn default() -> Self {
+        Mating::Free
+    }
+}
+
+impl fmt::Display for Mating {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match *self {
+                Mating::Free => "free",
+                Mating::Consent => "consent",
+            }
+        )
+    }
+}
+
+impl FromStr for Mating {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "free" => Ok(Mating::Free),
+            "consent" => Ok(Mating::Consent),
+            _ => Err("expected free or consent".to_string()),
+        }
+    }
+}
+
+/// Running totals of what has happened on a grid since it was created or loaded.
+#[derive(Debug, Clone, Default)]
+pub struct Counters {
+    /// Cells spawned at random.
+    pub spawned: u64,
+    /// Cells born from a division.
+    pub divided: u64,
+    /// Cells born from mating.
+    pub mated: u64,
+    /// Cells that died, indexed by `DeathCause`.
+    pub deaths: [u64; DEATH_CAUSES],
+    /// Energy that entered and left the grid.
+    pub energy: Ledger,
+    /// Bonded groups that moved together.
+    pub group_moves: u64,
+    /// Bonds torn by cells moving away from each other.
+    pub torn: u64,
+}
+
+/// A tile whose fluid became non-finite or negative.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub cycle: u64,
+    /// The phase of the cycle that caused it.
+    pub phase: &'static str,
+    pub x: usize,
+    pub y: usize,
+    pub fluid: usize,
+    pub value: f64,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} fluid at ({}, {}) became {} in the {} phase of cycle {}",
+            NAMES[self.fluid],
+            self.x,
+            self.y,
+            self.value,
+            self.phase,
+            self.cycle
+        )
+    }
+}
+
+/// Checks that every fluid stays finite and non-negative after each phase of a cycle that changes
+/// fluids.
+///
+/// Fluids that are already negative when checking starts, like chemical B in a new grid, are only
+/// reported if they go on to become non-finite.
+#[derive(Debug, Clone, Default)]
+pub struct Checker {
+    pub enabled: bool,
+    /// The first violation found. Nothing more is checked until it is taken out.
+    pub violation: Option<Violation>,
+    /// The `faults` of every tile when last checked.
+    known: Vec<u16>,
+}
+
+/// One bit per fluid that is negative, followed by one bit per fluid that is not finite.
+fn faults(fluids: &[f64; TOTAL_FLUIDS]) -> u16 {
+    let mut faults = 0;
+    for (i, &f) in fluids.iter().enumerate() {
+        if !f.is_finite() {
+            faults |= 1 << (TOTAL_FLUIDS + i);
+        } else if f < 0.0 && NON_NEGATIVE[i] {
+            faults |= 1 << i;
+        }
+    }
+    faults
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Grid {
     pub spawning: bool,
@@ -111,7 +347,36 @@ pub struct Grid {
     pub explode_requirement: usize,
     pub death_release_coefficient: f64,
     pub explode_amount: f64,
+    pub boundary: Boundary,
+    pub fluid: FluidParams,
+    /// What new and mutated brains are built from.
+    pub instructions: InstructionSet,
+    /// How cells trying to enter the same hex are settled.
+    pub conflicts: Conflicts,
+    /// Whether inhale paid for moving, dividing and mating goes into the food on the tile it was
+    /// paid on instead of being lost.
+    pub recycle_costs: bool,
+    pub mating: Mating,
+    /// Whether cells can bond and how many it takes to move a bonded group.
+    pub adhesion: Adhesion,
+    /// The number of cycles run since the grid was created.
+    pub cycle: u64,
+    /// Seeds the random number generator used by the next cycle. Each cycle draws the seed for the
+    /// one after it, so a saved grid continues exactly as it would have without being saved.
+    pub seed: [u64; 4],
+    /// The id of the next cell to be born.
+    pub next_id: u64,
     pub tiles: Vec<Hex>,
+    #[serde(skip)]
+    pub counters: Counters,
+    /// Collects what happens to cells while it is set. Whoever sets it should take the events out
+    /// after every cycle.
+    #[serde(skip)]
+    pub events: Option<Vec<Event>>,
+    #[serde(skip)]
+    pub checker: Checker,
+    #[serde(skip)]
+    pub audit: Audit,
 }
 
 impl Grid {
@@ -127,6 +392,9 @@ impl Grid {
         explode_requirement: usize,
         death_release_coefficient: f64,
         explode_amount: f64,
+        boundary: Boundary,
+        fluid: FluidParams,
+        instructions: InstructionSet,
         rng: &mut Isaac64Rng,
     ) -> Self {
         Grid {
@@ -142,12 +410,104 @@ impl Grid {
             explode_requirement: explode_requirement,
             death_release_coefficient: death_release_coefficient,
             explode_amount: explode_amount,
-            tiles: randomizing_vec(width, height, rng),
+            boundary: boundary,
+            tiles: randomizing_vec(width, height, &fluid, rng),
+            fluid: fluid,
+            instructions: instructions,
+            conflicts: Conflicts::default(),
+            recycle_costs: false,
+            mating: Mating::default(),
+            adhesion: Adhesion::default(),
+            cycle: 0,
+            seed: rng.gen(),
+            next_id: 0,
+            counters: Counters::default(),
+            events: None,
+            checker: Checker::default(),
+            audit: Audit::default(),
+        }
+    }
+
+    pub fn randomize(&mut self) {
+        let mut rng = self.next_rng();
+        let terrain: Vec<Terrain> = self.tiles.iter().map(|t| t.terrain).collect();
+        self.tiles = randomizing_vec(self.width, self.height, &self.fluid, &mut rng);
+        self.set_terrain(&terrain);
+        // The new fluids start out like those of a new grid.
+        self.checker.known.clear();
+    }
+
+    /// Sets the terrain of every tile, given in the same order as `tiles`. Cells on tiles that
+    /// become impassable die.
+    pub fn set_terrain(&mut self, terrain: &[Terrain]) {
+        for (i, &terrain) in terrain.iter().enumerate().take(self.tiles.len()) {
+            self.tiles[i].terrain = terrain;
+            if terrain.passable() {
+                continue;
+            }
+            if let Some(c) = self.tiles[i].cell.take() {
+                self.counters.energy.died += self.consumption * c.inhale as f64;
+                self.unbond(i);
+                self.died(i, c.lineage.id, DeathCause::Buried);
+            }
+        }
+    }
+
+    /// Places up to `copies` cells with the given brain on random empty tiles, returning how many
+    /// were placed.
+    pub fn plant(&mut self, brain: &brain::Brain, copies: usize) -> usize {
+        let mut rng = self.next_rng();
+        let mut empty: Vec<usize> = (0..self.tiles.len())
+            .filter(|&i| self.tiles[i].cell.is_none() && self.tiles[i].terrain.passable())
+            .collect();
+        rng.shuffle(&mut empty);
+        empty.truncate(copies);
+        for &i in &empty {
+            let id = self.new_id();
+            let cell = Cell::with_brain(brain.clone(), id, self.cycle, &mut rng);
+            self.put(i, cell);
         }
+        empty.len()
+    }
+
+    /// Takes the generator for the current seed and advances the seed.
+    fn next_rng(&mut self) -> Isaac64Rng {
+        let mut rng = Isaac64Rng::from_seed(&self.seed);
+        self.seed = rng.gen();
+        rng
+    }
+
+    fn new_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id - 1
+    }
+
+    /// Puts a cell with no parents on tile `i`, accounting for the inhale it starts with.
+    fn put(&mut self, i: usize, cell: Cell) {
+        self.counters.energy.spawned += self.consumption * cell.inhale as f64;
+        self.tiles[i].cell = Some(Box::new(cell));
+    }
+
+    /// Accounts for the death of cell `id` on tile `i`.
+    fn died(&mut self, i: usize, id: u64, cause: DeathCause) {
+        self.counters.deaths[cause as usize] += 1;
+        let (cycle, x, y) = (self.cycle, i % self.width, i / self.width);
+        self.record(|| {
+            Event::Death {
+                cycle: cycle,
+                id: id,
+                x: x,
+                y: y,
+                cause: cause,
+            }
+        });
     }
 
-    pub fn randomize(&mut self, rng: &mut Isaac64Rng) {
-        self.tiles = randomizing_vec(self.width, self.height, rng);
+    /// Adds an event if events are being collected.
+    fn record<F: FnOnce() -> Event>(&mut self, event: F) {
+        if let Some(ref mut events) = self.events {
+            events.push(event());
+        }
     }
 
     pub fn hex(&self, x: usize, y: usize) -> &Hex {
@@ -158,468 +518,1709 @@ impl Grid {
         &mut self.tiles[x + y * self.width]
     }
 
-    fn hex_and_neighbors(&mut self, x: usize, y: usize) -> (&mut Hex, [&Hex; 6]) {
-        (
-            unsafe { mem::transmute(self.hex_mut(x, y)) },
-            if y % 2 == 0 {
-                [
-                    // UpRight
-                    self.hex(
-                        (x + self.width + 1) % self.width,
-                        (y + self.height - 1) % self.height,
-                    ),
-                    // UpLeft
-                    self.hex(x, (y + self.height - 1) % self.height),
-                    // Left
-                    self.hex((x + self.width - 1) % self.width, y),
-                    // DownLeft
-                    self.hex(x, (y + self.height + 1) % self.height),
-                    // DownRight
-                    self.hex(
-                        (x + self.width + 1) % self.width,
-                        (y + self.height + 1) % self.height,
-                    ),
-                    // Right
-                    self.hex((x + self.width + 1) % self.width, y),
-                ]
-            } else {
-                [
-                    // UpRight
-                    self.hex(x, (y + self.height - 1) % self.height),
-                    // UpLeft
-                    self.hex(
-                        (x + self.width - 1) % self.width,
-                        (y + self.height - 1) % self.height,
-                    ),
-                    // Left
-                    self.hex((x + self.width - 1) % self.width, y),
-                    // DownLeft
-                    self.hex(
-                        (x + self.width - 1) % self.width,
-                        (y + self.height + 1) % self.height,
-                    ),
-                    // DownRight
-                    self.hex(x, (y + self.height + 1) % self.height),
-                    // Right
-                    self.hex((x + self.width + 1) % self.width, y),
-                ]
-            },
-        )
+    /// Food on every tile plus the inhale of every cell, counted as the food it takes to gain it.
+    pub fn energy(&self) -> f64 {
+        let mut food = 0.0;
+        let mut inhale = 0;
+        for t in &self.tiles {
+            food += t.solution.fluids[0];
+            inhale += t.cell.as_ref().map_or(0, |c| c.inhale);
+        }
+        food + self.consumption * inhale as f64
+    }
+
+    /// The number of living cells on the grid.
+    pub fn population(&self) -> usize {
+        self.tiles.iter().filter(|t| t.cell.is_some()).count()
     }
 
-    pub fn cycle(&mut self, rng: &mut Isaac64Rng) {
+    /// The groups of cells joined by bonds.
+    pub fn groups(&self) -> Groups {
+        let (width, height, boundary) = (self.width, self.height, self.boundary);
+        let tiles = &self.tiles;
+        let bonds: Vec<Option<[Option<usize>; 6]>> = (0..tiles.len())
+            .map(|i| {
+                tiles[i].cell.as_ref().map(|_| {
+                    let n = neighbors(i, width, height, boundary);
+                    let mut bonds = [None; 6];
+                    for (d, bond) in bonds.iter_mut().enumerate() {
+                        *bond = bonded(tiles, i, d, n[d]);
+                    }
+                    bonds
+                })
+            })
+            .collect();
+        Groups::find(&bonds)
+    }
+
+    /// The tiles of every pair of cells bonded to each other, each pair once.
+    pub fn bonds(&self) -> Vec<(usize, usize)> {
+        let mut bonds = Vec::new();
+        for i in 0..self.tiles.len() {
+            for (d, &n) in neighbors(i, self.width, self.height, self.boundary).iter().enumerate() {
+                if let Some(n) = bonded(&self.tiles, i, d, n).filter(|&n| n > i) {
+                    bonds.push((i, n));
+                }
+            }
+        }
+        bonds
+    }
+
+    /// Breaks the bonds of the cell on tile `i`, or of the cell that was there, from both ends.
+    /// Returns how many there were.
+    fn unbond(&mut self, i: usize) -> u64 {
+        let mut broken = 0;
+        for (d, &n) in neighbors(i, self.width, self.height, self.boundary).iter().enumerate() {
+            if bonded(&self.tiles, i, d, n).is_some() {
+                broken += 1;
+            }
+            if let Some(n) = facing(i, d, self.width, self.height, self.boundary) {
+                if let Some(ref mut c) = self.tiles[n].cell {
+                    c.bonds[DIRECTIONS[d].flip() as usize] = false;
+                }
+            }
+        }
+        if let Some(ref mut c) = self.tiles[i].cell {
+            c.bonds = [false; 6];
+        }
+        broken
+    }
+
+    pub fn cycle(&mut self) {
+        let mut rng = self.next_rng();
+
+        if self.checker.enabled && self.checker.known.len() != self.tiles.len() {
+            self.checker.known = self.tiles
+                .par_iter()
+                .map(|hex| faults(&hex.solution.fluids))
+                .collect();
+        }
+
+        if self.audit.due() {
+            let energy = self.energy();
+            self.audit.begin(energy, &self.counters.energy);
+        }
+
         if self.spawning {
-            self.cycle_spawn(rng);
+            self.cycle_spawn(&mut rng);
         }
 
         self.cycle_cells();
 
-        self.cycle_decisions(rng);
+        self.cycle_decisions(&mut rng);
+        self.check("decisions");
 
         self.cycle_fluids();
+        self.check("fluids");
 
         self.cycle_death();
+        self.check("death");
+
+        if self.audit.due() {
+            let energy = self.energy();
+            self.audit.end(self.cycle, energy, &self.counters.energy);
+        }
+
+        self.cycle += 1;
+    }
+
+    /// Looks for a tile whose fluids broke an invariant, if the checker is enabled.
+    fn check(&mut self, phase: &'static str) {
+        if !self.checker.enabled || self.checker.violation.is_some() {
+            return;
+        }
+        let faults: Vec<u16> = self.tiles
+            .par_iter()
+            .map(|hex| faults(&hex.solution.fluids))
+            .collect();
+        let new = self.checker
+            .known
+            .iter()
+            .zip(&faults)
+            .map(|(&known, &faults)| faults & !known)
+            .enumerate()
+            .find(|&(_, new)| new != 0);
+        if let Some((i, new)) = new {
+            let fluid = ((new | new >> TOTAL_FLUIDS) & 0xff).trailing_zeros() as usize;
+            self.checker.violation = Some(Violation {
+                cycle: self.cycle,
+                phase: phase,
+                x: i % self.width,
+                y: i / self.width,
+                fluid: fluid,
+                value: self.tiles[i].solution.fluids[fluid],
+            });
+        }
+        self.checker.known = faults;
     }
 
     fn cycle_spawn(&mut self, rng: &mut Isaac64Rng) {
         if self.spawn_rate >= 1.0 {
             for _ in 0..self.spawn_rate as usize {
                 let tile = rng.gen_range(0, self.width * self.height);
-                if self.tiles[tile].cell.is_none() {
-                    self.tiles[tile].cell = Some(Box::new(Cell::new(rng)));
+                if self.tiles[tile].cell.is_none() && self.tiles[tile].terrain.passable() {
+                    let id = self.new_id();
+                    let cell = Cell::new(id, self.cycle, &self.instructions, rng);
+                    self.put(tile, cell);
+                    self.counters.spawned += 1;
                 }
             }
         } else {
             if rng.next_f64() < self.spawn_rate {
                 let tile = rng.gen_range(0, self.width * self.height);
-                if self.tiles[tile].cell.is_none() {
-                    self.tiles[tile].cell = Some(Box::new(Cell::new(rng)));
+                if self.tiles[tile].cell.is_none() && self.tiles[tile].terrain.passable() {
+                    let id = self.new_id();
+                    let cell = Cell::new(id, self.cycle, &self.instructions, rng);
+                    self.put(tile, cell);
+                    self.counters.spawned += 1;
                 }
             }
         }
     }
 
     fn cycle_cells(&mut self) {
-        let g = GridCont(self as *mut Grid);
-        let g = &g;
-        let numcpus = num_cpus::get();
-        crossbeam::scope(|scope| for i in 0..numcpus {
-            scope.spawn(move || {
-                let g: &mut Grid = unsafe { mem::transmute(g.0) };
-                for x in 0..g.width {
-                    for y in (g.height * i / numcpus)..(g.height * (i + 1) / numcpus) {
-                        let (this, neighbors) = g.hex_and_neighbors(x, y);
-                        this.decision = if let Some(ref mut this_cell) = this.cell {
-                            let neighbor_presents = [
-                                neighbors[0].cell.is_some(),
-                                neighbors[1].cell.is_some(),
-                                neighbors[2].cell.is_some(),
-                                neighbors[3].cell.is_some(),
-                                neighbors[4].cell.is_some(),
-                                neighbors[5].cell.is_some(),
-                            ];
-
-                            Some(this_cell.decide(
-                                [
-                                    &this.solution.fluids,
-                                    &neighbors[0].solution.fluids,
-                                    &neighbors[1].solution.fluids,
-                                    &neighbors[2].solution.fluids,
-                                    &neighbors[3].solution.fluids,
-                                    &neighbors[4].solution.fluids,
-                                    &neighbors[5].solution.fluids,
-                                ],
-                                &neighbor_presents,
-                            ))
-                        } else {
-                            None
-                        }
-                    }
-                }
-            });
+        let (width, height, boundary) = (self.width, self.height, self.boundary);
+        // Every cell sees its surroundings as they were before any cell made a decision.
+        let fluids: Vec<[f64; TOTAL_FLUIDS]> =
+            self.tiles.iter().map(|t| t.solution.fluids).collect();
+        // Impassable terrain looks the same as a neighboring cell.
+        let occupied: Vec<bool> = self.tiles
+            .iter()
+            .map(|t| t.cell.is_some() || !t.terrain.passable())
+            .collect();
+        let nothing = [0.0; TOTAL_FLUIDS];
+        let params = &self.fluid;
+        self.tiles.par_iter_mut().enumerate().for_each(|(i, this)| {
+            this.decision = if let Some(ref mut this_cell) = this.cell {
+                let n = neighbors(i, width, height, boundary);
+                // Past an absorbing edge there is nothing, while walls and mirrors show this hex
+                // and its cell back to it.
+                let fluid = |n: Option<usize>| match n {
+                    Some(n) => &fluids[n],
+                    None if boundary == Boundary::Absorbing => &nothing,
+                    None => &fluids[i],
+                };
+                let present = |n: Option<usize>| match n {
+                    Some(n) => occupied[n],
+                    None => boundary != Boundary::Absorbing,
+                };
+                let neighbor_presents = [
+                    present(n[0]),
+                    present(n[1]),
+                    present(n[2]),
+                    present(n[3]),
+                    present(n[4]),
+                    present(n[5]),
+                ];
+
+                Some(this_cell.decide(
+                    [
+                        &fluids[i],
+                        fluid(n[0]),
+                        fluid(n[1]),
+                        fluid(n[2]),
+                        fluid(n[3]),
+                        fluid(n[4]),
+                        fluid(n[5]),
+                    ],
+                    &neighbor_presents,
+                    params,
+                ))
+            } else {
+                None
+            }
         });
     }
 
     fn cycle_decisions(&mut self, rng: &mut Isaac64Rng) {
-        let g = GridCont(self as *mut Grid);
-        let g = &g;
+        let (width, height, boundary) = (self.width, self.height, self.boundary);
+        let mating = self.mating;
         let explode_amount = self.explode_amount;
         let explode_requirement = self.explode_requirement;
-        let numcpus = num_cpus::get();
+        let normal_diffusion = self.fluid.normal_diffusion();
+        // Taking the decisions out clears them for the next cycle and lets every hex read its
+        // neighbors' decisions while it is being modified.
+        let mut decisions: Vec<Option<Decision>> =
+            self.tiles.iter_mut().map(|t| t.decision.take()).collect();
+        // Bonded groups that move take over the choices of their cells, so they go first.
+        self.update_bonds(&decisions);
+        let moves = self.group_moves(&mut decisions, rng);
+        let terrain: Vec<Terrain> = self.tiles.iter().map(|t| t.terrain).collect();
         // Compute the deltas resulting from the decision.
-        crossbeam::scope(|scope| {
-            for i in 0..numcpus {
-                scope.spawn(move || {
-                    let g: &mut Grid = unsafe { mem::transmute(g.0) };
-                    for x in 0..g.width {
-                        for y in (g.height * i / numcpus)..(g.height * (i + 1) / numcpus) {
-                            let (width, height) = (g.width, g.height);
-                            let (this, neighbors) = g.hex_and_neighbors(x, y);
-                            // Clear the movements from the previous cycle.
-                            this.delta.movement_attempts.clear();
-                            this.delta.mate_attempts.clear();
-                            this.solution.coefficients =
-                                if let Some(ref decision) = this.decision {
-                                    decision.coefficients
-                                } else {
-                                    // Set the diffusion coefficients to the normal values.
-                                    [NORMAL_DIFFUSION; 6]
-                                };
+        self.tiles.par_iter_mut().enumerate().for_each(|(i, this)| {
+            let (x, y) = (i % width, i / width);
+            // Clear the movements from the previous cycle.
+            this.delta.movement_attempts.clear();
+            this.delta.mate_attempts.clear();
+            this.solution.coefficients = if let Some(ref decision) = decisions[i] {
+                decision.coefficients
+            } else {
+                // Set the diffusion coefficients to the normal values.
+                [normal_diffusion; 6]
+            };
+            // Terrain slows diffusion to and from its neighbors. Both sides of an edge use the same
+            // scale so whatever leaves one hex arrives in the other.
+            let here = terrain[i].diffusion();
+            for (coefficients, &n) in this.solution.coefficients.iter_mut().zip(
+                &neighbors(i, width, height, boundary),
+            )
+            {
+                let scale = n.map_or(here, |n| here.min(terrain[n].diffusion()));
+                if scale != 1.0 {
+                    for c in coefficients.iter_mut() {
+                        *c *= scale;
+                    }
+                }
+            }
 
-                            // Only add movements here if no cell is present.
-                            if this.cell.is_none() {
-                                // Add any neighbor movements to the movement_attempts vector.
-                                for (n, &facing) in neighbors.iter().zip(
-                                    &[
-                                        Direction::DownLeft,
-                                        Direction::DownRight,
-                                        Direction::Right,
-                                        Direction::UpRight,
-                                        Direction::UpLeft,
-                                        Direction::Left,
-                                    ],
-                                )
-                                {
-                                    match n.decision {
-                                        Some(Decision {
-                                                 choice: Choice::Move(direction), ..
-                                             }) => {
-                                            // It attempted to move into this hex cell.
-                                            if facing == direction {
-                                                this.delta.movement_attempts.push(in_direction(
-                                                    x,
-                                                    y,
-                                                    width,
-                                                    height,
-                                                    facing.flip(),
-                                                ));
-                                            }
-                                        }
-                                        Some(Decision {
-                                                 choice: Choice::Divide { mate, spawn }, ..
-                                             }) => {
-                                            // It attempted to spawn into this hex cell.
-                                            if facing == spawn {
-                                                let source = in_direction(
-                                                    x,
-                                                    y,
-                                                    width,
-                                                    height,
-                                                    facing.flip(),
-                                                );
-                                                this.delta.mate_attempts.push(Mate {
-                                                    mate: in_direction(
-                                                        source.0,
-                                                        source.1,
-                                                        width,
-                                                        height,
-                                                        mate,
-                                                    ),
-                                                    source: source,
-                                                });
-                                            }
-                                        }
-                                        _ => {}
-                                    }
-                                }
-                            } else {
-                                match this.decision {
-                                    Some(Decision { choice: Choice::Explode(way), .. }) => {
-                                        if let Some(ref mut c) = this.cell {
-                                            if c.inhale >= explode_requirement {
-                                                this.solution.diffuse[3] += if way {
-                                                    explode_amount
-                                                } else {
-                                                    -explode_amount
-                                                };
-                                            }
-                                        }
-                                    }
-                                    Some(Decision { choice: Choice::Suicide, .. }) => {
-                                        if let Some(ref mut c) = this.cell {
-                                            c.suicide = true;
-                                        }
-                                    }
-                                    _ => {}
+            // Only add movements here if no cell is present and cells can enter.
+            if this.cell.is_none() && this.terrain.passable() {
+                // Add any neighbor movements to the movement_attempts vector.
+                for &direction in &DIRECTIONS {
+                    let (nx, ny) = match boundary.neighbor(x, y, width, height, direction) {
+                        Some(n) => n,
+                        None => continue,
+                    };
+                    // The neighbor faces this hex in the opposite direction.
+                    let facing = direction.flip();
+                    match decisions[nx + ny * width] {
+                        Some(Decision { choice: Choice::Move(direction), .. }) => {
+                            // It attempted to move into this hex cell.
+                            if facing == direction {
+                                this.delta.movement_attempts.push((nx, ny));
+                            // It attempted to move into a mirror and bounced back into this hex.
+                            } else if boundary == Boundary::Reflecting &&
+                                       facing == direction.flip() &&
+                                       boundary.neighbor(nx, ny, width, height, direction).is_none()
+                            {
+                                this.delta.movement_attempts.push((nx, ny));
+                            }
+                        }
+                        Some(Decision { choice: Choice::Divide { mate, spawn }, .. }) => {
+                            // It attempted to spawn into this hex cell.
+                            if facing == spawn {
+                                // A mate past the edge doesn't exist, so the source stands in for
+                                // it, which never produces a child.
+                                let (mx, my) = boundary.neighbor(nx, ny, width, height, mate)
+                                    .unwrap_or((nx, ny));
+                                // The mate has to want it too, unless the cell is dividing.
+                                let consents = match decisions[mx + my * width] {
+                                    Some(ref d) => d.consent[mate.flip() as usize],
+                                    None => false,
+                                };
+                                if mating == Mating::Free || mate == spawn || consents {
+                                    this.delta.mate_attempts.push(Mate {
+                                        mate: (mx, my),
+                                        source: (nx, ny),
+                                    });
                                 }
                             }
                         }
+                        _ => {}
                     }
-                });
+                }
+            } else {
+                match decisions[i] {
+                    Some(Decision { choice: Choice::Explode(way), .. }) => {
+                        if let Some(ref mut c) = this.cell {
+                            if c.inhale >= explode_requirement {
+                                this.solution.diffuse[3] += if way {
+                                    explode_amount
+                                } else {
+                                    -explode_amount
+                                };
+                            }
+                        }
+                    }
+                    Some(Decision { choice: Choice::Suicide, .. }) => {
+                        if let Some(ref mut c) = this.cell {
+                            c.suicide = true;
+                        }
+                    }
+                    _ => {}
+                }
             }
         });
 
-        // Perform the deltas.
-        for x in 0..self.width {
-            for y in 0..self.height {
-                // Handle movement.
-                if self.hex(x, y).delta.movement_attempts.len() == 1 {
-                    let from_coord = self.hex(x, y).delta.movement_attempts[0];
-                    self.hex_mut(x, y).cell = self.hex_mut(from_coord.0, from_coord.1).cell.take();
-                    // Apply movement cost.
-                    let inhale = self.hex(x, y).cell.as_ref().unwrap().inhale;
-                    if inhale >= self.movement_cost {
-                        self.hex_mut(x, y).cell.as_mut().unwrap().inhale -= self.movement_cost;
-                    } else {
-                        self.hex_mut(x, y).cell.as_mut().unwrap().inhale = 0;
-                    }
-                // Handle mating.
-                } else if self.hex(x, y).delta.mate_attempts.len() == 1 {
-                    let mate = self.hex(x, y).delta.mate_attempts[0].clone();
-                    self.hex_mut(x, y).cell = if mate.mate == (x, y) {
-                        // Apply movement and divide cost to source.
-                        let inhale = self.hex(mate.source.0, mate.source.1)
-                            .cell
-                            .as_ref()
-                            .unwrap()
-                            .inhale;
-                        if inhale >= self.movement_cost + self.divide_cost {
-                            self.hex_mut(mate.source.0, mate.source.1)
-                                .cell
-                                .as_mut()
-                                .unwrap()
-                                .inhale -= self.movement_cost + self.divide_cost;
-                        } else {
-                            self.hex_mut(mate.source.0, mate.source.1)
-                                .cell
-                                .as_mut()
-                                .unwrap()
-                                .inhale = 0;
+        if self.events.is_some() {
+            let cycle = self.cycle;
+            for (i, decision) in decisions.iter().enumerate() {
+                if let Some(Decision { choice: Choice::Explode(way), .. }) = *decision {
+                    let id = match self.tiles[i].cell {
+                        Some(ref c) if c.inhale >= explode_requirement => c.lineage.id,
+                        _ => continue,
+                    };
+                    self.record(|| {
+                        Event::Explode {
+                            cycle: cycle,
+                            id: id,
+                            x: i % width,
+                            y: i / width,
+                            amount: if way { explode_amount } else { -explode_amount },
                         }
-                        Some(Box::new(
-                            self.hex_mut(mate.source.0, mate.source.1)
-                                .cell
-                                .as_mut()
-                                .unwrap()
-                                .divide(rng),
-                        ))
-                    } else {
-                        if self.hex(mate.mate.0, mate.mate.1).cell.is_some() {
-                            // Apply movement and divide cost to source.
-                            let inhale = self.hex(mate.source.0, mate.source.1)
-                                .cell
-                                .as_ref()
-                                .unwrap()
-                                .inhale;
-                            if inhale >= self.movement_cost + self.divide_cost {
-                                self.hex_mut(mate.source.0, mate.source.1)
-                                    .cell
-                                    .as_mut()
-                                    .unwrap()
-                                    .inhale -= self.movement_cost + self.divide_cost;
-                            } else {
-                                self.hex_mut(mate.source.0, mate.source.1)
-                                    .cell
-                                    .as_mut()
-                                    .unwrap()
-                                    .inhale = 0;
-                            }
-                            // This is safe so long as the cells arent the same.
-                            Some(Box::new(
-                                unsafe {
-                                    mem::transmute::<_, &mut Hex>(
-                                        self.hex_mut(mate.source.0, mate.source.1),
-                                    )
-                                }.cell
-                                    .as_mut()
-                                    .unwrap()
-                                    .mate(
-                                        &self.hex(mate.mate.0, mate.mate.1).cell.as_ref().unwrap(),
-                                        rng,
-                                    ),
-                            ))
-                        } else {
-                            None
+                    });
+                }
+            }
+        }
+
+        // Cells that move over an absorbing edge leave the world.
+        if boundary == Boundary::Absorbing {
+            for (i, decision) in decisions.iter().enumerate() {
+                if let Some(Decision { choice: Choice::Move(direction), .. }) = *decision {
+                    if boundary.neighbor(i % width, i / width, width, height, direction).is_none() {
+                        if let Some(c) = self.tiles[i].cell.take() {
+                            self.counters.energy.died += self.consumption * c.inhale as f64;
+                            self.unbond(i);
+                            self.died(i, c.lineage.id, DeathCause::LeftWorld);
                         }
+                    }
+                }
+            }
+        }
+
+        // Every hex settles who gets it before anyone acts, so every cell acts on the grid as it
+        // was and does at most one thing. Both draw from the rng, so they happen in order.
+        let claims = self.claim(&moves, rng);
+        self.commit(&claims, &moves, rng);
+        self.carry(&moves);
+    }
+
+    /// Forms and releases bonds between neighbors. A bond forms between two cells that both want
+    /// it and holds while either of them still does.
+    fn update_bonds(&mut self, decisions: &[Option<Decision>]) {
+        if !self.adhesion.enabled {
+            return;
+        }
+        let (width, height, boundary) = (self.width, self.height, self.boundary);
+        let bonds: Vec<[bool; 6]> = {
+            let tiles = &self.tiles;
+            (0..tiles.len())
+                .into_par_iter()
+                .map(|i| {
+                    let mut bonds = [false; 6];
+                    let wants = match decisions[i] {
+                        Some(ref decision) => decision.bonds,
+                        None => return bonds,
                     };
+                    for (d, bond) in bonds.iter_mut().enumerate() {
+                        let n = match facing(i, d, width, height, boundary) {
+                            Some(n) => n,
+                            None => continue,
+                        };
+                        let theirs = match decisions[n] {
+                            Some(ref decision) => decision.bonds[DIRECTIONS[d].flip() as usize],
+                            None => continue,
+                        };
+                        *bond = if bonded(tiles, i, d, Some(n)).is_some() {
+                            wants[d] || theirs
+                        } else {
+                            wants[d] && theirs
+                        };
+                    }
+                    bonds
+                })
+                .collect()
+        };
+        for (hex, bonds) in self.tiles.iter_mut().zip(bonds) {
+            if let Some(ref mut c) = hex.cell {
+                c.bonds = bonds;
+            }
+        }
+    }
+
+    /// Picks the bonded groups that move this cycle. A group moves when enough of its cells try
+    /// to move the same way, and all of its cells then move with it instead of doing what they
+    /// chose.
+    ///
+    /// A cell only follows its group into an empty hex that no other group is moving into or into
+    /// a hex the group is moving out of. Cells that can't follow stay behind.
+    fn group_moves(
+        &self,
+        decisions: &mut [Option<Decision>],
+        rng: &mut Isaac64Rng,
+    ) -> Vec<GroupMove> {
+        if !self.adhesion.enabled {
+            return Vec::new();
+        }
+        let (width, height, boundary) = (self.width, self.height, self.boundary);
+        let mut taken = vec![false; self.tiles.len()];
+        let mut moves = Vec::new();
+        for members in self.groups().members.iter().filter(|m| m.len() > 1) {
+            let mut votes = [0; 6];
+            for &m in members {
+                if let Some(Decision { choice: Choice::Move(direction), .. }) = decisions[m] {
+                    votes[direction as usize] += 1;
+                }
+            }
+            let d = match self.adhesion.vote(&votes, members.len(), rng) {
+                Some(d) => d,
+                None => continue,
+            };
+            for &m in members {
+                if let Some(ref mut decision) = decisions[m] {
+                    decision.choice = Choice::Nothing;
+                }
+            }
+            let targets: Vec<Option<usize>> = members
+                .iter()
+                .map(|&m| neighbors(m, width, height, boundary)[d])
+                .collect();
+            // A cell that can't follow blocks any cell trying to move into its hex.
+            let mut moving = vec![true; members.len()];
+            let mut changed = true;
+            while changed {
+                changed = false;
+                for k in 0..members.len() {
+                    let follows = moving[k] &&
+                        targets[k].map_or(false, |t| {
+                            let hex = &self.tiles[t];
+                            hex.terrain.passable() && !taken[t] &&
+                                (hex.cell.is_none() ||
+                                     members.binary_search(&t).map_or(false, |j| moving[j]))
+                        });
+                    if moving[k] && !follows {
+                        moving[k] = false;
+                        changed = true;
+                    }
                 }
+            }
+            let cells: Vec<(usize, usize)> = (0..members.len())
+                .filter(|&k| moving[k])
+                .map(|k| (members[k], targets[k].unwrap()))
+                .collect();
+            if cells.is_empty() {
+                continue;
+            }
+            for &(_, target) in &cells {
+                taken[target] = true;
+            }
+            moves.push(GroupMove { cells: cells });
+        }
+        moves
+    }
 
-                // Clear the decisions.
-                self.hex_mut(x, y).decision = None;
+    /// Moves the groups, tearing the bonds of the cells that stay behind.
+    fn carry(&mut self, moves: &[GroupMove]) {
+        let (width, height, boundary) = (self.width, self.height, self.boundary);
+        let cost = self.movement_cost;
+        let cycle = self.cycle;
+        for group in moves {
+            let moving = |i: usize| group.cells.binary_search_by_key(&i, |&(s, _)| s).is_ok();
+            for &(source, _) in &group.cells {
+                for (d, &n) in neighbors(source, width, height, boundary).iter().enumerate() {
+                    let left = match bonded(&self.tiles, source, d, n) {
+                        Some(n) if !moving(n) => n,
+                        _ => continue,
+                    };
+                    self.tiles[source].cell.as_mut().unwrap().bonds[d] = false;
+                    self.tiles[left].cell.as_mut().unwrap().bonds[DIRECTIONS[d].flip() as usize] =
+                        false;
+                    self.counters.torn += 1;
+                }
             }
+            let cells: Vec<Box<Cell>> = group
+                .cells
+                .iter()
+                .map(|&(source, _)| self.tiles[source].cell.take().unwrap())
+                .collect();
+            for (&(source, target), mut cell) in group.cells.iter().zip(cells) {
+                let paid = pay(&mut cell.inhale, cost);
+                self.counters.energy.moved += self.recycle(source, paid);
+                let id = cell.lineage.id;
+                self.record(|| {
+                    Event::Move {
+                        cycle: cycle,
+                        id: id,
+                        x: source % width,
+                        y: source / width,
+                        to: (target % width, target / width),
+                    }
+                });
+                self.tiles[target].cell = Some(cell);
+            }
+            self.counters.group_moves += 1;
         }
     }
 
-    fn cycle_fluids(&mut self) {
-        let g = GridCont(self as *mut Grid);
-        let g = &g;
-        let numcpus = num_cpus::get();
-        // Then update diffusion.
-        crossbeam::scope(|scope| for i in 0..numcpus {
-            scope.spawn(move || {
-                let g: &mut Grid = unsafe { mem::transmute(g.0) };
-                for x in 0..g.width {
-                    for y in (g.height * i / numcpus)..(g.height * (i + 1) / numcpus) {
-                        let (this, neighbors) = g.hex_and_neighbors(x, y);
-
-                        for (i, n) in neighbors.iter().enumerate() {
-                            this.solution.diffuse_from(
-                                &n.solution,
-                                match n.cell {
-                                    Some(_) => DiffusionType::FlatSignals,
-                                    None => DiffusionType::DynSignals,
-                                },
-                                (i + 3) % 6,
-                            );
-                        }
+    /// Settles which cell gets each hex that cells tried to enter, charging the losers. Nobody
+    /// gets a hex that a bonded group is moving into.
+    ///
+    /// Spawns whose mate is missing or leaving with its group can't happen, so they don't contend.
+    fn claim(&mut self, moves: &[GroupMove], rng: &mut Isaac64Rng) -> Vec<Claim> {
+        let (width, height) = (self.width, self.height);
+        let cycle = self.cycle;
+        let mut taken = vec![false; self.tiles.len()];
+        let mut leaving = vec![false; self.tiles.len()];
+        for &(source, target) in moves.iter().flat_map(|m| &m.cells) {
+            taken[target] = true;
+            leaving[source] = true;
+        }
+        let mut claims = Vec::new();
+        for (x, y) in (0..width).cartesian_product(0..height) {
+            let i = x + y * width;
+            let moves = self.tiles[i].delta.movement_attempts.clone();
+            let mates: Vec<Mate> = {
+                let tiles = &self.tiles;
+                tiles[i]
+                    .delta
+                    .mate_attempts
+                    .iter()
+                    .filter(|m| {
+                        let mate = m.mate.0 + m.mate.1 * width;
+                        let source = m.source.0 + m.source.1 * width;
+                        // Dividing needs no mate.
+                        mate == i ||
+                            (mate != source && tiles[mate].cell.is_some() && !leaving[mate])
+                    })
+                    .cloned()
+                    .collect()
+            };
+            if moves.is_empty() && mates.is_empty() {
+                continue;
+            }
+            let sources: Vec<(usize, usize)> =
+                moves.iter().cloned().chain(mates.iter().map(|m| m.source)).collect();
+            let contenders: Vec<Contender> = sources
+                .iter()
+                .enumerate()
+                .map(|(n, &(sx, sy))| {
+                    Contender {
+                        spawn: n >= moves.len(),
+                        inhale: self.hex(sx, sy).cell.as_ref().map_or(0, |c| c.inhale),
                     }
+                })
+                .collect();
+            let winner = if taken[i] {
+                None
+            } else {
+                conflict::resolve(self.conflicts.policy, &contenders, self.inhale_minimum, rng)
+            };
+            // Everyone else stays put and pays for losing.
+            for (n, &from) in sources.iter().enumerate() {
+                if winner.map_or(false, |(w, _)| w == n) {
+                    continue;
+                }
+                let penalty = self.conflicts.penalty;
+                let (id, paid) = match self.hex_mut(from.0, from.1).cell {
+                    Some(ref mut c) => (c.lineage.id, pay(&mut c.inhale, penalty)),
+                    None => continue,
+                };
+                self.counters.energy.conflicts += self.consumption * paid as f64;
+                let spawn = contenders[n].spawn;
+                self.record(|| if spawn {
+                    Event::SpawnConflict {
+                        cycle: cycle,
+                        id: id,
+                        x: from.0,
+                        y: from.1,
+                        to: (x, y),
+                        contenders: sources.len(),
+                    }
+                } else {
+                    Event::MoveConflict {
+                        cycle: cycle,
+                        id: id,
+                        x: from.0,
+                        y: from.1,
+                        to: (x, y),
+                        contenders: sources.len(),
+                    }
+                });
+            }
+            if let Some((winner, price)) = winner {
+                let (sx, sy) = sources[winner];
+                claims.push(Claim {
+                    target: i,
+                    source: sx + sy * width,
+                    price: price,
+                    spawn: if winner < moves.len() {
+                        None
+                    } else {
+                        let (mx, my) = mates[winner - moves.len()].mate;
+                        Some(mx + my * width)
+                    },
+                });
+            }
+        }
+        claims
+    }
+
+    /// Carries out the claims in order.
+    ///
+    /// A mate has to be a cell that was there at the start of the cycle and isn't moving away,
+    /// alone or with its group.
+    fn commit(&mut self, claims: &[Claim], moves: &[GroupMove], rng: &mut Isaac64Rng) {
+        let width = self.width;
+        let consumption = self.consumption;
+        let cost = self.movement_cost;
+        let spawn_cost = self.movement_cost + self.divide_cost;
+        let mating = self.mating;
+        let cycle = self.cycle;
+        let mut staying: Vec<bool> = self.tiles.iter().map(|t| t.cell.is_some()).collect();
+        for claim in claims.iter().filter(|c| c.spawn.is_none()) {
+            staying[claim.source] = false;
+        }
+        for &(source, _) in moves.iter().flat_map(|m| &m.cells) {
+            staying[source] = false;
+        }
+        for claim in claims {
+            let (i, source) = (claim.target, claim.source);
+            let (x, y) = (i % width, i / width);
+            let from = (source % width, source / width);
+            // A mate that moved away on its own leaves nothing to spawn, and nothing to pay for.
+            if let Some(partner) = claim.spawn {
+                if partner != i && !staying[partner] {
+                    continue;
+                }
+            }
+            let (id, price) = match self.tiles[source].cell {
+                Some(ref mut c) => (c.lineage.id, pay(&mut c.inhale, claim.price)),
+                None => continue,
+            };
+            self.counters.energy.conflicts += consumption * price as f64;
+            let partner = match claim.spawn {
+                // Handle movement.
+                None => {
+                    // Moving away on its own tears the cell from whatever it is bonded to.
+                    self.counters.torn += self.unbond(source);
+                    let mut cell = self.tiles[source].cell.take();
+                    // Apply movement cost.
+                    let paid = pay(&mut cell.as_mut().unwrap().inhale, cost);
+                    self.counters.energy.moved += self.recycle(source, paid);
+                    self.record(|| {
+                        Event::Move {
+                            cycle: cycle,
+                            id: id,
+                            x: from.0,
+                            y: from.1,
+                            to: (x, y),
+                        }
+                    });
+                    self.tiles[i].cell = cell;
+                    continue;
                 }
+                Some(partner) => partner,
+            };
+            // Handle mating.
+            let child = self.next_id;
+            let (born, paid, before) = if partner == i {
+                let c = self.tiles[source].cell.as_mut().unwrap();
+                // Apply movement and divide cost to source.
+                let paid = pay(&mut c.inhale, spawn_cost);
+                let before = c.inhale;
+                self.counters.divided += 1;
+                (c.divide(child, cycle, &self.instructions, rng), paid, before)
+            } else {
+                let (c, other) = cell_pair(&mut self.tiles, source, partner);
+                // Apply movement and divide cost to source, or split it with a consenting mate.
+                let share = match mating {
+                    Mating::Free => 0,
+                    Mating::Consent => spawn_cost / 2,
+                };
+                let shared = pay(&mut other.inhale, share);
+                let paid = pay(&mut c.inhale, spawn_cost - share);
+                let before = c.inhale;
+                self.counters.mated += 1;
+                let born = c.mate(other, child, cycle, &self.instructions, rng);
+                self.counters.energy.births += self.recycle(partner, shared);
+                (born, paid, before)
+            };
+            self.next_id += 1;
+            // The parent shares what it has left with the child, which may not split evenly.
+            let kept = self.tiles[source].cell.as_ref().map_or(0, |c| c.inhale);
+            let uneven = before as f64 - kept as f64 - born.inhale as f64;
+            self.counters.energy.births += self.recycle(source, paid) + consumption * uneven;
+            let parents = born.lineage.parents;
+            self.tiles[i].cell = Some(Box::new(born));
+            self.record(|| match parents {
+                Parents::Mated(_, other) => Event::Mate {
+                    cycle: cycle,
+                    id: id,
+                    x: from.0,
+                    y: from.1,
+                    mate: other,
+                    child: child,
+                    to: (x, y),
+                },
+                _ => Event::Divide {
+                    cycle: cycle,
+                    id: id,
+                    x: from.0,
+                    y: from.1,
+                    child: child,
+                    to: (x, y),
+                },
             });
-        });
+        }
+    }
 
-        // Finish the cycle.
-        crossbeam::scope(|scope| for i in 0..numcpus {
-            scope.spawn(move || {
-                let g: &mut Grid = unsafe { mem::transmute(g.0) };
-                for x in 0..g.width {
-                    for y in (g.height * i / numcpus)..(g.height * (i + 1) / numcpus) {
-                        g.hex_mut(x, y).solution.end_cycle();
+    /// Puts `paid` inhale spent on tile `i` back into its food if costs are recycled. Returns the
+    /// inhale as energy.
+    fn recycle(&mut self, i: usize, paid: usize) -> f64 {
+        let energy = self.consumption * paid as f64;
+        if self.recycle_costs {
+            self.tiles[i].solution.fluids[0] += energy;
+            self.counters.energy.recycled += energy;
+        }
+        energy
+    }
+
+    fn cycle_fluids(&mut self) {
+        let (width, height, boundary) = (self.width, self.height, self.boundary);
+        let params = &self.fluid;
+        let dtype = |hex: &Hex| match hex.cell {
+            Some(_) => DiffusionType::FlatSignals,
+            None => DiffusionType::DynSignals,
+        };
+        // Gather what diffuses into each hex from the unmodified neighbors first.
+        let diffuse: Vec<[f64; TOTAL_FLUIDS]> = {
+            let tiles = &self.tiles;
+            (0..tiles.len())
+                .into_par_iter()
+                .map(|i| {
+                    let mut diffuse = tiles[i].solution.diffuse;
+                    let adjacent = neighbors(i, width, height, boundary);
+                    for (direction, &n) in adjacent.iter().enumerate() {
+                        match n {
+                            Some(n) => {
+                                let n = &tiles[n];
+                                n.solution.diffuse_into(
+                                    &mut diffuse,
+                                    dtype(n),
+                                    (direction + 3) % 6,
+                                    params,
+                                );
+                            }
+                            // Walls and mirrors send back whatever flows into them. Whatever flows
+                            // over an absorbing edge is gone.
+                            None => {
+                                if boundary != Boundary::Absorbing {
+                                    let this = &tiles[i];
+                                    this.solution.diffuse_into(
+                                        &mut diffuse,
+                                        dtype(this),
+                                        direction,
+                                        params,
+                                    );
+                                }
+                            }
+                        }
+                    }
+                    diffuse
+                })
+                .collect()
+        };
+
+        // Account for the food made and the food that flows over the edges before it changes.
+        let timestep = params.timestep();
+        let mut produced = 0.0;
+        let mut lost = 0.0;
+        for (i, hex) in self.tiles.iter().enumerate() {
+            let solution = &hex.solution;
+            produced += timestep * solution.react_deltas(params, hex.terrain.food_rate())[0];
+            if boundary == Boundary::Absorbing {
+                for (direction, n) in neighbors(i, width, height, boundary).iter().enumerate() {
+                    if n.is_none() {
+                        let coefficient = solution.coefficients[direction][0];
+                        lost += timestep * solution.fluids[0] * coefficient / 6.0;
                     }
                 }
-            });
-        });
+            }
+        }
+        self.counters.energy.produced += produced;
+        self.counters.energy.lost += lost;
+
+        // Finish the cycle.
+        self.tiles.par_iter_mut().zip(diffuse).for_each(
+            |(hex, diffuse)| {
+                hex.solution.diffuse = diffuse;
+                hex.solution.end_cycle(params, hex.terrain.food_rate());
+            },
+        );
     }
 
     fn cycle_death(&mut self) {
-        let g = GridCont(self as *mut Grid);
-        let g = &g;
-        let numcpus = num_cpus::get();
         let consumption = self.consumption;
-        // Finish the cycle.
-        crossbeam::scope(|scope| {
-            for i in 0..numcpus {
-                scope.spawn(move || {
-                    let g: &mut Grid = unsafe { mem::transmute(g.0) };
-                    let inhale_minimum = g.inhale_minimum;
-                    let inhale_cap = g.inhale_cap;
-                    let death_release_coefficient = g.death_release_coefficient;
-                    for x in 0..g.width {
-                        for y in (g.height * i / numcpus)..(g.height * (i + 1) / numcpus) {
-                            let hex = g.hex_mut(x, y);
-                            if hex.cell.is_some() {
-                                if hex.cell.as_ref().unwrap().suicide ||
-                                    hex.solution.fluids[3] > KILL_FLUID_UPPER_THRESHOLD ||
-                                    hex.solution.fluids[3] < KILL_FLUID_LOWER_THRESHOLD ||
-                                    hex.cell.as_ref().unwrap().inhale < inhale_minimum
-                                {
-                                    hex.solution.fluids[0] += death_release_coefficient *
-                                        consumption *
-                                        hex.cell.as_ref().unwrap().inhale as f64;
-                                    hex.cell = None;
-                                } else if hex.solution.fluids[0] <= consumption {
-                                    if hex.cell.as_ref().unwrap().inhale != 0 {
-                                        hex.cell.as_mut().unwrap().inhale -= 1;
-                                    } else {
-                                        hex.cell = None;
-                                    }
-                                } else {
-                                    hex.solution.fluids[0] -= consumption;
-                                    // NOTE: This used to be survival threshold.
-                                    if hex.solution.fluids[0] < 0.0 {
-                                        if hex.cell.as_ref().unwrap().inhale != 0 {
-                                            hex.cell.as_mut().unwrap().inhale -= 1;
-                                        } else {
-                                            hex.solution.fluids[0] += death_release_coefficient *
-                                                consumption *
-                                                hex.cell.as_ref().unwrap().inhale as f64;
-                                            hex.cell = None;
-                                        }
-                                    } else {
-                                        if hex.cell.as_ref().unwrap().inhale < inhale_cap {
-                                            hex.cell.as_mut().unwrap().inhale += 1;
-                                        }
-                                    }
-                                }
-                            }
+        let inhale_minimum = self.inhale_minimum;
+        let inhale_cap = self.inhale_cap;
+        let death_release_coefficient = self.death_release_coefficient;
+        let kill_upper = self.fluid.kill_fluid_upper_threshold;
+        let kill_lower = self.fluid.kill_fluid_lower_threshold;
+        // Finish the cycle, collecting what every cell did that changed the energy on the grid.
+        let upkeep: Vec<(usize, Upkeep)> = self.tiles
+            .par_iter_mut()
+            .enumerate()
+            .filter_map(|(i, hex)| {
+                let (id, inhale, suicide) = match hex.cell {
+                    Some(ref c) => (c.lineage.id, c.inhale, c.suicide),
+                    None => return None,
+                };
+                let kill = hex.solution.fluids[3];
+                let cause = if suicide {
+                    Some(DeathCause::Suicide)
+                } else if kill > kill_upper {
+                    Some(DeathCause::KillHigh)
+                } else if kill < kill_lower {
+                    Some(DeathCause::KillLow)
+                } else if inhale < inhale_minimum {
+                    Some(DeathCause::InhaleMinimum)
+                } else {
+                    None
+                };
+                let mut upkeep = Upkeep::default();
+                if let Some(cause) = cause {
+                    hex.solution.fluids[0] += death_release_coefficient * consumption *
+                        inhale as f64;
+                    hex.cell = None;
+                    upkeep.death = Some((id, cause));
+                    upkeep.died = inhale;
+                    return Some((i, upkeep));
+                }
+                if hex.solution.fluids[0] <= consumption {
+                    if inhale != 0 {
+                        hex.cell.as_mut().unwrap().inhale -= 1;
+                        upkeep.burned = true;
+                    } else {
+                        hex.cell = None;
+                        upkeep.death = Some((id, DeathCause::Starvation));
+                    }
+                } else {
+                    hex.solution.fluids[0] -= consumption;
+                    // NOTE: This used to be survival threshold.
+                    if hex.solution.fluids[0] < 0.0 {
+                        upkeep.wasted = true;
+                        if inhale != 0 {
+                            hex.cell.as_mut().unwrap().inhale -= 1;
+                            upkeep.burned = true;
+                        } else {
+                            hex.solution.fluids[0] += death_release_coefficient * consumption *
+                                inhale as f64;
+                            hex.cell = None;
+                            upkeep.death = Some((id, DeathCause::Starvation));
                         }
+                    } else if inhale < inhale_cap {
+                        hex.cell.as_mut().unwrap().inhale += 1;
+                        return None;
+                    } else {
+                        upkeep.wasted = true;
                     }
-                });
+                }
+                Some((i, upkeep))
+            })
+            .collect();
+        for (i, upkeep) in upkeep {
+            let energy = &mut self.counters.energy;
+            energy.died += consumption * upkeep.died as f64;
+            energy.released += death_release_coefficient * consumption * upkeep.died as f64;
+            if upkeep.burned {
+                energy.burned += consumption;
             }
-        });
+            if upkeep.wasted {
+                energy.wasted += consumption;
+            }
+            if let Some((id, cause)) = upkeep.death {
+                self.unbond(i);
+                self.died(i, id, cause);
+            }
+        }
+    }
+}
+
+/// Indices of the six neighbors of tile `i`, in the order of `DIRECTIONS`, with `None` for those
+/// past the edge.
+fn neighbors(i: usize, width: usize, height: usize, boundary: Boundary) -> [Option<usize>; 6] {
+    let mut indices = [None; 6];
+    for (index, &direction) in indices.iter_mut().zip(&DIRECTIONS) {
+        *index = boundary
+            .neighbor(i % width, i / width, width, height, direction)
+            .map(|(x, y)| x + y * width);
     }
+    indices
+}
+
+/// The tile in direction `d` from tile `i`, if tile `i` is in the opposite direction from it.
+///
+/// Wrapping a grid with an odd number of rows around leaves some tiles along the seam neighbors
+/// one way only, and cells on those can't bond.
+fn facing(i: usize, d: usize, width: usize, height: usize, boundary: Boundary) -> Option<usize> {
+    let back = DIRECTIONS[d].flip() as usize;
+    neighbors(i, width, height, boundary)[d]
+        .filter(|&n| n != i && neighbors(n, width, height, boundary)[back] == Some(i))
+}
+
+/// The tile `n`, which is in direction `d` from tile `i`, if the cells on the two are bonded to
+/// each other.
+fn bonded(tiles: &[Hex], i: usize, d: usize, n: Option<usize>) -> Option<usize> {
+    let bonds = |i: usize, d: usize| tiles[i].cell.as_ref().map_or(false, |c| c.bonds[d]);
+    n.filter(|&n| n != i && bonds(i, d) && bonds(n, DIRECTIONS[d].flip() as usize))
 }
 
-fn randomizing_vec(width: usize, height: usize, rng: &mut Isaac64Rng) -> Vec<Hex> {
+/// Takes up to `cost` from `inhale`, returning how much was taken.
+fn pay(inhale: &mut usize, cost: usize) -> usize {
+    let paid = cmp::min(*inhale, cost);
+    *inhale -= paid;
+    paid
+}
+
+/// Borrows the cells on tiles `a` and `b` mutably.
+///
+/// Panics if `a` and `b` are the same tile or either has no cell.
+fn cell_pair(tiles: &mut [Hex], a: usize, b: usize) -> (&mut Cell, &mut Cell) {
+    assert_ne!(a, b);
+    let (first, second) = tiles.split_at_mut(cmp::max(a, b));
+    let (a_hex, b_hex) = if a < b {
+        (&mut first[a], &mut second[0])
+    } else {
+        (&mut second[0], &mut first[b])
+    };
+    (a_hex.cell.as_mut().unwrap(), b_hex.cell.as_mut().unwrap())
+}
+
+fn randomizing_vec(
+    width: usize,
+    height: usize,
+    params: &FluidParams,
+    rng: &mut Isaac64Rng,
+) -> Vec<Hex> {
     let seeds = [rng.gen(), rng.gen()];
     let noise = Brownian2::new(perlin2, 4).wavelength(24.0);
     (0..height)
-        .cartesian_product((0..width))
+        .cartesian_product(0..width)
         .map(|(x, y)| {
-            Hex {
-                solution: Solution::new(
+            Hex::new(
+                Solution::new(
                     [
                         0.0,
                         1.0,
                         noise.apply(&seeds[0], &[x as f64, y as f64]),
-                        KILL_FLUID_NORMAL,
+                        params.kill_fluid_normal,
                         0.0,
                         0.0,
                         0.0,
                         0.0,
                     ],
-                    [NORMAL_DIFFUSION; 6],
+                    [params.normal_diffusion(); 6],
                 ),
-                cell: None,
-                decision: None,
-                delta: Delta {
-                    movement_attempts: Vec::with_capacity(6),
-                    mate_attempts: Vec::with_capacity(6),
-                },
-            }
+                None,
+            )
         })
         .collect_vec()
 }
 
-fn in_direction(
-    x: usize,
-    y: usize,
-    width: usize,
-    height: usize,
-    direction: Direction,
-) -> (usize, usize) {
-    let diff = direction.delta(y % 2 == 0);
-    (
-        ((width + x) as isize + diff.0) as usize % width,
-        ((height + y) as isize + diff.1) as usize % height,
-    )
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use conflict::Policy;
+    use bincode;
+    use rayon;
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::Hasher;
+
+    fn grid() -> Grid {
+        let mut rng = Isaac64Rng::from_seed(&[1, 2, 3, 4]);
+        Grid::new(
+            24,
+            16,
+            0.04,
+            2.0,
+            500,
+            10000,
+            0,
+            5,
+            2100,
+            0.5,
+            0.5,
+            Boundary::Toroidal,
+            FluidParams::default(),
+            InstructionSet::default(),
+            &mut rng,
+        )
+    }
+
+    fn hash(g: &Grid) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        hasher.write(&bincode::serialize(g, bincode::Infinite).unwrap());
+        hasher.finish()
+    }
+
+    fn run(mut g: Grid, threads: usize, cycles: usize) -> Grid {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+        pool.install(|| for _ in 0..cycles {
+            g.cycle();
+        });
+        g
+    }
+
+    #[test]
+    fn thread_count_does_not_change_result() {
+        let one = run(grid(), 1, 100);
+        let eight = run(grid(), 8, 100);
+        assert!(one.population() > 0);
+        assert_eq!(hash(&one), hash(&eight));
+    }
+
+    #[test]
+    fn saved_grid_resumes_identically() {
+        let straight = run(grid(), 0, 100);
+        let halfway = run(grid(), 0, 50);
+        let bytes = bincode::serialize(&halfway, bincode::Infinite).unwrap();
+        let restored: Grid = bincode::deserialize(&bytes).unwrap();
+        let resumed = run(restored, 3, 50);
+        assert_eq!(resumed.cycle, 100);
+        assert_eq!(hash(&straight), hash(&resumed));
+    }
+
+    #[test]
+    fn checker_reports_new_faults() {
+        let mut g = run(grid(), 0, 10);
+        g.checker.enabled = true;
+        g.cycle();
+        assert_eq!(g.checker.violation, None);
+        g.tiles[30].solution.fluids[3] = -1.0;
+        g.cycle();
+        let v = g.checker.violation.take().unwrap();
+        assert_eq!((v.x, v.y, v.fluid, v.phase), (6, 1, 3, "decisions"));
+    }
+
+    /// A 3x3 walled grid with nothing on it, settling conflicts with `policy`.
+    fn small(policy: Policy, penalty: usize) -> Grid {
+        let mut rng = Isaac64Rng::from_seed(&[5, 6, 7, 8]);
+        let mut g = Grid::new(
+            3,
+            3,
+            0.04,
+            0.0,
+            500,
+            10000,
+            0,
+            5,
+            2100,
+            0.5,
+            0.5,
+            Boundary::Wall,
+            FluidParams::default(),
+            InstructionSet::default(),
+            &mut rng,
+        );
+        g.spawning = false;
+        g.conflicts = Conflicts {
+            policy: policy,
+            penalty: penalty,
+        };
+        g
+    }
+
+    /// Puts a cell with the given inhale and choice on `(x, y)` and returns its id.
+    fn place(g: &mut Grid, x: usize, y: usize, inhale: usize, choice: Choice) -> u64 {
+        let id = g.new_id();
+        let mut rng = Isaac64Rng::from_seed(&[id]);
+        let mut cell = Cell::new(id, 0, &g.instructions, &mut rng);
+        cell.inhale = inhale;
+        let hex = g.hex_mut(x, y);
+        hex.cell = Some(Box::new(cell));
+        hex.decision = Some(decision(choice));
+        id
+    }
+
+    fn decision(choice: Choice) -> Decision {
+        Decision {
+            choice: choice,
+            coefficients: [[0.0; TOTAL_FLUIDS]; 6],
+            consent: [false; 6],
+            bonds: [false; 6],
+        }
+    }
+
+    /// Makes the cell on `(x, y)` want to bond in direction `d` this cycle.
+    fn want_bond(g: &mut Grid, x: usize, y: usize, d: Direction) {
+        g.hex_mut(x, y).decision.as_mut().unwrap().bonds[d as usize] = true;
+    }
+
+    /// Places two cells on `(0, 1)` and `(1, 1)` that bond on the next settle, moving in the
+    /// given directions if any. Returns their ids.
+    fn pair(g: &mut Grid, left: Choice, right: Choice) -> (u64, u64) {
+        g.adhesion.enabled = true;
+        let ids = (place(g, 0, 1, 2000, left), place(g, 1, 1, 2000, right));
+        want_bond(g, 0, 1, Direction::Right);
+        want_bond(g, 1, 1, Direction::Left);
+        ids
+    }
+
+    fn settle(g: &mut Grid, seed: u64) {
+        g.cycle_decisions(&mut Isaac64Rng::from_seed(&[seed]));
+    }
+
+    fn cell_at(g: &Grid, x: usize, y: usize) -> Option<&Cell> {
+        g.hex(x, y).cell.as_deref()
+    }
+
+    fn inhale_at(g: &Grid, x: usize, y: usize) -> usize {
+        cell_at(g, x, y).unwrap().inhale
+    }
+
+    // The middle hex (1, 1) has (0, 1) to its left and (2, 1) to its right.
+    const INTO_MIDDLE: [(usize, usize, Direction); 2] =
+        [(0, 1, Direction::Right), (2, 1, Direction::Left)];
+
+    #[test]
+    fn reject_keeps_contested_cells_put() {
+        let mut g = small(Policy::Reject, 7);
+        for &(x, y, d) in &INTO_MIDDLE {
+            place(&mut g, x, y, 2000, Choice::Move(d));
+        }
+        settle(&mut g, 1);
+        assert!(cell_at(&g, 1, 1).is_none());
+        assert_eq!(inhale_at(&g, 0, 1), 1993);
+        assert_eq!(inhale_at(&g, 2, 1), 1993);
+    }
+
+    #[test]
+    fn reject_moves_ahead_of_spawns() {
+        let mut g = small(Policy::Reject, 7);
+        let mover = place(&mut g, 0, 1, 2000, Choice::Move(Direction::Right));
+        let spawn = Choice::Divide {
+            mate: Direction::Left,
+            spawn: Direction::Left,
+        };
+        place(&mut g, 2, 1, 9000, spawn);
+        settle(&mut g, 1);
+        assert_eq!(cell_at(&g, 1, 1).unwrap().lineage.id, mover);
+        assert!(cell_at(&g, 0, 1).is_none());
+        assert_eq!(inhale_at(&g, 2, 1), 8993);
+    }
+
+    #[test]
+    fn inhale_picks_the_richest() {
+        let mut g = small(Policy::Inhale, 7);
+        place(&mut g, 0, 1, 2000, Choice::Move(Direction::Right));
+        let spawn = Choice::Divide {
+            mate: Direction::Left,
+            spawn: Direction::Left,
+        };
+        let parent = place(&mut g, 2, 1, 3000, spawn);
+        settle(&mut g, 1);
+        // The spawner divided, paying the divide cost and giving half its inhale to the child.
+        let child = cell_at(&g, 1, 1).unwrap();
+        assert_eq!(child.lineage.parents, Parents::Divided(parent));
+        assert_eq!(inhale_at(&g, 2, 1), (3000 - 5) / 2);
+        assert_eq!(inhale_at(&g, 0, 1), 1993);
+    }
+
+    #[test]
+    fn auction_winner_pays_second_bid() {
+        let mut g = small(Policy::Auction, 7);
+        g.movement_cost = 10;
+        place(&mut g, 0, 1, 2500, Choice::Move(Direction::Right));
+        let rich = place(&mut g, 2, 1, 3000, Choice::Move(Direction::Left));
+        settle(&mut g, 1);
+        assert_eq!(cell_at(&g, 1, 1).unwrap().lineage.id, rich);
+        // Bids are inhale above the minimum of 500, so the second bid is 2000.
+        assert_eq!(inhale_at(&g, 1, 1), 3000 - 2000 - 10);
+        assert_eq!(inhale_at(&g, 0, 1), 2493);
+    }
+
+    #[test]
+    fn random_lets_either_win() {
+        let mut winners = Vec::new();
+        for seed in 0..16 {
+            let mut g = small(Policy::Random, 0);
+            let ids: Vec<u64> = INTO_MIDDLE
+                .iter()
+                .map(|&(x, y, d)| place(&mut g, x, y, 2000, Choice::Move(d)))
+                .collect();
+            settle(&mut g, seed);
+            let winner = cell_at(&g, 1, 1).unwrap().lineage.id;
+            assert_eq!(g.population(), 2);
+            winners.push(ids.iter().position(|&id| id == winner).unwrap());
+        }
+        assert!(winners.contains(&0) && winners.contains(&1));
+    }
+
+    #[test]
+    fn recycled_costs_feed_the_tile() {
+        let mut g = small(Policy::Reject, 0);
+        g.movement_cost = 10;
+        g.recycle_costs = true;
+        place(&mut g, 0, 1, 2000, Choice::Move(Direction::Right));
+        let food = g.hex(0, 1).solution.fluids[0];
+        settle(&mut g, 1);
+        assert_eq!(inhale_at(&g, 1, 1), 1990);
+        assert_eq!(g.hex(0, 1).solution.fluids[0], food + 10.0 * g.consumption);
+        assert_eq!(g.counters.energy.recycled, g.counters.energy.moved);
+    }
+
+    #[test]
+    fn moving_cell_is_not_a_mate() {
+        let mut g = small(Policy::Reject, 0);
+        let spawn = Choice::Divide {
+            mate: Direction::Right,
+            spawn: Direction::DownRight,
+        };
+        place(&mut g, 0, 1, 2000, spawn);
+        let mover = place(&mut g, 1, 1, 2000, Choice::Move(Direction::Right));
+        settle(&mut g, 1);
+        assert_eq!(cell_at(&g, 2, 1).unwrap().lineage.id, mover);
+        assert_eq!(g.population(), 2);
+        assert_eq!(g.counters.mated, 0);
+    }
+
+    #[test]
+    fn spawn_whose_mate_moves_away_pays_nothing() {
+        let mut g = small(Policy::Auction, 7);
+        let spawn = Choice::Divide {
+            mate: Direction::Right,
+            spawn: Direction::DownRight,
+        };
+        // The spawner outbids the cell moving into (0, 2), but its mate leaves for (2, 1).
+        place(&mut g, 0, 1, 3000, spawn);
+        let mate = place(&mut g, 1, 1, 2000, Choice::Move(Direction::Right));
+        place(&mut g, 1, 2, 2000, Choice::Move(Direction::Left));
+        settle(&mut g, 1);
+        assert_eq!(cell_at(&g, 2, 1).unwrap().lineage.id, mate);
+        assert!(cell_at(&g, 0, 2).is_none());
+        assert_eq!(inhale_at(&g, 0, 1), 3000);
+        assert_eq!(inhale_at(&g, 1, 2), 1993);
+        assert_eq!(g.counters.mated, 0);
+    }
+
+    #[test]
+    fn spawn_without_a_mate_does_not_contend() {
+        let mut g = small(Policy::Auction, 7);
+        let spawn = Choice::Divide {
+            mate: Direction::Left,
+            spawn: Direction::Right,
+        };
+        place(&mut g, 0, 1, 3000, spawn);
+        let mover = place(&mut g, 2, 1, 2000, Choice::Move(Direction::Left));
+        settle(&mut g, 1);
+        assert_eq!(cell_at(&g, 1, 1).unwrap().lineage.id, mover);
+        assert_eq!(inhale_at(&g, 1, 1), 2000);
+        assert_eq!(inhale_at(&g, 0, 1), 3000);
+    }
+
+    #[test]
+    fn consenting_mates_split_the_cost() {
+        let spawn = Choice::Divide {
+            mate: Direction::Right,
+            spawn: Direction::DownRight,
+        };
+        for &consent in &[false, true] {
+            let mut g = small(Policy::Reject, 0);
+            g.mating = Mating::Consent;
+            g.movement_cost = 10;
+            g.divide_cost = 5;
+            let parent = place(&mut g, 0, 1, 2000, spawn.clone());
+            let mate = place(&mut g, 1, 1, 2000, Choice::Nothing);
+            g.hex_mut(1, 1).decision.as_mut().unwrap().consent[Direction::Left as usize] = consent;
+            settle(&mut g, 1);
+            if consent {
+                let child = cell_at(&g, 0, 2).unwrap();
+                assert_eq!(child.lineage.parents, Parents::Mated(parent, mate));
+                assert_eq!(inhale_at(&g, 0, 1), (2000 - 8) / 2);
+                assert_eq!(inhale_at(&g, 1, 1), 2000 - 7);
+            } else {
+                assert!(cell_at(&g, 0, 2).is_none());
+                assert_eq!(inhale_at(&g, 0, 1), 2000);
+            }
+        }
+    }
+
+    #[test]
+    fn bonds_need_both_cells_to_form_and_one_to_hold() {
+        let mut g = small(Policy::Reject, 0);
+        g.adhesion.enabled = true;
+        place(&mut g, 0, 1, 2000, Choice::Nothing);
+        place(&mut g, 1, 1, 2000, Choice::Nothing);
+        // Who wants the bond on each side, and whether there should be one after.
+        let steps = [
+            (true, false, false),
+            (true, true, true),
+            (false, true, true),
+            (false, false, false),
+        ];
+        for &(left, right, bonded) in &steps {
+            g.hex_mut(0, 1).decision = Some(decision(Choice::Nothing));
+            g.hex_mut(1, 1).decision = Some(decision(Choice::Nothing));
+            if left {
+                want_bond(&mut g, 0, 1, Direction::Right);
+            }
+            if right {
+                want_bond(&mut g, 1, 1, Direction::Left);
+            }
+            settle(&mut g, 1);
+            assert_eq!(g.bonds(), if bonded { vec![(3, 4)] } else { vec![] });
+        }
+    }
+
+    #[test]
+    fn bonded_group_moves_together() {
+        let mut g = small(Policy::Reject, 0);
+        g.movement_cost = 10;
+        let (left, right) = pair(&mut g, Choice::Move(Direction::Right), Choice::Nothing);
+        settle(&mut g, 1);
+        assert_eq!(cell_at(&g, 1, 1).unwrap().lineage.id, left);
+        assert_eq!(cell_at(&g, 2, 1).unwrap().lineage.id, right);
+        assert_eq!(inhale_at(&g, 1, 1), 1990);
+        assert_eq!(inhale_at(&g, 2, 1), 1990);
+        assert_eq!(g.bonds(), vec![(4, 5)]);
+        assert_eq!((g.counters.group_moves, g.counters.torn), (1, 0));
+    }
+
+    #[test]
+    fn cells_that_cannot_follow_are_torn_off() {
+        let mut g = small(Policy::Reject, 0);
+        let (left, right) = pair(
+            &mut g,
+            Choice::Move(Direction::UpRight),
+            Choice::Move(Direction::UpRight),
+        );
+        // The right cell would move to (1, 0), so block it there.
+        place(&mut g, 1, 0, 2000, Choice::Nothing);
+        settle(&mut g, 1);
+        assert_eq!(cell_at(&g, 0, 0).unwrap().lineage.id, left);
+        assert_eq!(cell_at(&g, 1, 1).unwrap().lineage.id, right);
+        assert!(g.bonds().is_empty());
+        assert_eq!((g.counters.group_moves, g.counters.torn), (1, 1));
+    }
+
+    #[test]
+    fn moving_alone_tears_bonds() {
+        let mut g = small(Policy::Reject, 0);
+        g.adhesion.quorum = 1.0;
+        let (left, right) = pair(&mut g, Choice::Nothing, Choice::Move(Direction::Right));
+        settle(&mut g, 1);
+        assert_eq!(cell_at(&g, 0, 1).unwrap().lineage.id, left);
+        assert_eq!(cell_at(&g, 2, 1).unwrap().lineage.id, right);
+        assert!(g.bonds().is_empty());
+        assert_eq!((g.counters.group_moves, g.counters.torn), (0, 1));
+    }
+
+    #[test]
+    fn cells_only_bond_across_mutual_neighbors() {
+        let mut g = small(Policy::Reject, 0);
+        g.boundary = Boundary::Toroidal;
+        g.adhesion.enabled = true;
+        // With three rows wrapped around, (1, 2) is up right of (0, 0) but (1, 0) is down left of
+        // (1, 2).
+        place(&mut g, 0, 0, 2000, Choice::Nothing);
+        place(&mut g, 1, 2, 2000, Choice::Nothing);
+        want_bond(&mut g, 0, 0, Direction::UpRight);
+        want_bond(&mut g, 1, 2, Direction::DownLeft);
+        settle(&mut g, 1);
+        assert_eq!(cell_at(&g, 0, 0).unwrap().bonds, [false; 6]);
+        assert_eq!(cell_at(&g, 1, 2).unwrap().bonds, [false; 6]);
+    }
+
+    /// Takes the events collected since the last call, in no particular order.
+    fn events(g: &mut Grid) -> Vec<Event> {
+        let mut events = g.events.as_mut().unwrap().split_off(0);
+        events.sort_by_key(|e| format!("{:?}", e));
+        events
+    }
+
+    /// Sorted the same way as `events`.
+    fn sorted(mut events: Vec<Event>) -> Vec<Event> {
+        events.sort_by_key(|e| format!("{:?}", e));
+        events
+    }
+
+    #[test]
+    fn events_describe_what_cells_did() {
+        let mut g = small(Policy::Reject, 7);
+        g.cycle = 4;
+        g.events = Some(Vec::new());
+        for hex in &mut g.tiles {
+            hex.solution.fluids[3] = g.fluid.kill_fluid_normal;
+        }
+        let mover = place(&mut g, 0, 0, 2000, Choice::Move(Direction::Right));
+        let divide = Choice::Divide {
+            mate: Direction::Left,
+            spawn: Direction::Left,
+        };
+        let parent = place(&mut g, 2, 2, 2000, divide);
+        let exploder = place(&mut g, 1, 1, 3000, Choice::Explode(true));
+        let suicide = place(&mut g, 0, 1, 2000, Choice::Suicide);
+        settle(&mut g, 1);
+        let child = cell_at(&g, 1, 2).unwrap().lineage.id;
+        assert_eq!(
+            events(&mut g),
+            sorted(vec![
+                Event::Move {
+                    cycle: 4,
+                    id: mover,
+                    x: 0,
+                    y: 0,
+                    to: (1, 0),
+                },
+                Event::Divide {
+                    cycle: 4,
+                    id: parent,
+                    x: 2,
+                    y: 2,
+                    child: child,
+                    to: (1, 2),
+                },
+                Event::Explode {
+                    cycle: 4,
+                    id: exploder,
+                    x: 1,
+                    y: 1,
+                    amount: 0.5,
+                },
+            ])
+        );
+        g.cycle_death();
+        assert_eq!(
+            events(&mut g),
+            [
+                Event::Death {
+                    cycle: 4,
+                    id: suicide,
+                    x: 0,
+                    y: 1,
+                    cause: DeathCause::Suicide,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn events_describe_mating_and_conflicts() {
+        let mut g = small(Policy::Reject, 7);
+        g.events = Some(Vec::new());
+        // Two cells move into the middle, which leaves it to the lone spawn.
+        let mut movers = Vec::new();
+        for &(x, y, d) in &INTO_MIDDLE {
+            movers.push(place(&mut g, x, y, 2000, Choice::Move(d)));
+        }
+        let mate = Choice::Divide {
+            mate: Direction::Right,
+            spawn: Direction::DownRight,
+        };
+        let parent = place(&mut g, 0, 0, 2000, mate);
+        let partner = place(&mut g, 1, 0, 2000, Choice::Nothing);
+        settle(&mut g, 1);
+        let conflict = |id, x| {
+            Event::MoveConflict {
+                cycle: 0,
+                id: id,
+                x: x,
+                y: 1,
+                to: (1, 1),
+                contenders: 3,
+            }
+        };
+        let child = cell_at(&g, 1, 1).unwrap().lineage.id;
+        assert_eq!(
+            events(&mut g),
+            sorted(vec![
+                conflict(movers[0], 0),
+                conflict(movers[1], 2),
+                Event::Mate {
+                    cycle: 0,
+                    id: parent,
+                    x: 0,
+                    y: 0,
+                    mate: partner,
+                    child: child,
+                    to: (1, 1),
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn impassable_terrain_buries_cells() {
+        let mut g = small(Policy::Reject, 0);
+        let (left, _) = pair(&mut g, Choice::Nothing, Choice::Nothing);
+        settle(&mut g, 1);
+        assert_eq!(g.bonds(), vec![(3, 4)]);
+        let died = g.counters.energy.died + g.consumption * inhale_at(&g, 0, 1) as f64;
+        g.events = Some(Vec::new());
+        let mut terrain = vec![Terrain::Open; g.tiles.len()];
+        terrain[3] = Terrain::Rock;
+        g.set_terrain(&terrain);
+        assert!(cell_at(&g, 0, 1).is_none());
+        assert_eq!(cell_at(&g, 1, 1).unwrap().bonds, [false; 6]);
+        assert_eq!(g.counters.deaths[DeathCause::Buried as usize], 1);
+        assert_eq!(g.counters.energy.died, died);
+        match g.events.as_ref().unwrap()[..] {
+            [Event::Death { id, cause, .. }] => assert_eq!((id, cause), (left, DeathCause::Buried)),
+            ref events => panic!("unexpected events {:?}", events),
+        }
+    }
+
+    #[test]
+    fn impassable_terrain_blocks_moves_and_spawns() {
+        for &terrain in &[Terrain::Rock, Terrain::Membrane] {
+            let mut g = small(Policy::Reject, 7);
+            let mut tiles = vec![Terrain::Open; g.tiles.len()];
+            tiles[4] = terrain;
+            g.set_terrain(&tiles);
+            let mover = place(&mut g, 0, 1, 2000, Choice::Move(Direction::Right));
+            let spawn = Choice::Divide {
+                mate: Direction::Left,
+                spawn: Direction::Left,
+            };
+            let parent = place(&mut g, 2, 1, 2000, spawn);
+            settle(&mut g, 1);
+            assert!(cell_at(&g, 1, 1).is_none(), "{:?}", terrain);
+            assert_eq!(cell_at(&g, 0, 1).unwrap().lineage.id, mover);
+            assert_eq!(cell_at(&g, 2, 1).unwrap().lineage.id, parent);
+            // Nothing was contested, so nobody paid.
+            assert_eq!((inhale_at(&g, 0, 1), inhale_at(&g, 2, 1)), (2000, 2000));
+            assert_eq!(g.population(), 2);
+        }
+    }
+
+    #[test]
+    fn soil_makes_more_food() {
+        let produced = |terrain: Terrain| {
+            let mut g = small(Policy::Reject, 0);
+            g.set_terrain(&vec![terrain; g.tiles.len()]);
+            // Food is made from chemical B.
+            for hex in &mut g.tiles {
+                hex.solution.fluids[2] = 0.5;
+            }
+            g.cycle_fluids();
+            g.counters.energy.produced
+        };
+        let open = produced(Terrain::Open);
+        assert!(open > 0.0);
+        assert_eq!(produced(Terrain::Soil), 2.0 * open);
+        assert_eq!(produced(Terrain::Rock), 0.0);
+        assert_eq!(produced(Terrain::Membrane), open);
+    }
+
+    /// Runs random small grids that cells keep spawning into and checks that no cell is lost or
+    /// duplicated, that no cell acts more than once in a cycle, that every bond goes both ways and
+    /// that the energy ledger balances. Across all of them, cells must have done everything these
+    /// checks are about.
+    #[test]
+    fn random_grids_keep_cells_consistent() {
+        let boundaries = [
+            Boundary::Toroidal,
+            Boundary::Wall,
+            Boundary::Absorbing,
+            Boundary::Reflecting,
+        ];
+        let policies = [
+            Policy::Reject,
+            Policy::Random,
+            Policy::Inhale,
+            Policy::Auction,
+        ];
+        // How often each of those things happened over every case.
+        let mut populated = 0;
+        let mut moves = 0;
+        let mut divisions = 0;
+        let mut matings = 0;
+        let mut consenting_matings = 0;
+        let mut move_conflicts = 0;
+        let mut spawn_conflicts = 0;
+        let mut recycled = 0.0;
+        let mut bonded = 0;
+        let mut group_moves = 0;
+        let mut cases = Isaac64Rng::from_seed(&[22]);
+        for case in 0..24 {
+            let mut rng = Isaac64Rng::from_seed(&[case]);
+            let mut g = Grid::new(
+                cases.gen_range(1, 7),
+                cases.gen_range(1, 7),
+                0.5,
+                cases.gen_range(0.1, 2.0),
+                cases.gen_range(0, 1000),
+                10000,
+                cases.gen_range(0, 200),
+                cases.gen_range(0, 200),
+                2100,
+                0.5,
+                0.5,
+                boundaries[cases.gen_range(0, 4)],
+                FluidParams::default(),
+                InstructionSet::default(),
+                &mut rng,
+            );
+            g.conflicts = Conflicts {
+                policy: policies[cases.gen_range(0, 4)],
+                penalty: cases.gen_range(0, 100),
+            };
+            g.recycle_costs = cases.gen();
+            g.mating = if cases.gen() {
+                Mating::Consent
+            } else {
+                Mating::Free
+            };
+            g.adhesion = Adhesion {
+                enabled: cases.gen(),
+                quorum: cases.gen(),
+            };
+            g.events = Some(Vec::new());
+            g.audit.enabled = true;
+            for _ in 0..250 {
+                g.cycle();
+                assert_eq!(g.audit.imbalance, None);
+                if g.population() > 0 {
+                    populated += 1;
+                }
+                let bonds = g.tiles
+                    .iter()
+                    .filter_map(|t| t.cell.as_ref())
+                    .map(|c| c.bonds.iter().filter(|&&b| b).count())
+                    .sum::<usize>();
+                assert_eq!(bonds, 2 * g.bonds().len());
+                bonded += g.bonds().len();
+                let mut ids: Vec<u64> = g.tiles
+                    .iter()
+                    .filter_map(|t| t.cell.as_ref().map(|c| c.lineage.id))
+                    .collect();
+                ids.sort();
+                ids.dedup();
+                assert_eq!(ids.len(), g.population());
+                let mut acted = Vec::new();
+                let mut moved = Vec::new();
+                let mut mates = Vec::new();
+                for event in g.events.as_mut().unwrap().drain(..) {
+                    match event {
+                        Event::Move { id, .. } => {
+                            acted.push(id);
+                            moved.push(id);
+                        }
+                        Event::Divide { id, .. } => {
+                            acted.push(id);
+                            divisions += 1;
+                        }
+                        Event::Mate { id, mate, .. } => {
+                            acted.push(id);
+                            mates.push(mate);
+                        }
+                        Event::MoveConflict { .. } => move_conflicts += 1,
+                        Event::SpawnConflict { .. } => spawn_conflicts += 1,
+                        _ => {}
+                    }
+                }
+                let actions = acted.len();
+                acted.sort();
+                acted.dedup();
+                assert_eq!(acted.len(), actions);
+                assert!(mates.iter().all(|m| !moved.contains(m)));
+                moves += moved.len();
+                matings += mates.len();
+                if g.mating == Mating::Consent {
+                    consenting_matings += mates.len();
+                }
+            }
+            recycled += g.counters.energy.recycled;
+            group_moves += g.counters.group_moves;
+        }
+        assert!(populated > 0);
+        assert!(moves > 0 && divisions > 0 && matings > 0);
+        assert!(move_conflicts > 0 && spawn_conflicts > 0);
+        assert!(recycled > 0.0);
+        assert!(consenting_matings > 0);
+        assert!(bonded > 0 && group_moves > 0);
+    }
 }
@@ -0,0 +1,585 @@
+use gg::render2::*;
+use nalgebra as na;
+use nalgebra::ToHomogeneous;
+use num::One;
+use glium;
+use rayon::prelude::*;
+
+use std::time;
+
+use genome::{self, Genome};
+use grid::Grid;
+use hex::{Axial, Offset, DIRECTIONS};
+use recording::{self, Recorders};
+use save;
+use save::snapshot::Snapshots;
+
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+const DEFAULT_SCREEN_ZOOM_RATIO: f32 = 1.0;
+
+// TODO: Figure out when lines are used and set it correctly.
+const SCROLL_LINES_RATIO: f32 = 0.707;
+const SCROLL_PIXELS_RATIO: f32 = 0.707;
+
+const GRID_SPAWN_MULTIPLY: f64 = 1.25;
+const GRID_EXPLODE_MULTIPLY: f64 = 1.25;
+const GRID_RELEASE_MULTIPLY: f64 = 1.25992104989;
+
+const SECONDS_BETWEEN_AUTOSAVES: u64 = 60 * 30;
+
+const MANUAL_FEED_AMOUNT: f64 = 500000.0;
+const MANUAL_KILL_AMOUNT: f64 = 500000.0;
+
+// Ratio of width/height in a 2d circle tight-pack or a hex grid.
+const WIDTH_HEIGHT_RATIO: f32 = 0.86602540378;
+
+/// Opens a window and runs the simulation interactively until the window is closed.
+pub fn run(mut g: Grid, snapshots: &Snapshots, recording: &recording::Options) {
+    use glium::DisplayBuild;
+    let display = glium::glutin::WindowBuilder::new()
+        .with_vsync()
+        .build_glium()
+        .unwrap();
+    // window.set_cursor_state(glium::glutin::CursorState::Hide).ok().unwrap();
+    let glowy = Renderer::new(&display);
+
+    let mut screen_hex_ratio = DEFAULT_SCREEN_ZOOM_RATIO * g.height as f32 * WIDTH_HEIGHT_RATIO;
+
+    // The middle of the screen, in the units of `hex::Offset::pixel`.
+    let mut center = grid_center(&g);
+    let mut last_mouse_pos = (0, 0);
+    let mut mouse_pressed = false;
+
+    let mut rendering_enabled = true;
+    let mut pure_color_mode = false;
+
+    let mut last_autosave = time::Instant::now();
+    let mut recorders = Recorders::start(recording, &mut g);
+    // Lines typed into the terminal, read on first use.
+    let mut typed: Option<Receiver<String>> = None;
+    let mut asking_snapshot = false;
+
+    loop {
+        use glium::Surface;
+
+        // // Get dimensions
+        let dims = display.get_framebuffer_dimensions();
+        // Multiply this by width coordinates to get normalized screen coordinates.
+        let hscale = dims.1 as f32 / dims.0 as f32;
+
+        // Don't even vsync if rendering is disabled.
+        let mut target = if rendering_enabled {
+            Some(display.draw())
+        } else {
+            None
+        };
+        target.as_mut().map_or_else(
+            || {},
+            |t| t.clear_color(0.0, 0.0, 0.0, 1.0),
+        );
+
+        let (screen_width, screen_height) = (screen_hex_ratio / hscale, screen_hex_ratio);
+        let units_per_pixel = 2.0 * screen_height / dims.1 as f32;
+
+        let center_mouse_coord = (dims.0 as f32 / 2.0, dims.1 as f32 / 2.0);
+
+        let projection = [
+            [1.0 / screen_width, 0.0, 0.0],
+            [0.0, 1.0 / screen_height, 0.0],
+            [0.0, 0.0, 1.0],
+        ];
+
+        if rendering_enabled {
+            let groups = g.groups();
+            // Rows are built in parallel and joined back in order into one buffer.
+            let v: Vec<QBezier> = (0..g.height)
+                .into_par_iter()
+                .flat_map(|y| {
+                    let mut v = Vec::new();
+                    for x in 0..g.width {
+                        let hex = g.hex(x, y);
+                        let here = Offset::new(x as isize, y as isize);
+                        let pos = here.pixel();
+                        let pos = (pos.0 - center.0, pos.1 - center.1);
+                        let transform = na::Isometry2::new(
+                            na::Vector2::new(pos.0, pos.1),
+                            na::Vector1::new(0.0),
+                        ).to_homogeneous();
+                        let color = if pure_color_mode {
+                            hex.pure_color()
+                        } else {
+                            hex.color(&g.fluid)
+                        };
+                        append_circle(&mut v, 0.6, 0.6, color, &transform);
+
+                        if let Some(ref cell) = hex.cell {
+                            let size = groups.of[x + y * g.width]
+                                .map_or(1, |n| groups.members[n].len());
+                            let color = if size > 1 {
+                                group_color(size)
+                            } else {
+                                cell.color()
+                            };
+                            append_circle(&mut v, 0.3, 0.3, color, &transform);
+                            // Each cell draws its half of every bond.
+                            for (&bonded, &direction) in cell.bonds.iter().zip(&DIRECTIONS) {
+                                if bonded {
+                                    let from = here.pixel();
+                                    let to = here.neighbor(direction).pixel();
+                                    let reach = (0.5 * (to.0 - from.0), 0.5 * (to.1 - from.1));
+                                    append_bond(&mut v, pos, reach, color);
+                                }
+                            }
+                        }
+                    }
+                    v
+                })
+                .collect();
+
+            glowy.render_qbeziers_flat(
+                target.as_mut().unwrap(),
+                *na::Matrix3::one().as_ref(),
+                projection,
+                &v,
+            );
+        }
+
+        g.cycle();
+        recorders.update(&mut g);
+
+        // Don't even vsync if rendering is disabled.
+        if rendering_enabled {
+            target.unwrap().finish().unwrap();
+        }
+
+        let now = time::Instant::now();
+        if now - last_autosave > time::Duration::from_secs(SECONDS_BETWEEN_AUTOSAVES) {
+            last_autosave = now;
+            recorders.flush();
+            save_snapshot(&g, snapshots);
+        }
+
+        for ev in display.poll_events() {
+            use glium::glutin::{Event, ElementState, MouseButton, MouseScrollDelta,
+                                VirtualKeyCode as VKC};
+            match ev {
+                Event::Closed => return,
+                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::L)) => {
+                    if !asking_snapshot {
+                        let typed = typed.get_or_insert_with(read_lines);
+                        // Only what is typed from now on answers the question.
+                        while typed.try_recv().is_ok() {}
+                        print!("Snapshot to load (cycle number or name, empty for the latest): ");
+                        let _ = io::stdout().flush();
+                        asking_snapshot = true;
+                    }
+                }
+                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::W)) => {
+                    last_autosave = now;
+                    recorders.flush();
+                    save_snapshot(&g, snapshots);
+                }
+                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::F)) => {
+                    let mouse = (
+                        last_mouse_pos.0 as f32 - center_mouse_coord.0,
+                        last_mouse_pos.1 as f32 - center_mouse_coord.1,
+                    );
+                    if let Some((x, y)) = hex_under_mouse(&g, center, units_per_pixel, mouse) {
+                        g.counters.energy.fed += MANUAL_FEED_AMOUNT;
+                        let hex = g.hex_mut(x, y);
+                        hex.solution.fluids[0] += MANUAL_FEED_AMOUNT;
+                        println!("New food: {}", hex.solution.fluids[0]);
+                    }
+                }
+                // Export the brain of the cell at cursor.
+                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::G)) => {
+                    let mouse = (
+                        last_mouse_pos.0 as f32 - center_mouse_coord.0,
+                        last_mouse_pos.1 as f32 - center_mouse_coord.1,
+                    );
+                    if let Some((x, y)) = hex_under_mouse(&g, center, units_per_pixel, mouse) {
+                        if let Some(ref c) = g.hex(x, y).cell {
+                            let genome = Genome::new(c, g.cycle);
+                            match genome.export(genome::DEFAULT_DIR) {
+                                Ok(path) => println!("Exported genome to \"{}\"", path.display()),
+                                Err(e) => println!("Failed to export genome: {}", e),
+                            }
+                        }
+                    }
+                }
+                // Make kill chemicals at cursor.
+                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::K)) => {
+                    let mouse = (
+                        last_mouse_pos.0 as f32 - center_mouse_coord.0,
+                        last_mouse_pos.1 as f32 - center_mouse_coord.1,
+                    );
+                    if let Some((x, y)) = hex_under_mouse(&g, center, units_per_pixel, mouse) {
+                        let hex = g.hex_mut(x, y);
+                        hex.solution.fluids[3] += MANUAL_KILL_AMOUNT;
+                        println!("New kill fluid: {}", hex.solution.fluids[3]);
+                    }
+                }
+                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::C)) => {
+                    let food = g.tiles.iter().map(|t| t.solution.fluids[0]).sum::<f64>();
+                    println!("Cleared {} food", food);
+                    g.counters.energy.fed -= food;
+                    for tile in &mut g.tiles {
+                        tile.solution.fluids[0] = 0.0;
+                    }
+                }
+                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::V)) => {
+                    pure_color_mode = !pure_color_mode;
+                    println!(
+                        "Pure color mode {}",
+                        if pure_color_mode { "on" } else { "off" }
+                    );
+                }
+                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::H)) => {
+                    println!("Reset screen ratio");
+                    screen_hex_ratio = DEFAULT_SCREEN_ZOOM_RATIO * g.height as f32 *
+                        WIDTH_HEIGHT_RATIO;
+                    center = grid_center(&g);
+                }
+                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::U)) => {
+                    g.spawn_rate *= GRID_SPAWN_MULTIPLY;
+                    println!("New spawn rate: {}", g.spawn_rate);
+                }
+                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::D)) => {
+                    g.spawn_rate /= GRID_SPAWN_MULTIPLY;
+                    println!("New spawn rate: {}", g.spawn_rate);
+                }
+                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::X)) => {
+                    g.explode_requirement =
+                        (g.explode_requirement as f64 * GRID_EXPLODE_MULTIPLY) as usize;
+                    println!("New explode requirement: {}", g.explode_requirement);
+                }
+                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::Z)) => {
+                    g.explode_requirement =
+                        (g.explode_requirement as f64 / GRID_EXPLODE_MULTIPLY) as usize;
+                    println!("New explode requirement: {}", g.explode_requirement);
+                }
+                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::LBracket)) => {
+                    g.death_release_coefficient /= GRID_RELEASE_MULTIPLY;
+                    println!(
+                        "New death release coefficient: {}",
+                        g.death_release_coefficient
+                    );
+                }
+                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::RBracket)) => {
+                    g.death_release_coefficient *= GRID_RELEASE_MULTIPLY;
+                    println!(
+                        "New death release coefficient: {}",
+                        g.death_release_coefficient
+                    );
+                }
+                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::Q)) => {
+                    g.explode_amount *= GRID_EXPLODE_MULTIPLY;
+                    println!("New explode amount: {}", g.explode_amount);
+                }
+                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::A)) => {
+                    g.explode_amount /= GRID_EXPLODE_MULTIPLY;
+                    println!("New explode amount: {}", g.explode_amount);
+                }
+                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::P)) => {
+                    g.movement_cost += 1;
+                    println!("New movement cost: {}", g.movement_cost);
+                }
+                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::O)) => {
+                    if g.movement_cost > 0 {
+                        g.movement_cost -= 1;
+                    }
+                    println!("New movement cost: {}", g.movement_cost);
+                }
+                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::R)) => {
+                    g.randomize();
+                }
+                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::S)) => {
+                    g.spawning = !g.spawning;
+                    println!(
+                        "Spawning {}",
+                        if g.spawning { "enabled" } else { "disabled" }
+                    );
+                }
+                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::T)) => {
+                    rendering_enabled = !rendering_enabled;
+                    println!(
+                        "Rendering {}",
+                        if rendering_enabled {
+                            "enabled"
+                        } else {
+                            "disabled"
+                        }
+                    );
+                }
+                Event::MouseWheel(MouseScrollDelta::LineDelta(_, lines), _) => {
+                    screen_hex_ratio -= lines * SCROLL_LINES_RATIO;
+                }
+                Event::MouseWheel(MouseScrollDelta::PixelDelta(_, pixels), _) => {
+                    screen_hex_ratio -= pixels * SCROLL_PIXELS_RATIO;
+                }
+                Event::MouseMoved(x, y) => {
+                    if mouse_pressed {
+                        center.0 -= units_per_pixel * (x - last_mouse_pos.0) as f32;
+                        center.1 += units_per_pixel * (y - last_mouse_pos.1) as f32;
+                    }
+                    last_mouse_pos = (x, y);
+                }
+                Event::MouseInput(ElementState::Released, MouseButton::Left) => {
+                    let mouse = (
+                        last_mouse_pos.0 as f32 - center_mouse_coord.0,
+                        last_mouse_pos.1 as f32 - center_mouse_coord.1,
+                    );
+                    if let Some((x, y)) = hex_under_mouse(&g, center, units_per_pixel, mouse) {
+                        println!("{:?}", g.hex(x, y));
+                    }
+                }
+                Event::MouseInput(state, MouseButton::Right) => {
+                    match state {
+                        ElementState::Pressed => mouse_pressed = true,
+                        ElementState::Released => mouse_pressed = false,
+                    }
+                }
+                Event::Focused(_) => {
+                    // Always stop handling mouse press if we loose or gain focus.
+                    mouse_pressed = false;
+                }
+                _ => (),
+            }
+        }
+
+        // The grid keeps running while the snapshot to load is being typed.
+        if asking_snapshot {
+            let spec = match typed.as_ref().unwrap().try_recv() {
+                Ok(line) => Some(line),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => {
+                    println!();
+                    println!("Standard input is closed, loading the latest snapshot.");
+                    Some(String::new())
+                }
+            };
+            if let Some(spec) = spec {
+                asking_snapshot = false;
+                last_autosave = time::Instant::now();
+                if let Some(t) = load_snapshot(snapshots, &spec) {
+                    g = t;
+                    // Close the old recordings before the lineage log is cut back to where the
+                    // loaded grid was saved.
+                    recorders.stop();
+                    recorders = Recorders::start(recording, &mut g);
+                }
+            }
+        }
+    }
+}
+
+/// Reads lines from stdin on another thread until it is closed.
+fn read_lines() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            let sent = line.ok().map_or(false, |line| sender.send(line).is_ok());
+            if !sent {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+fn append_circle(
+    v: &mut Vec<QBezier>,
+    radius: f32,
+    circle_scale: f32,
+    color: [f32; 4],
+    modelview: &na::Matrix3<f32>,
+) {
+    let transform = |n: [f32; 2]| {
+        let na::Vector3 { x, y, .. } = *modelview *
+            na::Vector3::new(n[0] * circle_scale, n[1] * circle_scale, 1.0);
+        [x, y]
+    };
+    v.extend(
+        [
+            QBezier {
+                position0: transform([0.0, -1.0]),
+                position1: transform([0.5773502691896256, -1.0]),
+                position2: transform([0.8660254037844386, -0.5]),
+                inner_color0: color,
+                inner_color1: color,
+                falloff_color0: color,
+                falloff_color1: color,
+                falloff0: 0.25,
+                falloff1: 0.25,
+                falloff_radius0: radius,
+                falloff_radius1: radius,
+                inner_radius0: 0.0,
+                inner_radius1: 0.0,
+            },
+            QBezier {
+                position0: transform([0.8660254037844386, -0.5]),
+                position1: transform([1.1547005383792515, 0.0]),
+                position2: transform([0.8660254037844387, 0.5]),
+                inner_color0: color,
+                inner_color1: color,
+                falloff_color0: color,
+                falloff_color1: color,
+                falloff0: 0.25,
+                falloff1: 0.25,
+                falloff_radius0: radius,
+                falloff_radius1: radius,
+                inner_radius0: 0.0,
+                inner_radius1: 0.0,
+            },
+            QBezier {
+                position0: transform([0.8660254037844387, 0.5]),
+                position1: transform([0.5773502691896261, 1.0]),
+                position2: transform([0.0, 1.0]),
+                inner_color0: color,
+                inner_color1: color,
+                falloff_color0: color,
+                falloff_color1: color,
+                falloff0: 0.25,
+                falloff1: 0.25,
+                falloff_radius0: radius,
+                falloff_radius1: radius,
+                inner_radius0: 0.0,
+                inner_radius1: 0.0,
+            },
+            QBezier {
+                position0: transform([0.0, 1.0]),
+                position1: transform([-0.5773502691896254, 1.0]),
+                position2: transform([-0.8660254037844384, 0.5]),
+                inner_color0: color,
+                inner_color1: color,
+                falloff_color0: color,
+                falloff_color1: color,
+                falloff0: 0.25,
+                falloff1: 0.25,
+                falloff_radius0: radius,
+                falloff_radius1: radius,
+                inner_radius0: 0.0,
+                inner_radius1: 0.0,
+            },
+            QBezier {
+                position0: transform([-0.8660254037844384, 0.5]),
+                position1: transform([-1.1547005383792515, 0.0]),
+                position2: transform([-0.866025403784439, -0.5]),
+                inner_color0: color,
+                inner_color1: color,
+                falloff_color0: color,
+                falloff_color1: color,
+                falloff0: 0.25,
+                falloff1: 0.25,
+                falloff_radius0: radius,
+                falloff_radius1: radius,
+                inner_radius0: 0.0,
+                inner_radius1: 0.0,
+            },
+            QBezier {
+                position0: transform([-0.866025403784439, -0.5]),
+                position1: transform([-0.5773502691896263, -1.0]),
+                position2: transform([-0.0, -1.0]),
+                inner_color0: color,
+                inner_color1: color,
+                falloff_color0: color,
+                falloff_color1: color,
+                falloff0: 0.25,
+                falloff1: 0.25,
+                falloff_radius0: radius,
+                falloff_radius1: radius,
+                inner_radius0: 0.0,
+                inner_radius1: 0.0,
+            },
+        ].iter(),
+    );
+}
+
+/// The color of the cells in a group of `size` cells, turning from yellow to red as it grows.
+fn group_color(size: usize) -> [f32; 4] {
+    let red = 1.0 - 2.0 / size as f32;
+    [1.0, 0.8 - 0.6 * red, 0.2, 1.0]
+}
+
+/// Appends a bond from `position` to `position + reach`.
+fn append_bond(v: &mut Vec<QBezier>, position: (f32, f32), reach: (f32, f32), color: [f32; 4]) {
+    let along = |t: f32| [position.0 + t * reach.0, position.1 + t * reach.1];
+    v.push(QBezier {
+        position0: along(0.0),
+        position1: along(0.5),
+        position2: along(1.0),
+        inner_color0: color,
+        inner_color1: color,
+        falloff_color0: color,
+        falloff_color1: color,
+        falloff0: 0.25,
+        falloff1: 0.25,
+        falloff_radius0: 0.1,
+        falloff_radius1: 0.1,
+        inner_radius0: 0.0,
+        inner_radius1: 0.0,
+    });
+}
+
+/// The middle of the grid, in the units of `hex::Offset::pixel`.
+fn grid_center(g: &Grid) -> (f32, f32) {
+    (g.width as f32, WIDTH_HEIGHT_RATIO * g.height as f32)
+}
+
+/// The grid position under the mouse, given as an offset in pixels from the middle of the screen.
+fn hex_under_mouse(
+    g: &Grid,
+    center: (f32, f32),
+    units_per_pixel: f32,
+    mouse: (f32, f32),
+) -> Option<(usize, usize)> {
+    let hex = Axial::from_pixel(
+        center.0 + mouse.0 * units_per_pixel,
+        center.1 - mouse.1 * units_per_pixel,
+    ).offset();
+    if hex.x >= 0 && hex.y >= 0 && (hex.x as usize) < g.width && (hex.y as usize) < g.height {
+        Some((hex.x as usize, hex.y as usize))
+    } else {
+        None
+    }
+}
+
+fn save_snapshot(g: &Grid, snapshots: &Snapshots) {
+    match snapshots.save(g) {
+        Ok(path) => println!("Successfully saved grid to \"{}\".", path.display()),
+        Err(e) => println!("Failed to save grid state: {}", e),
+    }
+}
+
+/// Loads the snapshot named by `spec`, a cycle number or name, or the latest if it is empty.
+fn load_snapshot(snapshots: &Snapshots, spec: &str) -> Option<Grid> {
+    let path = match spec.trim() {
+        "" => snapshots.latest().map(|s| s.map(|s| s.path)),
+        spec => snapshots.find(spec).map(Some),
+    };
+    let path = match path {
+        Ok(Some(path)) => path,
+        Ok(None) => {
+            println!("There are no snapshots in \"{}\".", snapshots.dir.display());
+            return None;
+        }
+        Err(e) => {
+            println!("Failed to find snapshot: {}", e);
+            return None;
+        }
+    };
+    match save::load(&path) {
+        Ok(g) => {
+            println!("Successfully loaded grid from \"{}\".", path.display());
+            Some(g)
+        }
+        Err(e) => {
+            println!("Failed to load grid state: {}", e);
+            None
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use grid::Grid;
+use recording::{self, Recorders};
+use save::snapshot::Snapshots;
+
+use std::time::{Duration, Instant};
+
+pub struct Options {
+    /// Stop after this many cycles.
+    pub cycles: Option<u64>,
+    /// Stop after this much wall-clock time.
+    pub duration: Option<Duration>,
+    /// Save the grid every this many cycles.
+    pub save_every: Option<u64>,
+    /// Print progress every this many cycles.
+    pub progress_every: u64,
+    pub snapshots: Snapshots,
+    pub recording: recording::Options,
+}
+
+/// Runs the simulation without creating any GL context until one of the limits in `opts` is hit.
+///
+/// If neither `cycles` nor `duration` is set this runs forever.
+pub fn run(mut g: Grid, opts: &Options) {
+    let start = Instant::now();
+    let mut last_progress = start;
+    let mut cycles = 0u64;
+    let mut recorders = Recorders::start(&opts.recording, &mut g);
+
+    loop {
+        if opts.cycles.map_or(false, |n| cycles >= n) ||
+            opts.duration.map_or(false, |d| start.elapsed() >= d)
+        {
+            break;
+        }
+
+        g.cycle();
+        cycles += 1;
+        recorders.update(&mut g);
+
+        if opts.progress_every != 0 && cycles % opts.progress_every == 0 {
+            let now = Instant::now();
+            let elapsed = now - last_progress;
+            let seconds = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9;
+            println!(
+                "Cycle {}: population {}, {:.2} cycles/s",
+                g.cycle,
+                g.population(),
+                opts.progress_every as f64 / seconds
+            );
+            last_progress = now;
+        }
+
+        if scheduled_save(opts, cycles) {
+            recorders.flush();
+            save_grid(&g, &opts.snapshots);
+        }
+    }
+
+    println!("Finished {} cycles in {:?}.", cycles, start.elapsed());
+    // Don't save the same state twice if the schedule just saved it.
+    recorders.flush();
+    if !scheduled_save(opts, cycles) {
+        save_grid(&g, &opts.snapshots);
+    }
+}
+
+fn scheduled_save(opts: &Options, cycles: u64) -> bool {
+    opts.save_every.map_or(false, |n| n != 0 && cycles % n == 0)
+}
+
+fn save_grid(g: &Grid, snapshots: &Snapshots) {
+    match snapshots.save(g) {
+        Ok(path) => println!("Successfully saved grid to \"{}\".", path.display()),
+        Err(e) => println!("Failed to save grid state: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cell::brain::InstructionSet;
+    use fluid::FluidParams;
+    use grid::Boundary;
+    use rand::{Isaac64Rng, SeedableRng};
+    use save;
+    use save::compression::Compression;
+    use save::snapshot::Retention;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    #[test]
+    fn saves_on_schedule_and_at_the_end() {
+        let dir = env::temp_dir().join(format!("evomata-headless-{}", process::id()));
+        let mut rng = Isaac64Rng::from_seed(&[1]);
+        let g = Grid::new(
+            8,
+            8,
+            0.04,
+            0.1,
+            500,
+            10000,
+            0,
+            5,
+            2100,
+            0.5,
+            0.5,
+            Boundary::Wall,
+            FluidParams::default(),
+            InstructionSet::default(),
+            &mut rng,
+        );
+        let opts = Options {
+            cycles: Some(5),
+            duration: None,
+            save_every: Some(2),
+            progress_every: 0,
+            snapshots: Snapshots::new(dir.clone(), Retention::default(), Compression::Gzip),
+            recording: recording::Options::default(),
+        };
+        run(g, &opts);
+
+        let cycles: Vec<u64> = opts.snapshots.list().unwrap().iter().map(|s| s.cycle).collect();
+        let latest = opts.snapshots.latest().unwrap().unwrap();
+        let loaded = save::load(&latest.path);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(cycles, vec![2, 4, 5]);
+        assert_eq!(loaded.unwrap().cycle, 5);
+    }
+}
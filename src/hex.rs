@@ -0,0 +1,282 @@
+//! Coordinates on the hexagonal grid.
+//!
+//! The grid is made of pointy topped hexes stored row by row in "even-r" offset coordinates, where
+//! every even row is shifted half a hex to the right of the odd rows around it. `y` grows
+//! downwards, so "up" means towards row `y - 1`. Whether the grid wraps around its edges is up to
+//! the grid's `Boundary`.
+//!
+//! Offset coordinates are only good for storage, so anything involving directions is done in axial
+//! coordinates, where moving in a direction is always the same vector.
+//!
+//! Pixel positions are in the units the renderer draws hexes in: neighboring hexes on a row are
+//! `2` apart and the center of the hex at offset `(0, 0)` is `(1.5, sqrt(3) / 2)`. The pixel `y`
+//! axis points the same way as the row index.
+
+use std::ops::Add;
+
+const SQRT_3: f32 = 1.732_050_8;
+
+enum_from_primitive! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+    pub enum Direction {
+        UpRight,
+        UpLeft,
+        Left,
+        DownLeft,
+        DownRight,
+        Right,
+    }
+}
+
+/// Every direction, in the order neighbors are listed in.
+pub const DIRECTIONS: [Direction; 6] = [
+    Direction::UpRight,
+    Direction::UpLeft,
+    Direction::Left,
+    Direction::DownLeft,
+    Direction::DownRight,
+    Direction::Right,
+];
+
+impl Direction {
+    pub fn flip(&self) -> Direction {
+        use self::Direction::*;
+        match *self {
+            UpRight => DownLeft,
+            UpLeft => DownRight,
+            Left => Right,
+            DownLeft => UpRight,
+            DownRight => UpLeft,
+            Right => Left,
+        }
+    }
+
+    /// The step one hex in this direction.
+    pub fn axial(&self) -> Axial {
+        use self::Direction::*;
+        match *self {
+            UpRight => Axial::new(1, -1),
+            UpLeft => Axial::new(0, -1),
+            Left => Axial::new(-1, 0),
+            DownLeft => Axial::new(-1, 1),
+            DownRight => Axial::new(0, 1),
+            Right => Axial::new(1, 0),
+        }
+    }
+}
+
+/// A position in storage order, which is a column `x` within a row `y`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Offset {
+    pub x: isize,
+    pub y: isize,
+}
+
+impl Offset {
+    pub fn new(x: isize, y: isize) -> Self {
+        Offset { x: x, y: y }
+    }
+
+    pub fn axial(self) -> Axial {
+        Axial::new(self.x - (self.y + (self.y & 1)) / 2, self.y)
+    }
+
+    pub fn neighbor(self, direction: Direction) -> Offset {
+        self.axial().neighbor(direction).offset()
+    }
+
+    pub fn pixel(self) -> (f32, f32) {
+        self.axial().pixel()
+    }
+
+    /// Wraps the position around a grid of the given size.
+    pub fn wrap(self, width: usize, height: usize) -> (usize, usize) {
+        let (width, height) = (width as isize, height as isize);
+        (
+            (((self.x % width) + width) % width) as usize,
+            (((self.y % height) + height) % height) as usize,
+        )
+    }
+
+    /// The position on a grid of the given size, or `None` if it is off the grid.
+    pub fn within(self, width: usize, height: usize) -> Option<(usize, usize)> {
+        if self.x >= 0 && self.y >= 0 && (self.x as usize) < width && (self.y as usize) < height {
+            Some((self.x as usize, self.y as usize))
+        } else {
+            None
+        }
+    }
+}
+
+/// A position in axial coordinates, where `r` is the row and `q` runs along it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Axial {
+    pub q: isize,
+    pub r: isize,
+}
+
+impl Axial {
+    pub fn new(q: isize, r: isize) -> Self {
+        Axial { q: q, r: r }
+    }
+
+    pub fn offset(self) -> Offset {
+        Offset::new(self.q + (self.r + (self.r & 1)) / 2, self.r)
+    }
+
+    pub fn neighbor(self, direction: Direction) -> Axial {
+        self + direction.axial()
+    }
+
+    /// The center of the hex.
+    pub fn pixel(self) -> (f32, f32) {
+        (
+            2.0 * self.q as f32 + self.r as f32 + 1.5,
+            SQRT_3 * (self.r as f32 + 0.5),
+        )
+    }
+
+    /// The hex containing a pixel.
+    pub fn from_pixel(x: f32, y: f32) -> Axial {
+        let r = y / SQRT_3 - 0.5;
+        let q = (x - 1.5 - r) / 2.0;
+        Axial::round(q as f64, r as f64)
+    }
+
+    /// The hex containing fractional axial coordinates.
+    fn round(q: f64, r: f64) -> Axial {
+        // Round in cube coordinates, where `q + r + s = 0`, and fix up whichever coordinate was
+        // rounded the furthest.
+        let s = -q - r;
+        let (mut rq, mut rr, rs) = (q.round(), r.round(), s.round());
+        let (dq, dr, ds) = ((rq - q).abs(), (rr - r).abs(), (rs - s).abs());
+        if dq > dr && dq > ds {
+            rq = -rr - rs;
+        } else if dr > ds {
+            rr = -rq - rs;
+        }
+        Axial::new(rq as isize, rr as isize)
+    }
+}
+
+impl Add for Axial {
+    type Output = Axial;
+
+    fn add(self, other: Axial) -> Axial {
+        Axial::new(self.q + other.q, self.r + other.r)
+    }
+}
+
+/// The grid position one step from `(x, y)` in `direction`, wrapping around the edges.
+pub fn neighbor(
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+    direction: Direction,
+) -> (usize, usize) {
+    Offset::new(x as isize, y as isize)
+        .neighbor(direction)
+        .wrap(width, height)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::Direction::*;
+
+    #[test]
+    fn even_row_neighbors() {
+        let expected = [
+            (UpRight, (4, 1)),
+            (UpLeft, (3, 1)),
+            (Left, (2, 2)),
+            (DownLeft, (3, 3)),
+            (DownRight, (4, 3)),
+            (Right, (4, 2)),
+        ];
+        for &(direction, n) in &expected {
+            assert_eq!(neighbor(3, 2, 10, 10, direction), n, "{:?}", direction);
+        }
+    }
+
+    #[test]
+    fn odd_row_neighbors() {
+        let expected = [
+            (UpRight, (3, 2)),
+            (UpLeft, (2, 2)),
+            (Left, (2, 3)),
+            (DownLeft, (2, 4)),
+            (DownRight, (3, 4)),
+            (Right, (4, 3)),
+        ];
+        for &(direction, n) in &expected {
+            assert_eq!(neighbor(3, 3, 10, 10, direction), n, "{:?}", direction);
+        }
+    }
+
+    #[test]
+    fn neighbors_wrap() {
+        // Even row in the top right corner.
+        let expected = [(0, 9), (9, 9), (8, 0), (9, 1), (0, 1), (0, 0)];
+        for (&direction, &n) in DIRECTIONS.iter().zip(&expected) {
+            assert_eq!(neighbor(9, 0, 10, 10, direction), n, "{:?}", direction);
+        }
+        // Odd row in the bottom left corner.
+        let expected = [(0, 8), (9, 8), (9, 9), (9, 0), (0, 0), (1, 9)];
+        for (&direction, &n) in DIRECTIONS.iter().zip(&expected) {
+            assert_eq!(neighbor(0, 9, 10, 10, direction), n, "{:?}", direction);
+        }
+    }
+
+    #[test]
+    fn within_bounds() {
+        assert_eq!(Offset::new(9, 0).neighbor(UpRight).within(10, 10), None);
+        assert_eq!(Offset::new(9, 0).neighbor(Right).within(10, 10), None);
+        assert_eq!(Offset::new(9, 0).neighbor(DownLeft).within(10, 10), Some((9, 1)));
+        assert_eq!(Offset::new(0, 9).neighbor(UpLeft).within(10, 10), None);
+        assert_eq!(Offset::new(0, 9).neighbor(UpRight).within(10, 10), Some((0, 8)));
+    }
+
+    #[test]
+    fn flipping_steps_back() {
+        for y in 0..4 {
+            for &direction in &DIRECTIONS {
+                let (nx, ny) = neighbor(5, y, 10, 4, direction);
+                assert_eq!(neighbor(nx, ny, 10, 4, direction.flip()), (5, y));
+            }
+        }
+    }
+
+    #[test]
+    fn offset_axial_round_trip() {
+        for y in -5..5 {
+            for x in -5..5 {
+                let o = Offset::new(x, y);
+                assert_eq!(o.axial().offset(), o);
+            }
+        }
+    }
+
+    #[test]
+    fn pixels_map_back_to_their_hex() {
+        for y in -3..4 {
+            for x in -3..4 {
+                let o = Offset::new(x, y);
+                let (px, py) = o.pixel();
+                assert_eq!(Axial::from_pixel(px, py).offset(), o);
+                // Anywhere inside the inscribed circle belongs to the same hex.
+                for &(dx, dy) in &[(0.95, 0.0), (-0.95, 0.0), (0.0, 0.95), (0.6, -0.7)] {
+                    assert_eq!(Axial::from_pixel(px + dx, py + dy).offset(), o);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn pixels_match_renderer_layout() {
+        assert_eq!(Offset::new(0, 0).pixel(), (1.5, SQRT_3 * 0.5));
+        assert_eq!(Offset::new(0, 1).pixel(), (0.5, SQRT_3 * 1.5));
+        assert_eq!(Offset::new(2, 2).pixel(), (5.5, SQRT_3 * 2.5));
+    }
+}
@@ -0,0 +1,148 @@
+//! Accounting for the energy that enters and leaves a grid.
+//!
+//! Energy is counted in food. Food fluid counts as it is and each unit of inhale is worth
+//! `consumption` food, since that is what a cell eats to gain it. Eating moves energy from food
+//! into inhale without changing the total, so everything else that changes the total goes through
+//! the `Ledger`.
+
+use std::fmt;
+
+/// Energy that entered and left a grid, by where it came from and went.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Ledger {
+    /// Food made from chemical B, which is negative where B is.
+    pub produced: f64,
+    /// Food added by hand, less food cleared by hand.
+    pub fed: f64,
+    /// Inhale given to cells spawned at random or planted from genomes.
+    pub spawned: f64,
+    /// Food released by cells that died.
+    pub released: f64,
+    /// Movement and divide costs returned to food, see `Grid::recycle_costs`.
+    pub recycled: f64,
+    /// Food eaten by cells that couldn't store any more inhale.
+    pub wasted: f64,
+    /// Inhale used up by cells that had too little food to eat.
+    pub burned: f64,
+    /// Inhale paid for moving.
+    pub moved: f64,
+    /// Inhale paid for dividing and mating, along with the unit lost when a parent with an odd
+    /// inhale gives half to its child.
+    pub births: f64,
+    /// Inhale lost to conflict penalties and auction prices.
+    pub conflicts: f64,
+    /// Inhale of cells that died or left the world.
+    pub died: f64,
+    /// Food that flowed over an absorbing edge.
+    pub lost: f64,
+}
+
+impl Ledger {
+    /// Energy that entered less energy that left.
+    pub fn net(&self) -> f64 {
+        self.produced + self.fed + self.spawned + self.released + self.recycled -
+            (self.wasted + self.burned + self.moved + self.births + self.conflicts + self.died +
+                 self.lost)
+    }
+
+    /// Energy that entered and left, without cancelling out.
+    fn gross(&self) -> f64 {
+        [
+            self.produced,
+            self.fed,
+            self.spawned,
+            self.released,
+            self.recycled,
+            self.wasted,
+            self.burned,
+            self.moved,
+            self.births,
+            self.conflicts,
+            self.died,
+            self.lost,
+        ].iter()
+            .map(|f| f.abs())
+            .sum()
+    }
+
+    /// What was accounted for after `earlier`, a copy of this ledger taken before.
+    pub fn since(&self, earlier: &Ledger) -> Ledger {
+        Ledger {
+            produced: self.produced - earlier.produced,
+            fed: self.fed - earlier.fed,
+            spawned: self.spawned - earlier.spawned,
+            released: self.released - earlier.released,
+            recycled: self.recycled - earlier.recycled,
+            wasted: self.wasted - earlier.wasted,
+            burned: self.burned - earlier.burned,
+            moved: self.moved - earlier.moved,
+            births: self.births - earlier.births,
+            conflicts: self.conflicts - earlier.conflicts,
+            died: self.died - earlier.died,
+            lost: self.lost - earlier.lost,
+        }
+    }
+}
+
+/// A cycle that changed the energy on the grid by something other than what the ledger accounts
+/// for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Imbalance {
+    pub cycle: u64,
+    /// The energy the ledger says the grid should have ended the cycle with.
+    pub expected: f64,
+    pub actual: f64,
+}
+
+impl fmt::Display for Imbalance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "cycle {} ended with {} energy but the ledger accounts for {}",
+            self.cycle,
+            self.actual,
+            self.expected
+        )
+    }
+}
+
+/// Checks that every cycle changes the energy on the grid by exactly what it adds to the ledger.
+#[derive(Debug, Clone, Default)]
+pub struct Audit {
+    pub enabled: bool,
+    /// The first imbalance found. Nothing more is checked until it is taken out.
+    pub imbalance: Option<Imbalance>,
+    /// The energy and ledger at the start of the cycle being checked.
+    start: Option<(f64, Ledger)>,
+}
+
+impl Audit {
+    /// Whether the cycle about to run should be checked.
+    pub fn due(&self) -> bool {
+        self.enabled && self.imbalance.is_none()
+    }
+
+    pub fn begin(&mut self, energy: f64, ledger: &Ledger) {
+        self.start = Some((energy, *ledger));
+    }
+
+    /// Compares the energy at the end of `cycle` with what the ledger accounts for.
+    pub fn end(&mut self, cycle: u64, energy: f64, ledger: &Ledger) {
+        let (start, before) = match self.start.take() {
+            Some(start) => start,
+            None => return,
+        };
+        let flows = ledger.since(&before);
+        let expected = start + flows.net();
+        // Sums of floats over the whole grid are only so exact.
+        let tolerance = 1e-9 * (start.abs() + energy.abs() + flows.gross()) + 1e-9;
+        let off = (energy - expected).abs();
+        if off > tolerance || off.is_nan() {
+            self.imbalance = Some(Imbalance {
+                cycle: cycle,
+                expected: expected,
+                actual: energy,
+            });
+        }
+    }
+}
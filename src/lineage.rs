@@ -0,0 +1,521 @@
+//! The family tree of every cell a grid has had.
+//!
+//! Births and deaths are appended to a log file as bincode encoded `Record`s while the grid runs.
+//! Records are in cycle order, so when a run is resumed from an earlier snapshot everything the
+//! log holds from that cycle on is cut off before appending again. The log can be turned into a
+//! tree of the surviving population's ancestry with `newick` or `graphml`.
+
+use bincode;
+use cell::{Lineage, Parents};
+use grid::Grid;
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Record {
+    Birth(Lineage),
+    Death { id: u64, cycle: u64 },
+}
+
+impl Record {
+    fn cycle(&self) -> u64 {
+        match *self {
+            Record::Birth(ref l) => l.birth,
+            Record::Death { cycle, .. } => cycle,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Bincode(bincode::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => write!(f, "{}", e),
+            Error::Bincode(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<bincode::Error> for Error {
+    fn from(e: bincode::Error) -> Self {
+        Error::Bincode(e)
+    }
+}
+
+/// Reads every complete record before `cycle`, along with the length of the file they take up.
+///
+/// A record cut short by a crash ends the log just like one from a later cycle does.
+fn read_until<R: Read>(r: R, cycle: u64) -> (Vec<Record>, u64) {
+    let mut r = BufReader::new(r);
+    let mut records = Vec::new();
+    let mut len = 0;
+    loop {
+        let record: Record = match bincode::deserialize_from(&mut r, bincode::Infinite) {
+            Ok(record) => record,
+            Err(_) => break,
+        };
+        if record.cycle() >= cycle {
+            break;
+        }
+        len += bincode::serialized_size(&record);
+        records.push(record);
+    }
+    (records, len)
+}
+
+/// Reads a whole log.
+pub fn read<P: AsRef<Path>>(path: P) -> Result<Vec<Record>, Error> {
+    Ok(read_until(File::open(path)?, u64::MAX).0)
+}
+
+/// Appends the births and deaths of a running grid to a log.
+pub struct Log {
+    file: BufWriter<File>,
+    /// Ids of the cells alive when the log was last updated.
+    alive: HashSet<u64>,
+}
+
+impl Log {
+    /// Opens the log of the run that led to `grid`, dropping anything it has from later cycles
+    /// and recording the births of any living cells it doesn't know about yet.
+    pub fn open<P: AsRef<Path>>(path: P, grid: &Grid) -> Result<Log, Error> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let (records, len) = read_until(&mut file, grid.cycle);
+        file.set_len(len)?;
+        file.seek(SeekFrom::End(0))?;
+
+        let born: HashSet<u64> = records
+            .iter()
+            .filter_map(|r| match *r {
+                Record::Birth(ref l) => Some(l.id),
+                _ => None,
+            })
+            .collect();
+        let mut log = Log {
+            file: BufWriter::new(file),
+            alive: HashSet::new(),
+        };
+        for cell in grid.tiles.iter().filter_map(|t| t.cell.as_ref()) {
+            if !born.contains(&cell.lineage.id) {
+                log.append(&Record::Birth(cell.lineage))?;
+            }
+            log.alive.insert(cell.lineage.id);
+        }
+        Ok(log)
+    }
+
+    fn append(&mut self, record: &Record) -> Result<(), Error> {
+        bincode::serialize_into(&mut self.file, record, bincode::Infinite)?;
+        Ok(())
+    }
+
+    /// Records every cell born or died since the last update. Call this after every cycle.
+    pub fn update(&mut self, grid: &Grid) -> Result<(), Error> {
+        let mut alive = HashSet::with_capacity(self.alive.len());
+        for cell in grid.tiles.iter().filter_map(|t| t.cell.as_ref()) {
+            if !self.alive.contains(&cell.lineage.id) {
+                self.append(&Record::Birth(cell.lineage))?;
+            }
+            alive.insert(cell.lineage.id);
+        }
+        let mut died: Vec<u64> = self.alive.difference(&alive).cloned().collect();
+        died.sort();
+        // Deaths are noticed after the cycle they happened in.
+        let cycle = grid.cycle.saturating_sub(1);
+        for id in died {
+            self.append(&Record::Death {
+                id: id,
+                cycle: cycle,
+            })?;
+        }
+        self.alive = alive;
+        Ok(())
+    }
+
+    /// Writes out any buffered records. A snapshot should only be saved after its log is flushed.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+struct Node {
+    lineage: Lineage,
+    death: Option<u64>,
+    /// Children that lead to a survivor.
+    children: Vec<u64>,
+}
+
+/// The ancestry of the cells alive at the end of a log.
+pub struct Tree {
+    nodes: HashMap<u64, Node>,
+    /// Ancestors whose own parents aren't in the tree, in order of id.
+    roots: Vec<u64>,
+}
+
+impl Tree {
+    pub fn new(records: &[Record]) -> Tree {
+        let mut all = HashMap::new();
+        let mut deaths = HashMap::new();
+        for record in records {
+            match *record {
+                Record::Birth(l) => {
+                    all.insert(l.id, l);
+                }
+                Record::Death { id, cycle } => {
+                    deaths.insert(id, cycle);
+                }
+            }
+        }
+
+        // Walk up from every survivor, keeping each ancestor once.
+        let mut nodes: HashMap<u64, Node> = HashMap::new();
+        let mut stack: Vec<u64> = all.keys()
+            .filter(|id| !deaths.contains_key(id))
+            .cloned()
+            .collect();
+        while let Some(id) = stack.pop() {
+            if nodes.contains_key(&id) {
+                continue;
+            }
+            let lineage = all[&id];
+            nodes.insert(
+                id,
+                Node {
+                    lineage: lineage,
+                    death: deaths.get(&id).cloned(),
+                    children: Vec::new(),
+                },
+            );
+            stack.extend(parents(lineage.parents).into_iter().filter(|p| all.contains_key(p)));
+        }
+
+        let mut ids: Vec<u64> = nodes.keys().cloned().collect();
+        ids.sort();
+        let mut roots = Vec::new();
+        for &id in &ids {
+            let parent = parents(nodes[&id].lineage.parents).into_iter().find(|p| {
+                nodes.contains_key(p)
+            });
+            match parent {
+                Some(p) => nodes.get_mut(&p).unwrap().children.push(id),
+                None => roots.push(id),
+            }
+        }
+        Tree {
+            nodes: nodes,
+            roots: roots,
+        }
+    }
+
+    /// Writes the tree in Newick format, one line per root.
+    ///
+    /// Newick can only give each cell one parent, so children of mating are placed under the
+    /// parent that spawned them. Branch lengths are in cycles.
+    pub fn newick<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        for &root in &self.roots {
+            // Walks the tree without recursing, since lineages can be very deep.
+            enum Step {
+                Enter(u64),
+                Next,
+                Leave(u64),
+            }
+            let mut stack = vec![Step::Enter(root)];
+            while let Some(step) = stack.pop() {
+                match step {
+                    Step::Enter(id) => {
+                        let children = &self.nodes[&id].children;
+                        if children.is_empty() {
+                            self.newick_label(w, id)?;
+                        } else {
+                            write!(w, "(")?;
+                            stack.push(Step::Leave(id));
+                            for (i, &child) in children.iter().enumerate().rev() {
+                                stack.push(Step::Enter(child));
+                                if i != 0 {
+                                    stack.push(Step::Next);
+                                }
+                            }
+                        }
+                    }
+                    Step::Next => write!(w, ",")?,
+                    Step::Leave(id) => {
+                        write!(w, ")")?;
+                        self.newick_label(w, id)?;
+                    }
+                }
+            }
+            writeln!(w, ";")?;
+        }
+        Ok(())
+    }
+
+    fn newick_label<W: Write>(&self, w: &mut W, id: u64) -> io::Result<()> {
+        let node = &self.nodes[&id];
+        write!(w, "c{}", id)?;
+        let parent = parents(node.lineage.parents).into_iter().find(|p| {
+            self.nodes.contains_key(p)
+        });
+        if let Some(p) = parent {
+            write!(
+                w,
+                ":{}",
+                node.lineage.birth.saturating_sub(self.nodes[&p].lineage.birth)
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Writes the tree as a GraphML graph with an edge from every parent to its children, so
+    /// children of mating have two incoming edges.
+    pub fn graphml<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        writeln!(w, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            w,
+            r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+        )?;
+        writeln!(
+            w,
+            r#"  <key id="birth" for="node" attr.name="birth" attr.type="long"/>"#
+        )?;
+        writeln!(
+            w,
+            r#"  <key id="death" for="node" attr.name="death" attr.type="long"/>"#
+        )?;
+        writeln!(
+            w,
+            r#"  <key id="kind" for="edge" attr.name="kind" attr.type="string"/>"#
+        )?;
+        writeln!(w, r#"  <graph id="lineage" edgedefault="directed">"#)?;
+        let mut ids: Vec<u64> = self.nodes.keys().cloned().collect();
+        ids.sort();
+        for &id in &ids {
+            let node = &self.nodes[&id];
+            writeln!(w, r#"    <node id="c{}">"#, id)?;
+            writeln!(
+                w,
+                r#"      <data key="birth">{}</data>"#,
+                node.lineage.birth
+            )?;
+            if let Some(death) = node.death {
+                writeln!(w, r#"      <data key="death">{}</data>"#, death)?;
+            }
+            writeln!(w, "    </node>")?;
+        }
+        for &id in &ids {
+            let node = &self.nodes[&id];
+            let kind = match node.lineage.parents {
+                Parents::Mated(..) => "mate",
+                _ => "divide",
+            };
+            for p in parents(node.lineage.parents) {
+                if self.nodes.contains_key(&p) {
+                    writeln!(
+                        w,
+                        r#"    <edge source="c{}" target="c{}"><data key="kind">{}</data></edge>"#,
+                        p,
+                        id,
+                        kind
+                    )?;
+                }
+            }
+        }
+        writeln!(w, "  </graph>")?;
+        writeln!(w, "</graphml>")
+    }
+}
+
+fn parents(parents: Parents) -> Vec<u64> {
+    match parents {
+        Parents::None => vec![],
+        Parents::Divided(p) => vec![p],
+        Parents::Mated(a, b) if a == b => vec![a],
+        Parents::Mated(a, b) => vec![a, b],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cell::brain::InstructionSet;
+    use cell::Cell;
+    use fluid::FluidParams;
+    use grid::Boundary;
+    use rand::{Isaac64Rng, SeedableRng};
+    use std::env;
+    use std::process;
+
+    fn lineage(id: u64, parents: Parents, birth: u64) -> Lineage {
+        Lineage {
+            id: id,
+            parents: parents,
+            birth: birth,
+        }
+    }
+
+    fn death(id: u64, cycle: u64) -> Record {
+        Record::Death {
+            id: id,
+            cycle: cycle,
+        }
+    }
+
+    /// A grid at `cycle` with a cell of each lineage on the first tiles.
+    fn grid(cycle: u64, cells: &[Lineage]) -> Grid {
+        let mut rng = Isaac64Rng::from_seed(&[3]);
+        let mut g = Grid::new(
+            3,
+            3,
+            0.04,
+            0.0,
+            500,
+            10000,
+            0,
+            5,
+            2100,
+            0.5,
+            0.5,
+            Boundary::Wall,
+            FluidParams::default(),
+            InstructionSet::default(),
+            &mut rng,
+        );
+        g.cycle = cycle;
+        for (tile, &l) in g.tiles.iter_mut().zip(cells) {
+            let mut cell = Cell::new(l.id, l.birth, &InstructionSet::default(), &mut rng);
+            cell.lineage = l;
+            tile.cell = Some(Box::new(cell));
+        }
+        g
+    }
+
+    /// Cell 0 divides into 1 and 2, which mate into 3, and 1 divides into 4, which dies out. 5
+    /// was spawned at random and outlives everyone.
+    fn records() -> Vec<Record> {
+        vec![
+            Record::Birth(lineage(0, Parents::None, 0)),
+            Record::Birth(lineage(5, Parents::None, 0)),
+            Record::Birth(lineage(1, Parents::Divided(0), 2)),
+            Record::Birth(lineage(2, Parents::Divided(0), 3)),
+            death(0, 4),
+            Record::Birth(lineage(4, Parents::Divided(1), 4)),
+            Record::Birth(lineage(3, Parents::Mated(1, 2), 5)),
+            death(4, 5),
+            death(2, 6),
+        ]
+    }
+
+    #[test]
+    fn newick() {
+        let mut out = Vec::new();
+        Tree::new(&records()).newick(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "((c3:3)c1:2,c2:3)c0;\nc5;\n"
+        );
+    }
+
+    #[test]
+    fn graphml() {
+        let mut out = Vec::new();
+        Tree::new(&records()).graphml(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let nodes: Vec<&str> = out.lines().filter(|l| l.contains("<node ")).collect();
+        assert_eq!(
+            nodes,
+            [
+                r#"    <node id="c0">"#,
+                r#"    <node id="c1">"#,
+                r#"    <node id="c2">"#,
+                r#"    <node id="c3">"#,
+                r#"    <node id="c5">"#,
+            ]
+        );
+        assert!(out.contains(
+            r#"      <data key="birth">3</data>
+      <data key="death">6</data>"#,
+        ));
+        let edges: Vec<&str> = out.lines().filter(|l| l.contains("<edge ")).collect();
+        let edge = |source, target, kind| {
+            format!(
+                r#"    <edge source="c{}" target="c{}"><data key="kind">{}</data></edge>"#,
+                source,
+                target,
+                kind
+            )
+        };
+        assert_eq!(
+            edges,
+            [
+                edge(0, 1, "divide"),
+                edge(0, 2, "divide"),
+                edge(1, 3, "mate"),
+                edge(2, 3, "mate"),
+            ]
+        );
+    }
+
+    #[test]
+    fn log_resumes_after_truncation() {
+        let path = env::temp_dir().join(format!("evomata-lineage-{}.log", process::id()));
+        let _ = fs::remove_file(&path);
+        let first = [lineage(0, Parents::None, 0), lineage(1, Parents::None, 0)];
+        let child = lineage(2, Parents::Divided(0), 2);
+
+        let mut log = Log::open(&path, &grid(0, &first)).unwrap();
+        log.update(&grid(3, &[first[0], child])).unwrap();
+        log.flush().unwrap();
+        drop(log);
+        let logged = vec![
+            Record::Birth(first[0]),
+            Record::Birth(first[1]),
+            Record::Birth(child),
+            death(1, 2),
+        ];
+        assert_eq!(read(&path).unwrap(), logged);
+
+        // A record cut short by a crash.
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(&[1]).unwrap();
+        assert_eq!(read(&path).unwrap(), logged);
+
+        // Resuming from a snapshot of cycle 2 drops what happened since and records a cell that
+        // the log didn't know about.
+        let stranger = lineage(7, Parents::None, 1);
+        let mut log = Log::open(&path, &grid(2, &[first[0], first[1], stranger])).unwrap();
+        log.flush().unwrap();
+        drop(log);
+        let resumed = vec![
+            Record::Birth(first[0]),
+            Record::Birth(first[1]),
+            Record::Birth(stranger),
+        ];
+        let len: u64 = resumed.iter().map(bincode::serialized_size).sum();
+        assert_eq!(fs::metadata(&path).unwrap().len(), len);
+        assert_eq!(read(&path).unwrap(), resumed);
+        fs::remove_file(&path).unwrap();
+    }
+}
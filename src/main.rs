@@ -1,3 +1,11 @@
+// The code keeps to the idioms of the compilers it was first written for, and some of the suggested
+// replacements need a much newer one.
+#![allow(unknown_lints)]
+#![allow(clippy::redundant_field_names, clippy::too_many_arguments, clippy::needless_range_loop)]
+#![allow(clippy::identity_op, clippy::excessive_precision, clippy::derivable_impls)]
+#![allow(clippy::unnecessary_map_or, clippy::manual_is_multiple_of, clippy::collapsible_match)]
+#![allow(clippy::while_let_loop)]
+
 #[macro_use]
 extern crate serde_derive;
 extern crate bincode;
@@ -11,600 +19,663 @@ extern crate glium;
 extern crate nalgebra;
 extern crate noise;
 extern crate mli;
+#[cfg(test)]
 extern crate mli_mep;
-extern crate num_cpus;
-extern crate crossbeam;
-
+extern crate clap;
+extern crate toml;
+extern crate serde_json;
+extern crate flate2;
+extern crate zstd;
+extern crate rayon;
+extern crate png;
+
+mod adhesion;
 mod cell;
+mod config;
+mod conflict;
+mod events;
 mod fluid;
+mod genome;
 mod grid;
+mod gui;
+mod headless;
+mod hex;
+mod ledger;
+mod lineage;
+mod recording;
+mod save;
+mod stats;
+mod terrain;
+
+use clap::{App, Arg, ArgGroup, ArgMatches, SubCommand};
+use save::snapshot::Snapshots;
 
-use gg::render2::*;
-use nalgebra as na;
-use nalgebra::ToHomogeneous;
-use num::One;
-
-use std::time;
 use std::fs::File;
-use std::sync::mpsc::channel;
-
-use rand::{Isaac64Rng, SeedableRng};
-
-const DEFAULT_SCREEN_ZOOM_RATIO: f32 = 1.0;
-
-const GRID_WIDTH: usize = 192 * 5 / 2;
-const GRID_HEIGHT: usize = 125 * 5 / 2;
-const DEFAULT_CONSUMPTION: f64 = 0.04;
-const SPAWN_DENSITY: f64 = 0.000005;
-const DEFAULT_SPAWN_RATE: f64 = SPAWN_DENSITY * GRID_WIDTH as f64 * GRID_HEIGHT as f64;
-const DEFAULT_INHALE_MINIMUM: usize = 500;
-const DEFAULT_INHALE_CAP: usize = 10000;
-const DEFAULT_MOVEMENT_COST: usize = 0;
-const DEFAULT_DIVIDE_COST: usize = 5;
-
-const DEFAULT_EXPLODE_REQUIREMENT: usize = 2100;
-const DEFAULT_EXPLODE_AMOUNT: f64 = 0.5;
-
-const DEFAULT_DEATH_RELEASE_COEFFICIENT: f64 = 0.5;
-
-// TODO: Figure out when lines are used and set it correctly.
-const SCROLL_LINES_RATIO: f32 = 0.707;
-const SCROLL_PIXELS_RATIO: f32 = 0.707;
-
-const GRID_SPAWN_MULTIPLY: f64 = 1.25;
-const GRID_EXPLODE_MULTIPLY: f64 = 1.25;
-const GRID_RELEASE_MULTIPLY: f64 = 1.25992104989;
-
-const SECONDS_BETWEEN_AUTOSAVES: u64 = 60 * 30;
-
-const MANUAL_FEED_AMOUNT: f64 = 500000.0;
-const MANUAL_KILL_AMOUNT: f64 = 500000.0;
-
-// Ratio of width/height in a 2d circle tight-pack or a hex grid.
-const WIDTH_HEIGHT_RATIO: f32 = 0.86602540378;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
+
+const DEFAULT_PROGRESS_INTERVAL: u64 = 1000;
+/// Where the grid was saved before snapshots were introduced.
+const LEGACY_STATE_PATH: &str = "gridstate";
+
+/// The command line interface.
+fn app() -> App<'static, 'static> {
+    App::new("evomata11")
+        .about("Evolves cells in a gray-scott reaction diffusion world.")
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .value_name("FILE")
+                .help("TOML or JSON file with run parameters")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("snapshots")
+                .long("snapshots")
+                .value_name("DIR")
+                .help("Directory to save snapshots to and load them from [default: snapshots]")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("keep")
+                .long("keep")
+                .value_name("N")
+                .help("Number of most recent snapshots to always keep [default: 10]")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("keep-daily")
+                .long("keep-daily")
+                .value_name("DAYS")
+                .help("Also keep the newest snapshot of each of this many days [default: 30]")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("compression")
+                .long("compression")
+                .value_name("METHOD")
+                .possible_values(&["none", "gzip", "zstd"])
+                .help("Compression of new snapshots [default: none]")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("load")
+                .long("load")
+                .value_name("SNAPSHOT")
+                .help("Snapshot to start from, by cycle number, name or path [default: latest]")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed")
+                .value_name("N,N,...")
+                .help("Comma separated seed for the random number generator")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("no-lineage")
+                .long("no-lineage")
+                .help("Don't log births and deaths to lineage.log in the snapshot directory")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("stats")
+                .long("stats")
+                .value_name("FILE")
+                .help("Record population statistics to a .csv or .jsonl file")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("stats-every")
+                .long("stats-every")
+                .value_name("N")
+                .help("Cycles between stats samples [default: 100]")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("events")
+                .long("events")
+                .value_name("FILE")
+                .help("Log cell events such as deaths to a .jsonl or binary file")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("threads")
+                .long("threads")
+                .value_name("N")
+                .help("Number of threads to run cycles on [default: one per CPU]")
+                .global(true),
+        )
+        .args(&grid_args())
+        .arg(
+            Arg::with_name("boundary")
+                .long("boundary")
+                .value_name("KIND")
+                .possible_values(&["toroidal", "wall", "absorbing", "reflecting"])
+                .help("What lies past the edges of a new grid [default: toroidal]")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("conflicts")
+                .long("conflicts")
+                .value_name("POLICY")
+                .possible_values(&["reject", "random", "inhale", "auction"])
+                .help("How cells trying to enter the same hex are settled [default: reject]")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("conflict-penalty")
+                .long("conflict-penalty")
+                .value_name("INHALE")
+                .help("Inhale taken from cells that lose a contested hex [default: 0]")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("mating")
+                .long("mating")
+                .value_name("MODE")
+                .possible_values(&["free", "consent"])
+                .help("Whether mates in a new grid have to consent [default: free]")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("adhesion")
+                .long("adhesion")
+                .help("Let cells in a new grid bond to their neighbors and move in groups")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("quorum")
+                .long("quorum")
+                .value_name("FRACTION")
+                .help("Share of a bonded group that has to agree for it to move [default: 0.5]")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("recycle-costs")
+                .long("recycle-costs")
+                .help("Return movement and divide costs to the food where they are paid")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("terrain")
+                .long("terrain")
+                .value_name("PNG")
+                .help("Mask of rock, membrane and soil for a new grid")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("genome")
+                .long("genome")
+                .value_name("FILE")
+                .help("Genome file to seed a new grid with, may be given more than once")
+                .multiple(true)
+                .number_of_values(1)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("check-fluids")
+                .long("check-fluids")
+                .help("Report the first tile whose fluids become NaN, infinite or negative")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("check-energy")
+                .long("check-energy")
+                .help("Report the first cycle whose energy doesn't match the energy ledger")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("instructions")
+                .long("instructions")
+                .value_name("INS[=WEIGHT],...")
+                .help("Instructions new brains are built from, such as add,sub,pdiv=2")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("genome-copies")
+                .long("genome-copies")
+                .value_name("N")
+                .help("Cells to place for each genome [default: 10]")
+                .global(true),
+        )
+        .subcommand(
+            SubCommand::with_name("headless")
+                .about("Runs the simulation without opening a window")
+                .arg(
+                    Arg::with_name("cycles")
+                        .long("cycles")
+                        .value_name("N")
+                        .help("Stop after running this many cycles"),
+                )
+                .arg(
+                    Arg::with_name("seconds")
+                        .long("seconds")
+                        .value_name("SECONDS")
+                        .help("Stop after running for this many seconds of wall-clock time"),
+                )
+                .arg(
+                    Arg::with_name("save-every")
+                        .long("save-every")
+                        .value_name("N")
+                        .help("Save the grid state every N cycles"),
+                )
+                .arg(
+                    Arg::with_name("progress-every")
+                        .long("progress-every")
+                        .value_name("N")
+                        .help("Print progress every N cycles")
+                        .default_value("1000"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("info")
+                .about("Prints the format version and parameters of a snapshot")
+                .arg(Arg::with_name("SNAPSHOT").help(
+                    "Cycle number, name or path of the snapshot [default: latest]",
+                )),
+        )
+        .subcommand(SubCommand::with_name("snapshots").about(
+            "Lists the snapshots in the snapshot directory",
+        ))
+        .subcommand(
+            SubCommand::with_name("lineage")
+                .about("Exports the ancestry of the surviving cells from the lineage log")
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .possible_values(&["newick", "graphml"])
+                        .default_value("newick")
+                        .help("Format of the tree"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .value_name("FILE")
+                        .help("File to write the tree to [default: standard output]"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("genomes")
+                .about("Exports the brains of cells in a snapshot to genome files")
+                .arg(
+                    Arg::with_name("top")
+                        .long("top")
+                        .value_name("N")
+                        .help("Export the best N cells"),
+                )
+                .arg(
+                    Arg::with_name("by")
+                        .long("by")
+                        .value_name("RANK")
+                        .possible_values(&["age", "inhale"])
+                        .default_value("inhale")
+                        .help("What makes a cell best for --top"),
+                )
+                .arg(
+                    Arg::with_name("cell")
+                        .long("cell")
+                        .value_name("X,Y")
+                        .help("Export the cell on this tile"),
+                )
+                .arg(
+                    Arg::with_name("id")
+                        .long("id")
+                        .value_name("ID")
+                        .help("Export the cell with this id"),
+                )
+                .group(ArgGroup::with_name("select").args(&["top", "cell", "id"]).required(true))
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .value_name("DIR")
+                        .help("Directory to write the genomes to [default: genomes]"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("disassemble")
+                .about("Prints what the brain in a genome file computes")
+                .arg(Arg::with_name("GENOME").required(true).help("Genome file to read"))
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .value_name("FILE")
+                        .help("File to write the listing to [default: standard output]"),
+                ),
+        )
+}
 
 fn main() {
-    use glium::DisplayBuild;
-    let mut rng = Isaac64Rng::from_seed(&[2, 5, 3, 12454]);
-    let mut g = match File::open("gridstate") {
-        Ok(mut f) => {
-            match bincode::deserialize_from(&mut f, bincode::Infinite) {
-                Ok(t) => {
-                    println!("Found grid file \"gridstate\" and loaded grid.");
-                    t
+    let matches = app().get_matches();
+
+    let config = config::Config::from_matches(&matches).unwrap_or_else(|e| {
+        println!("Invalid configuration: {}", e);
+        process::exit(1);
+    });
+    let snapshots = config.snapshots();
+
+    match matches.subcommand() {
+        ("info", Some(sub)) => {
+            info(&snapshots, sub.value_of("SNAPSHOT"));
+            return;
+        }
+        ("snapshots", Some(_)) => {
+            list_snapshots(&snapshots);
+            return;
+        }
+        ("lineage", Some(sub)) => {
+            export_lineage(&config.lineage_log(), sub);
+            return;
+        }
+        ("genomes", Some(sub)) => {
+            export_genomes(&snapshots, matches.value_of("load"), sub);
+            return;
+        }
+        ("disassemble", Some(sub)) => {
+            disassemble(sub);
+            return;
+        }
+        _ => {}
+    }
+
+    let path = resolve(&snapshots, matches.value_of("load")).unwrap_or_else(|e| {
+        println!("Unable to find a snapshot to load: {}", e);
+        process::exit(1);
+    });
+    // Grid parameters only apply to new grids; a loaded grid keeps the parameters it was saved
+    // with.
+    let g = match path {
+        Some(path) => {
+            match save::load_versioned(&path) {
+                Ok((g, version)) => {
+                    println!(
+                        "Loaded grid at cycle {} from \"{}\".",
+                        g.cycle,
+                        path.display()
+                    );
+                    if version < save::VERSION {
+                        println!(
+                            "Migrated grid from format version {}; the original file is unchanged.",
+                            version
+                        );
+                    }
+                    let ignored = config.ignored_by(&g);
+                    if !ignored.is_empty() {
+                        println!(
+                            "Ignoring {} for the loaded grid, which keeps the settings it was \
+                             saved with.",
+                            ignored.join(", ")
+                        );
+                    }
+                    g
                 }
                 Err(e) => {
+                    // Don't quietly start over when the user expected to continue a run.
+                    println!("Failed to load grid from \"{}\": {}", path.display(), e);
                     println!(
-                        "Found grid file \"gridstate\" but failed to load grid: {}",
-                        e
+                        "Choose another snapshot with --load or another --snapshots directory."
                     );
-                    grid::Grid::new(
-                        GRID_WIDTH,
-                        GRID_HEIGHT,
-                        DEFAULT_CONSUMPTION,
-                        DEFAULT_SPAWN_RATE,
-                        DEFAULT_INHALE_MINIMUM,
-                        DEFAULT_INHALE_CAP,
-                        DEFAULT_MOVEMENT_COST,
-                        DEFAULT_DIVIDE_COST,
-                        DEFAULT_EXPLODE_REQUIREMENT,
-                        DEFAULT_DEATH_RELEASE_COEFFICIENT,
-                        DEFAULT_EXPLODE_AMOUNT,
-                        &mut rng,
-                    )
+                    process::exit(1);
                 }
             }
         }
-        Err(_) => {
-            grid::Grid::new(
-                GRID_WIDTH,
-                GRID_HEIGHT,
-                DEFAULT_CONSUMPTION,
-                DEFAULT_SPAWN_RATE,
-                DEFAULT_INHALE_MINIMUM,
-                DEFAULT_INHALE_CAP,
-                DEFAULT_MOVEMENT_COST,
-                DEFAULT_DIVIDE_COST,
-                DEFAULT_EXPLODE_REQUIREMENT,
-                DEFAULT_DEATH_RELEASE_COEFFICIENT,
-                DEFAULT_EXPLODE_AMOUNT,
-                &mut rng,
-            )
+        None => {
+            let mut g = config.new_grid(&mut config.rng()).unwrap_or_else(|e| {
+                println!(
+                    "Unable to load terrain from \"{}\": {}",
+                    config.terrain.as_ref().unwrap(),
+                    e
+                );
+                process::exit(1);
+            });
+            for path in &config.genomes {
+                let genome = genome::Genome::load(path).unwrap_or_else(|e| {
+                    println!("Unable to load genome from \"{}\": {}", path, e);
+                    process::exit(1);
+                });
+                let placed = g.plant(&genome.brain, config.genome_copies);
+                println!("Placed {} cells from \"{}\".", placed, path);
+            }
+            g
         }
     };
-    let display = glium::glutin::WindowBuilder::new()
-        .with_vsync()
-        .build_glium()
-        .unwrap();
-    // window.set_cursor_state(glium::glutin::CursorState::Hide).ok().unwrap();
-    let glowy = Renderer::new(&display);
-
-    let mut screen_hex_ratio = DEFAULT_SCREEN_ZOOM_RATIO * g.height as f32 * WIDTH_HEIGHT_RATIO;
-
-    let mut center = (0.5 * g.width as f32, 0.5 * g.height as f32);
-    let mut last_mouse_pos = (0, 0);
-    let mut mouse_pressed = false;
-
-    let mut rendering_enabled = true;
-    let mut pure_color_mode = false;
-
-    let mut last_autosave = time::Instant::now();
-
-    loop {
-        use glium::Surface;
-
-        // // Get dimensions
-        let dims = display.get_framebuffer_dimensions();
-        // Multiply this by width coordinates to get normalized screen coordinates.
-        let hscale = dims.1 as f32 / dims.0 as f32;
-
-        // Don't even vsync if rendering is disabled.
-        let mut target = if rendering_enabled {
-            Some(display.draw())
-        } else {
-            None
-        };
-        target.as_mut().map_or_else(
-            || {},
-            |t| t.clear_color(0.0, 0.0, 0.0, 1.0),
-        );
-
-        let (screen_width, screen_height) = (screen_hex_ratio / hscale, screen_hex_ratio);
-        let (hex_per_width_pixel, hex_per_height_pixel) = (
-            screen_width / dims.0 as f32,
-            screen_height / WIDTH_HEIGHT_RATIO /
-                dims.1 as f32,
-        );
-
-        let center_mouse_coord = (dims.0 as f32 / 2.0, dims.1 as f32 / 2.0);
-
-        let projection = [
-            [1.0 / screen_width, 0.0, 0.0],
-            [0.0, 1.0 / screen_height, 0.0],
-            [0.0, 0.0, 1.0],
-        ];
-
-        if rendering_enabled {
+    // Zero lets rayon choose, which is one thread per CPU.
+    if let Err(e) = rayon::ThreadPoolBuilder::new()
+        .num_threads(config.threads)
+        .build_global()
+    {
+        println!("Failed to start worker threads: {}", e);
+        process::exit(1);
+    }
 
-            let (render_tx, render_rx) = channel();
+    let recording = config.recording();
+    match matches.subcommand() {
+        ("headless", Some(sub)) => {
+            headless::run(g, &headless_options(sub, snapshots, recording));
+        }
+        _ => gui::run(g, &snapshots, &recording),
+    }
+}
 
-            let numcpus = num_cpus::get();
+/// Finds the file to load from a `--load` style argument.
+///
+/// Without one this is the latest snapshot, falling back to a `gridstate` file from before
+/// snapshots existed. `None` means there is nothing to load and a new grid should be made.
+fn resolve(snapshots: &Snapshots, spec: Option<&str>) -> Result<Option<PathBuf>, save::Error> {
+    if let Some(spec) = spec {
+        return snapshots.find(spec).map(Some);
+    }
+    if let Some(latest) = snapshots.latest()? {
+        return Ok(Some(latest.path));
+    }
+    let legacy = Path::new(LEGACY_STATE_PATH);
+    Ok(if legacy.is_file() {
+        Some(legacy.to_path_buf())
+    } else {
+        None
+    })
+}
 
-            crossbeam::scope(|scope| {
-                use std::ops::Deref;
-                let g = &g;
-                // Render nodes
-                for i in 0..numcpus {
-                    let render_tx = render_tx.clone();
-                    scope.spawn(move || {
-                            let mut v = Vec::new();
-                            for x in 0..g.width {
-                                for y in (g.height * i / numcpus)..(g.height * (i + 1) / numcpus) {
-                                    append_circle(&mut v,
-                                                  0.6,
-                                                  0.6,
-                                                  if pure_color_mode {g.hex(x, y).pure_color()} else {g.hex(x, y).color()},
-                                                  &na::Isometry2::new(na::Vector2::new(if y % 2 == 0 {
-                                                                                           1.5
-                                                                                       } else {
-                                                                                           0.5
-                                                                                       } +
-                                                                                       2.0 *
-                                                                                       (x as f32 -
-                                                                                        center.0),
-                                                                                       WIDTH_HEIGHT_RATIO *
-                                                                                       (2.0 *
-                                                                                        (y as f32 -
-                                                                                         center.1 +
-                                                                                         0.5))),
-                                                                      na::Vector1::new(0.0))
-                                                      .to_homogeneous());
+/// Command line overrides for every `Grid` parameter in the config.
+fn grid_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    [
+        ("width", "N", "Width of a new grid in hexes"),
+        ("height", "N", "Height of a new grid in hexes"),
+        ("consumption", "FOOD", "Food a cell consumes each cycle"),
+        ("spawn-density", "RATE", "Expected random spawns per tile per cycle"),
+        ("inhale-minimum", "N", "Cells with less inhale than this die"),
+        ("inhale-cap", "N", "Maximum inhale a cell can store"),
+        ("movement-cost", "N", "Inhale a cell pays to move"),
+        ("divide-cost", "N", "Inhale a cell pays to divide or mate"),
+        ("explode-requirement", "N", "Inhale a cell needs to explode"),
+        ("explode-amount", "AMOUNT", "Kill fluid released by an explosion"),
+        ("death-release-coefficient", "RATIO", "Portion of inhale released as food on death"),
+    ].iter()
+        .map(|&(name, value, help)| {
+            Arg::with_name(name)
+                .long(name)
+                .value_name(value)
+                .help(help)
+                .global(true)
+        })
+        .collect()
+}
 
-                                    if g.hex(x, y).cell.is_some() {
-                                        append_circle(&mut v,
-                                                      0.3,
-                                                      0.3,
-                                                      g.hex(x, y).cell.as_ref().map(Deref::deref).map(cell::Cell::color).unwrap(),
-                                                      &na::Isometry2::new(na::Vector2::new(if y % 2 == 0 {
-                                                                                               1.5
-                                                                                           } else {
-                                                                                               0.5
-                                                                                           } +
-                                                                                           2.0 *
-                                                                                           (x as f32 -
-                                                                                            center.0),
-                                                                                           WIDTH_HEIGHT_RATIO *
-                                                                                           (2.0 *
-                                                                                            (y as f32 -
-                                                                                             center.1 +
-                                                                                             0.5))),
-                                                                          na::Vector1::new(0.0))
-                                                          .to_homogeneous());
-                                    }
-                                }
-                            }
-                            render_tx.send(v).unwrap_or_else(|_| panic!("Render channel closed."));
-                        });
-                }
+fn headless_options(
+    matches: &ArgMatches,
+    snapshots: Snapshots,
+    recording: recording::Options,
+) -> headless::Options {
+    let number = |name: &str| {
+        matches.value_of(name).map(|v| {
+            v.parse::<u64>().unwrap_or_else(|e| {
+                println!("Invalid value \"{}\" for --{}: {}", v, name, e);
+                process::exit(1);
+            })
+        })
+    };
+    headless::Options {
+        cycles: number("cycles"),
+        duration: number("seconds").map(Duration::from_secs),
+        save_every: number("save-every"),
+        progress_every: number("progress-every").unwrap_or(DEFAULT_PROGRESS_INTERVAL),
+        snapshots: snapshots,
+        recording: recording,
+    }
+}
 
-                for _ in 0..numcpus {
-                    glowy.render_qbeziers_flat(
-                        target.as_mut().unwrap(),
-                        na::Matrix3::one().as_ref().clone(),
-                        projection,
-                        &render_rx.recv().unwrap_or_else(|e| {
-                            panic!(
-                                "Error: Render threads unexpectedly closed: \
-                                                           {}",
-                                e
-                            )
-                        })
-                            [..],
-                    );
-                }
-            });
+fn info(snapshots: &Snapshots, spec: Option<&str>) {
+    let path = match resolve(snapshots, spec) {
+        Ok(Some(path)) => path,
+        Ok(None) => {
+            println!("There are no snapshots in \"{}\".", snapshots.dir.display());
+            process::exit(1);
         }
-
-        g.cycle(&mut rng);
-
-        // Don't even vsync if rendering is disabled.
-        if rendering_enabled {
-            target.unwrap().finish().unwrap();
+        Err(e) => {
+            println!("Unable to find snapshot: {}", e);
+            process::exit(1);
         }
+    };
+    match save::info(&path) {
+        Ok((version, params)) => {
+            println!("\"{}\": format version {}", path.display(), version);
+            println!("{:#?}", params);
+        }
+        Err(e) => {
+            println!("Unable to read \"{}\": {}", path.display(), e);
+            process::exit(1);
+        }
+    }
+}
 
-        let now = time::Instant::now();
-        if now - last_autosave > time::Duration::from_secs(SECONDS_BETWEEN_AUTOSAVES) {
-            last_autosave = now;
-
-            match File::create("gridstate") {
-                Ok(mut f) => {
-                    match bincode::serialize_into(&mut f, &g, bincode::Infinite) {
-                        Ok(()) => println!("Successfully saved grid to \"gridstate\"."),
-                        Err(e) => println!("Failed to save grid state: {}", e),
-                    }
-                }
-                Err(e) => println!("Unable to open file \"gridstate\": {}", e),
+fn list_snapshots(snapshots: &Snapshots) {
+    match snapshots.list() {
+        Ok(list) => {
+            println!("{:>12}  {:>10}  name", "cycle", "unix time");
+            for s in list {
+                println!("{:>12}  {}  {}", s.cycle, s.time, s.name());
             }
         }
+        Err(e) => {
+            println!("Unable to list \"{}\": {}", snapshots.dir.display(), e);
+            process::exit(1);
+        }
+    }
+}
 
-        for ev in display.poll_events() {
-            use glium::glutin::{Event, ElementState, MouseButton, MouseScrollDelta,
-                                VirtualKeyCode as VKC};
-            match ev {
-                Event::Closed => return,
-                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::L)) => {
-                    last_autosave = now;
-                    match File::open("gridstate") {
-                        Ok(mut f) => {
-                            match bincode::deserialize_from(&mut f, bincode::Infinite) {
-                                Ok(t) => {
-                                    g = t;
-                                    println!("Successfully loaded grid from \"gridstate\".");
-                                }
-                                Err(e) => println!("Failed to load grid state: {}", e),
-                            }
-                        }
-                        Err(e) => println!("Unable to create file \"gridstate\": {}", e),
-                    }
-                }
-                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::W)) => {
-                    last_autosave = now;
-                    match File::create("gridstate") {
-                        Ok(mut f) => {
-                            match bincode::serialize_into(&mut f, &g, bincode::Infinite) {
-                                Ok(()) => println!("Successfully saved grid to \"gridstate\"."),
-                                Err(e) => println!("Failed to save grid state: {}", e),
-                            }
-                        }
-                        Err(e) => println!("Unable to open file \"gridstate\": {}", e),
-                    }
-                }
-                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::F)) => {
-                    let relative_coord = (
-                        last_mouse_pos.0 as f32 - center_mouse_coord.0,
-                        last_mouse_pos.1 as f32 - center_mouse_coord.1,
-                    );
-
-                    let hex = (
-                        center.0 + relative_coord.0 * hex_per_width_pixel,
-                        center.1 - relative_coord.1 * hex_per_height_pixel,
-                    );
-                    // Adjust the width based on the height.
-                    let hex = (
-                        if hex.1 as isize % 2 == 0 {
-                            hex.0 - 0.25
-                        } else {
-                            hex.0 + 0.25
-                        },
-                        hex.1,
-                    );
-                    if hex.0 > 0.0 && hex.0 < g.width as f32 && hex.1 > 0.0 &&
-                        hex.1 < g.height as f32
-                    {
-                        let hex = g.hex_mut(hex.0 as usize, hex.1 as usize);
-                        hex.solution.fluids[0] += MANUAL_FEED_AMOUNT;
-                        println!("New food: {}", hex.solution.fluids[0]);
-                    }
-                }
-                // Make kill chemicals at cursor.
-                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::K)) => {
-                    let relative_coord = (
-                        last_mouse_pos.0 as f32 - center_mouse_coord.0,
-                        last_mouse_pos.1 as f32 - center_mouse_coord.1,
-                    );
-
-                    let hex = (
-                        center.0 + relative_coord.0 * hex_per_width_pixel,
-                        center.1 - relative_coord.1 * hex_per_height_pixel,
-                    );
-                    // Adjust the width based on the height.
-                    let hex = (
-                        if hex.1 as isize % 2 == 0 {
-                            hex.0 - 0.25
-                        } else {
-                            hex.0 + 0.25
-                        },
-                        hex.1,
-                    );
-                    if hex.0 > 0.0 && hex.0 < g.width as f32 && hex.1 > 0.0 &&
-                        hex.1 < g.height as f32
-                    {
-                        let hex = g.hex_mut(hex.0 as usize, hex.1 as usize);
-                        hex.solution.fluids[3] += MANUAL_KILL_AMOUNT;
-                        println!("New kill fluid: {}", hex.solution.fluids[3]);
-                    }
-                }
-                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::C)) => {
-                    println!(
-                        "Cleared {} food",
-                        g.tiles.iter().map(|t| t.solution.fluids[0]).sum::<f64>()
-                    );
-                    for tile in &mut g.tiles {
-                        tile.solution.fluids[0] = 0.0;
-                    }
-                }
-                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::V)) => {
-                    pure_color_mode = !pure_color_mode;
-                    println!(
-                        "Pure color mode {}",
-                        if pure_color_mode { "on" } else { "off" }
-                    );
-                }
-                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::H)) => {
-                    println!("Reset screen ratio");
-                    screen_hex_ratio = DEFAULT_SCREEN_ZOOM_RATIO * g.height as f32 *
-                        WIDTH_HEIGHT_RATIO;
-                    center = (0.5 * g.width as f32, 0.5 * g.height as f32);
-                }
-                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::U)) => {
-                    g.spawn_rate *= GRID_SPAWN_MULTIPLY;
-                    println!("New spawn rate: {}", g.spawn_rate);
-                }
-                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::D)) => {
-                    g.spawn_rate /= GRID_SPAWN_MULTIPLY;
-                    println!("New spawn rate: {}", g.spawn_rate);
-                }
-                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::X)) => {
-                    g.explode_requirement =
-                        (g.explode_requirement as f64 * GRID_EXPLODE_MULTIPLY) as usize;
-                    println!("New explode requirement: {}", g.explode_requirement);
-                }
-                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::Z)) => {
-                    g.explode_requirement =
-                        (g.explode_requirement as f64 / GRID_EXPLODE_MULTIPLY) as usize;
-                    println!("New explode requirement: {}", g.explode_requirement);
-                }
-                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::LBracket)) => {
-                    g.death_release_coefficient /= GRID_RELEASE_MULTIPLY;
-                    println!(
-                        "New death release coefficient: {}",
-                        g.death_release_coefficient
-                    );
-                }
-                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::RBracket)) => {
-                    g.death_release_coefficient *= GRID_RELEASE_MULTIPLY;
-                    println!(
-                        "New death release coefficient: {}",
-                        g.death_release_coefficient
-                    );
-                }
-                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::Q)) => {
-                    g.explode_amount *= GRID_EXPLODE_MULTIPLY;
-                    println!("New explode amount: {}", g.explode_amount);
-                }
-                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::A)) => {
-                    g.explode_amount /= GRID_EXPLODE_MULTIPLY;
-                    println!("New explode amount: {}", g.explode_amount);
-                }
-                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::P)) => {
-                    g.movement_cost += 1;
-                    println!("New movement cost: {}", g.movement_cost);
-                }
-                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::O)) => {
-                    if g.movement_cost > 0 {
-                        g.movement_cost -= 1;
-                    }
-                    println!("New movement cost: {}", g.movement_cost);
-                }
-                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::R)) => {
-                    g.randomize(&mut rng);
-                }
-                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::S)) => {
-                    g.spawning = !g.spawning;
-                    println!(
-                        "Spawning {}",
-                        if g.spawning { "enabled" } else { "disabled" }
-                    );
-                }
-                Event::KeyboardInput(ElementState::Pressed, _, Some(VKC::T)) => {
-                    rendering_enabled = !rendering_enabled;
-                    println!(
-                        "Rendering {}",
-                        if rendering_enabled {
-                            "enabled"
-                        } else {
-                            "disabled"
-                        }
-                    );
-                }
-                Event::MouseWheel(MouseScrollDelta::LineDelta(_, lines), _) => {
-                    screen_hex_ratio -= lines * SCROLL_LINES_RATIO;
-                }
-                Event::MouseWheel(MouseScrollDelta::PixelDelta(_, pixels), _) => {
-                    screen_hex_ratio -= pixels * SCROLL_PIXELS_RATIO;
-                }
-                Event::MouseMoved(x, y) => {
-                    if mouse_pressed {
-                        center.0 -= hex_per_width_pixel * (x - last_mouse_pos.0) as f32;
-                        center.1 += hex_per_height_pixel * (y - last_mouse_pos.1) as f32;
-                    }
-                    last_mouse_pos = (x, y);
-                }
-                Event::MouseInput(ElementState::Released, MouseButton::Left) => {
-                    let relative_coord = (
-                        last_mouse_pos.0 as f32 - center_mouse_coord.0,
-                        last_mouse_pos.1 as f32 - center_mouse_coord.1,
-                    );
+fn export_lineage(log: &Path, matches: &ArgMatches) {
+    let records = lineage::read(log).unwrap_or_else(|e| {
+        println!("Unable to read \"{}\": {}", log.display(), e);
+        process::exit(1);
+    });
+    let tree = lineage::Tree::new(&records);
+    let graphml = matches.value_of("format") == Some("graphml");
+    let result = match matches.value_of("output") {
+        Some(path) => {
+            File::create(path).and_then(|f| write_tree(&tree, graphml, BufWriter::new(f)))
+        }
+        None => write_tree(&tree, graphml, io::stdout()),
+    };
+    if let Err(e) = result {
+        println!("Unable to write the lineage tree: {}", e);
+        process::exit(1);
+    }
+}
 
-                    let hex = (
-                        center.0 + relative_coord.0 * hex_per_width_pixel,
-                        center.1 - relative_coord.1 * hex_per_height_pixel,
-                    );
-                    // Adjust the width based on the height.
-                    let hex = (
-                        if hex.1 as isize % 2 == 0 {
-                            hex.0 - 0.25
-                        } else {
-                            hex.0 + 0.25
-                        },
-                        hex.1,
-                    );
-                    if hex.0 > 0.0 && hex.0 < g.width as f32 && hex.1 > 0.0 &&
-                        hex.1 < g.height as f32
-                    {
-                        println!("{:?}", g.hex(hex.0 as usize, hex.1 as usize));
-                    }
-                }
-                Event::MouseInput(state, MouseButton::Right) => {
-                    match state {
-                        ElementState::Pressed => mouse_pressed = true,
-                        ElementState::Released => mouse_pressed = false,
-                    }
-                }
-                Event::Focused(_) => {
-                    // Always stop handling mouse press if we loose or gain focus.
-                    mouse_pressed = false;
-                }
-                _ => (),
+fn export_genomes(snapshots: &Snapshots, spec: Option<&str>, matches: &ArgMatches) {
+    let g = match resolve(snapshots, spec) {
+        Ok(Some(path)) => {
+            save::load(&path).unwrap_or_else(|e| {
+                println!("Unable to load \"{}\": {}", path.display(), e);
+                process::exit(1);
+            })
+        }
+        Ok(None) => {
+            println!("There are no snapshots in \"{}\".", snapshots.dir.display());
+            process::exit(1);
+        }
+        Err(e) => {
+            println!("Unable to find snapshot: {}", e);
+            process::exit(1);
+        }
+    };
+    let invalid = |name: &str, value: &str| -> ! {
+        println!("Invalid value \"{}\" for --{}", value, name);
+        process::exit(1);
+    };
+    let genomes = if let Some(n) = matches.value_of("top") {
+        let n = n.parse().unwrap_or_else(|_| invalid("top", n));
+        let rank = matches.value_of("by").unwrap().parse().unwrap();
+        genome::top(&g, n, rank)
+    } else if let Some(cell) = matches.value_of("cell") {
+        let xy: Vec<usize> = cell.split(',')
+            .map(|s| s.trim().parse())
+            .collect::<Result<_, _>>()
+            .unwrap_or_else(|_| invalid("cell", cell));
+        if xy.len() != 2 || xy[0] >= g.width || xy[1] >= g.height {
+            invalid("cell", cell);
+        }
+        g.hex(xy[0], xy[1])
+            .cell
+            .iter()
+            .map(|c| genome::Genome::new(c, g.cycle))
+            .collect()
+    } else {
+        let id = matches.value_of("id").unwrap();
+        let id: u64 = id.parse().unwrap_or_else(|_| invalid("id", id));
+        g.tiles
+            .iter()
+            .filter_map(|t| t.cell.as_ref())
+            .filter(|c| c.lineage.id == id)
+            .map(|c| genome::Genome::new(c, g.cycle))
+            .collect()
+    };
+    if genomes.is_empty() {
+        println!("No cell matched at cycle {}.", g.cycle);
+        process::exit(1);
+    }
+    let dir = matches.value_of("output").unwrap_or(genome::DEFAULT_DIR);
+    for genome in genomes {
+        match genome.export(dir) {
+            Ok(path) => println!("Exported cell {} to \"{}\".", genome.lineage.id, path.display()),
+            Err(e) => {
+                println!("Unable to export cell {}: {}", genome.lineage.id, e);
+                process::exit(1);
             }
         }
     }
 }
 
-fn append_circle(
-    v: &mut Vec<QBezier>,
-    radius: f32,
-    circle_scale: f32,
-    color: [f32; 4],
-    modelview: &na::Matrix3<f32>,
-) {
-    let transform = |n: [f32; 2]| {
-        let na::Vector3 { x, y, .. } = *modelview *
-            na::Vector3::new(n[0] * circle_scale, n[1] * circle_scale, 1.0);
-        [x, y]
+fn disassemble(matches: &ArgMatches) {
+    let path = matches.value_of("GENOME").unwrap();
+    let genome = genome::Genome::load(path).unwrap_or_else(|e| {
+        println!("Unable to load genome from \"{}\": {}", path, e);
+        process::exit(1);
+    });
+    let result = match matches.value_of("output") {
+        Some(path) => {
+            File::create(path).and_then(|f| {
+                let mut w = BufWriter::new(f);
+                cell::disasm::disassemble(&genome.brain, &mut w)?;
+                w.flush()
+            })
+        }
+        None => cell::disasm::disassemble(&genome.brain, &mut io::stdout()),
     };
-    v.extend(
-        [
-            QBezier {
-                position0: transform([0.0, -1.0]),
-                position1: transform([0.5773502691896256, -1.0]),
-                position2: transform([0.8660254037844386, -0.5]),
-                inner_color0: color,
-                inner_color1: color,
-                falloff_color0: color,
-                falloff_color1: color,
-                falloff0: 0.25,
-                falloff1: 0.25,
-                falloff_radius0: radius,
-                falloff_radius1: radius,
-                inner_radius0: 0.0,
-                inner_radius1: 0.0,
-            },
-            QBezier {
-                position0: transform([0.8660254037844386, -0.5]),
-                position1: transform([1.1547005383792515, 0.0]),
-                position2: transform([0.8660254037844387, 0.5]),
-                inner_color0: color,
-                inner_color1: color,
-                falloff_color0: color,
-                falloff_color1: color,
-                falloff0: 0.25,
-                falloff1: 0.25,
-                falloff_radius0: radius,
-                falloff_radius1: radius,
-                inner_radius0: 0.0,
-                inner_radius1: 0.0,
-            },
-            QBezier {
-                position0: transform([0.8660254037844387, 0.5]),
-                position1: transform([0.5773502691896261, 1.0]),
-                position2: transform([0.0, 1.0]),
-                inner_color0: color,
-                inner_color1: color,
-                falloff_color0: color,
-                falloff_color1: color,
-                falloff0: 0.25,
-                falloff1: 0.25,
-                falloff_radius0: radius,
-                falloff_radius1: radius,
-                inner_radius0: 0.0,
-                inner_radius1: 0.0,
-            },
-            QBezier {
-                position0: transform([0.0, 1.0]),
-                position1: transform([-0.5773502691896254, 1.0]),
-                position2: transform([-0.8660254037844384, 0.5]),
-                inner_color0: color,
-                inner_color1: color,
-                falloff_color0: color,
-                falloff_color1: color,
-                falloff0: 0.25,
-                falloff1: 0.25,
-                falloff_radius0: radius,
-                falloff_radius1: radius,
-                inner_radius0: 0.0,
-                inner_radius1: 0.0,
-            },
-            QBezier {
-                position0: transform([-0.8660254037844384, 0.5]),
-                position1: transform([-1.1547005383792515, 0.0]),
-                position2: transform([-0.866025403784439, -0.5]),
-                inner_color0: color,
-                inner_color1: color,
-                falloff_color0: color,
-                falloff_color1: color,
-                falloff0: 0.25,
-                falloff1: 0.25,
-                falloff_radius0: radius,
-                falloff_radius1: radius,
-                inner_radius0: 0.0,
-                inner_radius1: 0.0,
-            },
-            QBezier {
-                position0: transform([-0.866025403784439, -0.5]),
-                position1: transform([-0.5773502691896263, -1.0]),
-                position2: transform([-0.0, -1.0]),
-                inner_color0: color,
-                inner_color1: color,
-                falloff_color0: color,
-                falloff_color1: color,
-                falloff0: 0.25,
-                falloff1: 0.25,
-                falloff_radius0: radius,
-                falloff_radius1: radius,
-                inner_radius0: 0.0,
-                inner_radius1: 0.0,
-            },
-        ].into_iter(),
-    );
+    if let Err(e) = result {
+        println!("Unable to write the listing: {}", e);
+        process::exit(1);
+    }
+}
+
+fn write_tree<W: Write>(tree: &lineage::Tree, graphml: bool, mut w: W) -> io::Result<()> {
+    if graphml {
+        tree.graphml(&mut w)?;
+    } else {
+        tree.newick(&mut w)?;
+    }
+    w.flush()
 }
@@ -0,0 +1,124 @@
+//! Everything written about a running grid besides its snapshots.
+//!
+//! A failure to write one of these is printed and stops that recording, but never stops the run.
+
+use events;
+use grid::Grid;
+use lineage;
+use stats;
+
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+/// Which recordings to make.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// The lineage log, if births and deaths are being logged.
+    pub lineage: Option<PathBuf>,
+    /// The stats file, if stats are being recorded.
+    pub stats: Option<PathBuf>,
+    /// How many cycles apart stats are sampled.
+    pub stats_every: u64,
+    /// The event log, if cell events are being logged.
+    pub events: Option<PathBuf>,
+    /// Whether to run the grid's fluid checks and print the first failure.
+    pub check_fluids: bool,
+    /// Whether to audit the grid's energy every cycle and print the first imbalance.
+    pub check_energy: bool,
+}
+
+/// The open recordings of a running grid.
+pub struct Recorders {
+    lineage: Option<lineage::Log>,
+    stats: Option<stats::Recorder>,
+    events: Option<events::Writer>,
+}
+
+impl Recorders {
+    /// Starts recording `grid`. This must be done again whenever another grid is loaded.
+    pub fn start(opts: &Options, grid: &mut Grid) -> Recorders {
+        let events = opts.events.as_ref().and_then(|path| {
+            opened("event log", path, events::Writer::open(path))
+        });
+        grid.events = events.as_ref().map(|_| Vec::new());
+        grid.checker.enabled = opts.check_fluids;
+        grid.audit.enabled = opts.check_energy;
+        Recorders {
+            lineage: opts.lineage.as_ref().and_then(|path| {
+                opened("lineage log", path, lineage::Log::open(path, grid))
+            }),
+            stats: opts.stats.as_ref().and_then(|path| {
+                opened(
+                    "stats file",
+                    path,
+                    stats::Recorder::open(path, opts.stats_every, grid),
+                )
+            }),
+            events: events,
+        }
+    }
+
+    /// Records the cycle the grid just ran.
+    pub fn update(&mut self, grid: &mut Grid) {
+        let lineage = self.lineage.as_mut().map(|l| l.update(grid));
+        check("lineage log", &mut self.lineage, lineage);
+        let stats = self.stats.as_mut().map(|s| s.update(grid));
+        check("stats file", &mut self.stats, stats);
+        if let Some(ref mut buffer) = grid.events {
+            let events = self.events.as_mut().map(|w| w.write(buffer));
+            check("event log", &mut self.events, events);
+            buffer.clear();
+        }
+        if self.events.is_none() {
+            grid.events = None;
+        }
+        // Only the first violation is reported, since the ones after it usually follow from it.
+        if let Some(v) = grid.checker.violation.take() {
+            println!("Fluid check failed: {}", v);
+            grid.checker.enabled = false;
+        }
+        if let Some(imbalance) = grid.audit.imbalance.take() {
+            println!("Energy check failed: {}", imbalance);
+            grid.audit.enabled = false;
+        }
+    }
+
+    /// Writes out anything buffered. Do this before saving a snapshot so the recordings cover
+    /// everything up to it.
+    pub fn flush(&mut self) {
+        let lineage = self.lineage.as_mut().map(|l| l.flush());
+        check("lineage log", &mut self.lineage, lineage);
+        let events = self.events.as_mut().map(|w| w.flush());
+        check("event log", &mut self.events, events);
+    }
+
+    /// Closes every recording. Do this before starting again on another grid.
+    pub fn stop(&mut self) {
+        self.lineage = None;
+        self.stats = None;
+        self.events = None;
+    }
+}
+
+fn opened<T, E: Display>(name: &str, path: &Path, result: Result<T, E>) -> Option<T> {
+    match result {
+        Ok(t) => Some(t),
+        Err(e) => {
+            println!(
+                "Failed to open {} \"{}\", not recording it: {}",
+                name,
+                path.display(),
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Stops a recording if writing to it failed.
+fn check<T, E: Display>(name: &str, recorder: &mut Option<T>, result: Option<Result<(), E>>) {
+    if let Some(Err(e)) = result {
+        println!("Failed to write {}, no longer recording it: {}", name, e);
+        *recorder = None;
+    }
+}
@@ -0,0 +1,242 @@
+//! Optional compression of whole state files.
+//!
+//! The compression of a file being written is chosen from its extension, while files being read
+//! are recognized by the magic number of the compressed stream, so a renamed file still loads.
+//! Both directions stream, so the uncompressed grid never has to fit in memory at once.
+
+use flate2;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use zstd;
+
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+const ZSTD_LEVEL: i32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::None
+    }
+}
+
+impl Compression {
+    /// The extension appended to the name of a file with this compression, including the dot.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Compression::None => "",
+            Compression::Gzip => ".gz",
+            Compression::Zstd => ".zst",
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Compression {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+
+    /// Recognizes the compression from the first bytes of a file.
+    pub fn detect(start: &[u8]) -> Compression {
+        if start.starts_with(&GZIP_MAGIC) {
+            Compression::Gzip
+        } else if start.starts_with(&ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match *self {
+                Compression::None => "none",
+                Compression::Gzip => "gzip",
+                Compression::Zstd => "zstd",
+            }
+        )
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Compression::None),
+            "gzip" | "gz" => Ok(Compression::Gzip),
+            "zstd" | "zst" => Ok(Compression::Zstd),
+            _ => Err("expected one of none, gzip or zstd".to_string()),
+        }
+    }
+}
+
+/// Reads a possibly compressed stream.
+pub enum Decoder<R: BufRead> {
+    None(R),
+    Gzip(GzDecoder<R>),
+    Zstd(zstd::Decoder<R>),
+}
+
+impl<R: BufRead> Decoder<R> {
+    pub fn new(mut r: R) -> io::Result<Self> {
+        let compression = Compression::detect(r.fill_buf()?);
+        Ok(match compression {
+            Compression::None => Decoder::None(r),
+            Compression::Gzip => Decoder::Gzip(GzDecoder::new(r)),
+            Compression::Zstd => Decoder::Zstd(zstd::Decoder::with_buffer(r)?),
+        })
+    }
+}
+
+impl<R: BufRead> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Decoder::None(ref mut r) => r.read(buf),
+            Decoder::Gzip(ref mut r) => r.read(buf),
+            Decoder::Zstd(ref mut r) => r.read(buf),
+        }
+    }
+}
+
+/// Writes a stream with the given compression. `finish` must be called once everything is written.
+pub enum Encoder<W: Write> {
+    None(W),
+    Gzip(GzEncoder<W>),
+    Zstd(zstd::Encoder<W>),
+}
+
+impl<W: Write> Encoder<W> {
+    pub fn new(w: W, compression: Compression) -> io::Result<Self> {
+        Ok(match compression {
+            Compression::None => Encoder::None(w),
+            Compression::Gzip => Encoder::Gzip(GzEncoder::new(w, flate2::Compression::default())),
+            Compression::Zstd => Encoder::Zstd(zstd::Encoder::new(w, ZSTD_LEVEL)?),
+        })
+    }
+
+    /// Writes out anything still buffered by the compressor and returns the inner writer.
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            Encoder::None(w) => Ok(w),
+            Encoder::Gzip(w) => w.finish(),
+            Encoder::Zstd(w) => w.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Encoder::None(ref mut w) => w.write(buf),
+            Encoder::Gzip(ref mut w) => w.write(buf),
+            Encoder::Zstd(ref mut w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Encoder::None(ref mut w) => w.flush(),
+            Encoder::Gzip(ref mut w) => w.flush(),
+            Encoder::Zstd(ref mut w) => w.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bincode;
+    use cell::brain::InstructionSet;
+    use fluid::FluidParams;
+    use grid::{Boundary, Grid};
+    use rand::{Isaac64Rng, SeedableRng};
+    use save::{self, MAGIC};
+    use std::env;
+    use std::fs::{self, File};
+    use std::process;
+
+    fn grid() -> Grid {
+        let mut rng = Isaac64Rng::from_seed(&[6]);
+        let mut g = Grid::new(
+            4,
+            3,
+            0.04,
+            1.0,
+            500,
+            10000,
+            10,
+            50,
+            2100,
+            0.5,
+            0.5,
+            Boundary::Wall,
+            FluidParams::default(),
+            InstructionSet::default(),
+            &mut rng,
+        );
+        for _ in 0..5 {
+            g.cycle();
+        }
+        g
+    }
+
+    #[test]
+    fn detect() {
+        let mut gzip = Vec::new();
+        Encoder::new(&mut gzip, Compression::Gzip).unwrap().finish().unwrap();
+        let mut zstd = Vec::new();
+        Encoder::new(&mut zstd, Compression::Zstd).unwrap().finish().unwrap();
+        // Saves start with a header, legacy saves with the bincode encoded width.
+        let legacy = bincode::serialize(&grid(), bincode::Infinite).unwrap();
+        assert_eq!(Compression::detect(&gzip), Compression::Gzip);
+        assert_eq!(Compression::detect(&zstd), Compression::Zstd);
+        assert_eq!(Compression::detect(&MAGIC), Compression::None);
+        assert_eq!(Compression::detect(&legacy), Compression::None);
+        assert_eq!(Compression::detect(&GZIP_MAGIC[..1]), Compression::None);
+        assert_eq!(Compression::detect(&[]), Compression::None);
+    }
+
+    #[test]
+    fn saves_round_trip() {
+        let dir = env::temp_dir().join(format!("evomata-compression-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let g = grid();
+        assert!(g.population() > 0);
+        let expected = bincode::serialize(&g, bincode::Infinite).unwrap();
+        for &compression in &[Compression::None, Compression::Gzip, Compression::Zstd] {
+            let path = dir.join(format!("grid{}", compression.extension()));
+            save::save(&g, &path).unwrap();
+            let mut start = [0; 8];
+            File::open(&path).unwrap().read_exact(&mut start).unwrap();
+            assert_eq!(Compression::detect(&start), compression);
+            let loaded = save::load(&path).unwrap();
+            assert_eq!(bincode::serialize(&loaded, bincode::Infinite).unwrap(), expected);
+
+            // The stream is recognized whatever the file is called.
+            let renamed = dir.join(format!("renamed-{}", compression));
+            fs::rename(&path, &renamed).unwrap();
+            let loaded = save::load(&renamed).unwrap();
+            assert_eq!(bincode::serialize(&loaded, bincode::Infinite).unwrap(), expected);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,438 @@
+//! The grid state file format.
+//!
+//! A state file starts with `MAGIC` and a little endian `u32` format version, followed by the
+//! bincode encoded `Parameters` of the grid and then the bincode encoded `Grid` itself. The
+//! parameters are stored separately so they can be read without understanding the tiles.
+//!
+//! The whole file may also be compressed, see `compression`.
+//!
+//! Files written before the header existed are raw bincode dumps of the grid and are treated as
+//! version `0`.
+//!
+//! Older versions are read into frozen copies of their layout, one module per version. Each of
+//! those modules converts the grid of the version before it, so an old file is read by the module
+//! of its version and converted a step at a time up to version 10, which `upgrade` turns into a
+//! current `Grid`. Whenever the encoding of `Grid` or anything inside it changes, `VERSION` must be
+//! bumped, the layout it replaces copied into a new module that converts from the one before it,
+//! and `upgrade` pointed at that module. `fixtures` holds a small grid written by each older
+//! version for the tests.
+
+pub mod compression;
+pub mod snapshot;
+mod v0;
+mod v1;
+mod v2;
+mod v3;
+mod v4;
+mod v5;
+mod v6;
+mod v7;
+mod v8;
+mod v9;
+mod v10;
+
+use adhesion::Adhesion;
+use bincode;
+use cell::{self, brain::InstructionSet};
+use conflict::Conflicts;
+use fluid::FluidParams;
+use grid::{self, Boundary, Grid, Mating};
+use self::compression::{Compression, Decoder, Encoder};
+
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+pub const MAGIC: [u8; 8] = *b"EVOMATA\x0b";
+pub const VERSION: u32 = 11;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Bincode(bincode::Error),
+    /// The file has no header and could not be read as a headerless legacy save either.
+    NotASave(bincode::Error),
+    /// The file was written by a newer build.
+    Newer(u32),
+    /// The file was written by an older build and there is no migration from its version.
+    Unsupported(u32),
+    /// The file decoded but holds something no build would have written.
+    Corrupt(String),
+    /// Saving would overwrite a file this build can't read.
+    WouldClobber(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => write!(f, "{}", e),
+            Error::Bincode(ref e) => write!(f, "{}", e),
+            Error::NotASave(ref e) => {
+                write!(
+                    f,
+                    "not a grid state file (reading it as a legacy save failed: {})",
+                    e
+                )
+            }
+            Error::Newer(v) => {
+                write!(
+                    f,
+                    "state file format version {} is newer than this build supports ({})",
+                    v,
+                    VERSION
+                )
+            }
+            Error::Unsupported(v) => {
+                write!(
+                    f,
+                    "state file format version {} can't be migrated to version {}",
+                    v,
+                    VERSION
+                )
+            }
+            Error::Corrupt(ref e) => write!(f, "corrupt state file: {}", e),
+            Error::WouldClobber(ref path) => {
+                write!(
+                    f,
+                    "refusing to overwrite \"{}\" because this build can't read it",
+                    path
+                )
+            }
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<bincode::Error> for Error {
+    fn from(e: bincode::Error) -> Self {
+        Error::Bincode(e)
+    }
+}
+
+/// The parameters a grid was created with, stored ahead of the tiles.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Parameters {
+    pub width: usize,
+    pub height: usize,
+    pub consumption: f64,
+    pub spawn_rate: f64,
+    pub inhale_minimum: usize,
+    pub inhale_cap: usize,
+    pub movement_cost: usize,
+    pub divide_cost: usize,
+    pub explode_requirement: usize,
+    pub death_release_coefficient: f64,
+    pub explode_amount: f64,
+    pub boundary: Boundary,
+    pub fluid: FluidParams,
+    pub instructions: InstructionSet,
+    pub conflicts: Conflicts,
+    pub recycle_costs: bool,
+    pub mating: Mating,
+    pub adhesion: Adhesion,
+}
+
+impl<'a> From<&'a Grid> for Parameters {
+    fn from(g: &'a Grid) -> Self {
+        Parameters {
+            width: g.width,
+            height: g.height,
+            consumption: g.consumption,
+            spawn_rate: g.spawn_rate,
+            inhale_minimum: g.inhale_minimum,
+            inhale_cap: g.inhale_cap,
+            movement_cost: g.movement_cost,
+            divide_cost: g.divide_cost,
+            explode_requirement: g.explode_requirement,
+            death_release_coefficient: g.death_release_coefficient,
+            explode_amount: g.explode_amount,
+            boundary: g.boundary,
+            fluid: g.fluid.clone(),
+            instructions: g.instructions.clone(),
+            conflicts: g.conflicts.clone(),
+            recycle_costs: g.recycle_costs,
+            mating: g.mating,
+            adhesion: g.adhesion.clone(),
+        }
+    }
+}
+
+type Reader = BufReader<Decoder<BufReader<File>>>;
+
+/// Opens a state file for reading, decompressing it if needed.
+fn open(path: &Path) -> Result<Reader, Error> {
+    Ok(BufReader::new(Decoder::new(BufReader::new(File::open(path)?))?))
+}
+
+enum Header {
+    Version(u32),
+    /// There was no header. Holds the bytes that were read looking for one.
+    Missing(Vec<u8>),
+}
+
+/// Reads the header from the start of `r`.
+fn read_header<R: Read>(r: &mut R) -> Result<Header, Error> {
+    let mut start = Vec::with_capacity(MAGIC.len());
+    r.take(MAGIC.len() as u64).read_to_end(&mut start)?;
+    if start[..] != MAGIC[..] {
+        return Ok(Header::Missing(start));
+    }
+    match bincode::deserialize_from(r, bincode::Infinite)? {
+        // Version 0 files have no header, so no build wrote this.
+        0 => Err(Error::Unsupported(0)),
+        v => Ok(Header::Version(v)),
+    }
+}
+
+/// Reads only the header of a state file, returning its version and parameters.
+///
+/// Files from older versions are migrated in full, since their parameters were stored differently.
+pub fn info<P: AsRef<Path>>(path: P) -> Result<(u32, Parameters), Error> {
+    let mut f = open(path.as_ref())?;
+    match read_header(&mut f)? {
+        Header::Version(v) if v > VERSION => Err(Error::Newer(v)),
+        Header::Version(VERSION) => {
+            Ok((VERSION, bincode::deserialize_from(&mut f, bincode::Infinite)?))
+        }
+        // Older headers have a different layout, so read the whole grid.
+        Header::Version(v) => Ok((v, Parameters::from(&migrate(v, &mut f)?))),
+        Header::Missing(start) => {
+            let grid = migrate(0, &mut (&start[..]).chain(f))?;
+            Ok((0, Parameters::from(&grid)))
+        }
+    }
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Grid, Error> {
+    load_versioned(path).map(|(g, _)| g)
+}
+
+/// Loads a grid, migrating it if necessary, and returns the version the file was written with.
+pub fn load_versioned<P: AsRef<Path>>(path: P) -> Result<(Grid, u32), Error> {
+    let mut f = open(path.as_ref())?;
+    match read_header(&mut f)? {
+        Header::Version(VERSION) => {
+            let _: Parameters = bincode::deserialize_from(&mut f, bincode::Infinite)?;
+            Ok((bincode::deserialize_from(&mut f, bincode::Infinite)?, VERSION))
+        }
+        Header::Version(v) if v > VERSION => Err(Error::Newer(v)),
+        Header::Version(v) => migrate(v, &mut f).map(|g| (g, v)),
+        Header::Missing(start) => Ok((migrate(0, &mut (&start[..]).chain(f))?, 0)),
+    }
+}
+
+/// Upgrades a file from an older version whose header, if it has one, has already been read.
+fn migrate<R: Read>(version: u32, r: &mut R) -> Result<Grid, Error> {
+    v10::read(version, r).and_then(upgrade)
+}
+
+/// Converts a version 10 grid to the current layout. Cells couldn't bond.
+fn upgrade(old: v10::Grid) -> Result<Grid, Error> {
+    let tiles = old.tiles
+        .into_iter()
+        .map(|hex| {
+            let cell = hex.cell.map(|c| {
+                Box::new(cell::Cell::from_parts(
+                    c.inhale,
+                    c.suicide,
+                    c.brain.upgrade(),
+                    c.turn,
+                    c.lineage.upgrade(),
+                    [false; 6],
+                ))
+            });
+            let mut tile = grid::Hex::new(hex.solution.upgrade(), cell);
+            tile.terrain = hex.terrain.upgrade();
+            tile
+        })
+        .collect();
+    Ok(Grid {
+        spawning: old.spawning,
+        width: old.width,
+        height: old.height,
+        consumption: old.consumption,
+        spawn_rate: old.spawn_rate,
+        inhale_minimum: old.inhale_minimum,
+        inhale_cap: old.inhale_cap,
+        movement_cost: old.movement_cost,
+        divide_cost: old.divide_cost,
+        explode_requirement: old.explode_requirement,
+        death_release_coefficient: old.death_release_coefficient,
+        explode_amount: old.explode_amount,
+        boundary: old.boundary.upgrade(),
+        fluid: old.fluid.upgrade(),
+        instructions: old.instructions.upgrade()?,
+        conflicts: old.conflicts.upgrade(),
+        recycle_costs: old.recycle_costs,
+        mating: old.mating.upgrade(),
+        adhesion: Adhesion::default(),
+        cycle: old.cycle,
+        seed: old.seed,
+        next_id: old.next_id,
+        tiles: tiles,
+        counters: grid::Counters::default(),
+        events: None,
+        checker: grid::Checker::default(),
+        audit: Default::default(),
+    })
+}
+
+/// Checks that overwriting `path` won't destroy a file this build can't read.
+///
+/// A file without a header is only overwritten if it reads as a legacy save.
+fn check_clobber(path: &Path) -> Result<(), Error> {
+    let mut f = match open(path) {
+        Ok(f) => f,
+        Err(Error::Io(ref e)) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    match read_header(&mut f)? {
+        Header::Version(v) if v <= VERSION => Ok(()),
+        Header::Version(v) => Err(Error::Newer(v)),
+        Header::Missing(start) => {
+            match migrate(0, &mut (&start[..]).chain(f)) {
+                Ok(_) => Ok(()),
+                Err(_) => Err(Error::WouldClobber(path.display().to_string())),
+            }
+        }
+    }
+}
+
+/// Saves the grid to `path`, compressed according to its extension.
+///
+/// The grid is first written to a temporary file next to `path` which is then renamed over it, so
+/// a crash part way through never leaves a truncated file behind.
+pub fn save<P: AsRef<Path>>(grid: &Grid, path: P) -> Result<(), Error> {
+    let path = path.as_ref();
+    check_clobber(path)?;
+    let mut tmp_name = OsString::from(path.file_name().unwrap_or_default());
+    tmp_name.push(".tmp");
+    let tmp = path.with_file_name(tmp_name);
+    let result = write(grid, &tmp, Compression::from_path(path))
+        .and_then(|()| fs::rename(&tmp, path).map_err(Error::from));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+fn write(grid: &Grid, path: &Path, compression: Compression) -> Result<(), Error> {
+    let mut f = BufWriter::new(Encoder::new(File::create(path)?, compression)?);
+    f.write_all(&MAGIC)?;
+    bincode::serialize_into(&mut f, &VERSION, bincode::Infinite)?;
+    bincode::serialize_into(&mut f, &Parameters::from(grid), bincode::Infinite)?;
+    bincode::serialize_into(&mut f, grid, bincode::Infinite)?;
+    let f = f.into_inner().map_err(io::Error::from)?.finish()?;
+    f.sync_all()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cell::brain::{Ins, TOTAL_OUTPUTS};
+    use conflict::Policy;
+    use std::env;
+    use std::process;
+    use terrain::Terrain;
+
+    /// The fixture written by every older version is a 4 by 3 grid holding one cell, saved after
+    /// 3 cycles with every option the version had set away from its default.
+    fn fixture(version: u32) -> String {
+        format!(
+            "{}/src/save/fixtures/v{}.grid.gz",
+            env!("CARGO_MANIFEST_DIR"),
+            version
+        )
+    }
+
+    #[test]
+    fn old_versions_migrate() {
+        for version in 0..VERSION {
+            let (mut grid, read) = load_versioned(fixture(version)).unwrap();
+            assert_eq!(read, version);
+            assert_eq!((grid.width, grid.height, grid.tiles.len()), (4, 3, 12));
+            // The cycle count wasn't saved before version 2.
+            assert_eq!(grid.cycle, if version < 2 { 0 } else { 3 });
+            if version >= 4 {
+                assert_eq!(grid.boundary, Boundary::Wall);
+            } else {
+                assert_eq!(grid.boundary, Boundary::Toroidal);
+            }
+            let count = |t| grid.tiles.iter().filter(|h| h.terrain == t).count();
+            if version >= 5 {
+                assert_eq!((count(Terrain::Rock), count(Terrain::Soil)), (1, 1));
+            } else {
+                assert_eq!(count(Terrain::Open), 12);
+            }
+            assert_eq!(
+                version >= 7,
+                grid.instructions.weights().contains(&(Ins::_IF, 2.0))
+            );
+            if version >= 8 {
+                assert_eq!(grid.conflicts.policy, Policy::Auction);
+                assert_eq!(grid.conflicts.penalty, 3);
+            } else {
+                assert_eq!(grid.conflicts, Conflicts::default());
+            }
+            assert_eq!(grid.recycle_costs, version >= 9);
+            if version >= 10 {
+                assert_eq!(grid.mating, Mating::Consent);
+            } else {
+                assert_eq!(grid.mating, Mating::Free);
+            }
+            assert_eq!(grid.adhesion, Adhesion::default());
+
+            let cells: Vec<_> = grid.tiles.iter().filter_map(|h| h.cell.as_ref()).collect();
+            assert_eq!(cells.len(), 1);
+            assert_eq!(cells[0].brain.program().outputs, TOTAL_OUTPUTS);
+            assert_eq!(cells[0].bonds, [false; 6]);
+            assert!(cells[0].lineage.id < grid.next_id);
+
+            for _ in 0..5 {
+                grid.cycle();
+            }
+        }
+    }
+
+    #[test]
+    fn only_files_this_build_reads_are_overwritten() {
+        let dir = env::temp_dir().join(format!("evomata-clobber-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let grid = load(fixture(VERSION - 1)).unwrap();
+
+        // A legacy save without a header is read and so can be replaced.
+        let legacy = dir.join("gridstate");
+        fs::copy(fixture(0), &legacy).unwrap();
+        save(&grid, &legacy).unwrap();
+        assert_eq!(load_versioned(&legacy).unwrap().1, VERSION);
+
+        let other = dir.join("notes");
+        File::create(&other).unwrap().write_all(b"not a grid").unwrap();
+        match save(&grid, &other) {
+            Err(Error::WouldClobber(_)) => {}
+            r => panic!("overwrote another file: {:?}", r),
+        }
+        let newer = dir.join("newer");
+        {
+            let mut f = File::create(&newer).unwrap();
+            f.write_all(&MAGIC).unwrap();
+            bincode::serialize_into(&mut f, &(VERSION + 1), bincode::Infinite).unwrap();
+        }
+        match save(&grid, &newer) {
+            Err(Error::Newer(v)) => assert_eq!(v, VERSION + 1),
+            r => panic!("overwrote a newer file: {:?}", r),
+        }
+        let mut contents = String::new();
+        File::open(&other).unwrap().read_to_string(&mut contents).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(contents, "not a grid");
+    }
+}
@@ -0,0 +1,322 @@
+//! A directory of cycle numbered state files with a retention policy.
+//!
+//! Snapshots are named `cycle-<cycle>-<unix seconds>.grid`, followed by `.gz` or `.zst` if they
+//! are compressed. A snapshot of a cycle that was already saved in the same second gets a number
+//! after the seconds, as in `cycle-<cycle>-<unix seconds>-2.grid`. Only files matching that
+//! pattern are ever listed or pruned, so other files in the directory are left alone.
+
+use grid::Grid;
+use super::{save, Error};
+use super::compression::Compression;
+
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const PREFIX: &str = "cycle-";
+const EXTENSION: &str = ".grid";
+const SECONDS_PER_DAY: u64 = 60 * 60 * 24;
+
+/// Which snapshots survive pruning.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Retention {
+    /// Always keep this many of the most recently written snapshots. Must be at least 1 so a
+    /// snapshot isn't pruned as soon as it's written.
+    pub keep_last: usize,
+    /// Also keep the newest snapshot of each of this many most recent days.
+    pub keep_daily: usize,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Retention {
+            keep_last: 10,
+            keep_daily: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub path: PathBuf,
+    pub cycle: u64,
+    /// Seconds since the unix epoch when the snapshot was written.
+    pub time: u64,
+    /// Sets apart snapshots of the same cycle written in the same second. The first has none.
+    pub copy: Option<u64>,
+}
+
+impl Snapshot {
+    fn parse(path: PathBuf) -> Option<Snapshot> {
+        let (cycle, time, copy) = {
+            let name = path.file_name()?.to_str()?;
+            let compression = Compression::from_path(&path);
+            let name = &name[..name.len() - compression.extension().len()];
+            if !name.starts_with(PREFIX) || !name.ends_with(EXTENSION) {
+                return None;
+            }
+            let mut parts = name[PREFIX.len()..name.len() - EXTENSION.len()].splitn(3, '-');
+            let cycle = parts.next()?.parse().ok()?;
+            let time = parts.next()?.parse().ok()?;
+            let copy = match parts.next() {
+                Some(copy) => Some(copy.parse().ok()?),
+                None => None,
+            };
+            (cycle, time, copy)
+        };
+        Some(Snapshot {
+            path: path,
+            cycle: cycle,
+            time: time,
+            copy: copy,
+        })
+    }
+
+    pub fn name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+}
+
+pub struct Snapshots {
+    pub dir: PathBuf,
+    pub retention: Retention,
+    /// Compression of newly saved snapshots. Snapshots of any compression are listed and loaded.
+    pub compression: Compression,
+}
+
+impl Snapshots {
+    pub fn new<P: Into<PathBuf>>(dir: P, retention: Retention, compression: Compression) -> Self {
+        Snapshots {
+            dir: dir.into(),
+            retention: retention,
+            compression: compression,
+        }
+    }
+
+    /// All snapshots in the directory, ordered by cycle and then by when they were written.
+    pub fn list(&self) -> Result<Vec<Snapshot>, Error> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut snapshots = Vec::new();
+        for entry in entries {
+            if let Some(s) = Snapshot::parse(entry?.path()) {
+                snapshots.push(s);
+            }
+        }
+        snapshots.sort_by_key(|s| (s.cycle, s.time, s.copy));
+        Ok(snapshots)
+    }
+
+    /// The most recently written snapshot.
+    pub fn latest(&self) -> Result<Option<Snapshot>, Error> {
+        Ok(self.list()?.into_iter().max_by_key(|s| (s.time, s.cycle, s.copy)))
+    }
+
+    /// Finds a snapshot from a cycle number, a file name in the directory or a path.
+    ///
+    /// When several snapshots share a cycle the newest one is chosen.
+    pub fn find(&self, spec: &str) -> Result<PathBuf, Error> {
+        if let Ok(cycle) = spec.parse::<u64>() {
+            if let Some(s) = self.list()?.into_iter().rev().find(|s| s.cycle == cycle) {
+                return Ok(s.path);
+            }
+        }
+        let in_dir = self.dir.join(spec);
+        if in_dir.is_file() {
+            return Ok(in_dir);
+        }
+        let path = PathBuf::from(spec);
+        if path.is_file() {
+            return Ok(path);
+        }
+        Err(Error::Io(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no snapshot \"{}\" in \"{}\"", spec, self.dir.display()),
+        )))
+    }
+
+    /// Writes a new snapshot of the grid and then prunes old ones.
+    pub fn save(&self, grid: &Grid) -> Result<PathBuf, Error> {
+        fs::create_dir_all(&self.dir)?;
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let name = |copy: Option<u64>, compression: Compression| {
+            let copy = copy.map(|n| format!("-{}", n)).unwrap_or_default();
+            format!(
+                "{}{:012}-{}{}{}{}",
+                PREFIX,
+                grid.cycle,
+                time,
+                copy,
+                EXTENSION,
+                compression.extension()
+            )
+        };
+        // Saving would replace a snapshot of the same cycle from the same second, whatever its
+        // compression, so number the new one.
+        let compressions = [Compression::None, Compression::Gzip, Compression::Zstd];
+        let mut copy = None;
+        while compressions.iter().any(|&c| self.dir.join(name(copy, c)).exists()) {
+            copy = Some(copy.map_or(2, |n| n + 1));
+        }
+        let path = self.dir.join(name(copy, self.compression));
+        save(grid, &path)?;
+        self.prune()?;
+        Ok(path)
+    }
+
+    /// Removes every snapshot the retention policy doesn't keep.
+    pub fn prune(&self) -> Result<(), Error> {
+        let mut snapshots = self.list()?;
+        // Newest first.
+        snapshots.sort_by_key(|s| Reverse((s.time, s.cycle, s.copy)));
+
+        let mut keep: HashSet<PathBuf> = snapshots
+            .iter()
+            .take(self.retention.keep_last)
+            .map(|s| s.path.clone())
+            .collect();
+        let mut days = HashSet::new();
+        for s in &snapshots {
+            let day = s.time / SECONDS_PER_DAY;
+            if days.len() < self.retention.keep_daily && days.insert(day) {
+                keep.insert(s.path.clone());
+            }
+        }
+
+        for s in snapshots {
+            if !keep.contains(&s.path) {
+                fs::remove_file(&s.path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cell::brain::InstructionSet;
+    use fluid::FluidParams;
+    use grid::Boundary;
+    use rand::{Isaac64Rng, SeedableRng};
+    use std::env;
+    use std::fs::File;
+    use std::process;
+
+    #[test]
+    fn parse() {
+        let dir = PathBuf::from("snapshots");
+        for name in &[
+            "cycle-000000000042-1500000000.grid",
+            "cycle-000000000042-1500000000.grid.gz",
+            "cycle-000000000042-1500000000.grid.zst",
+        ] {
+            let s = Snapshot::parse(dir.join(name)).unwrap();
+            assert_eq!((s.cycle, s.time, s.copy), (42, 1500000000, None));
+            assert_eq!(s.name(), *name);
+        }
+        let s = Snapshot::parse(dir.join("cycle-000000000042-1500000000-2.grid.gz")).unwrap();
+        assert_eq!((s.cycle, s.time, s.copy), (42, 1500000000, Some(2)));
+        for name in &[
+            "notes.txt",
+            "cycle-42.grid",
+            "cycle-x-1500000000.grid",
+            "cycle-42-1500000000.grid.bak",
+            "cycle-42-1500000000-.grid",
+            "cycle-42-1500000000-x.grid",
+            "cycle-42-1500000000.gz",
+            "save-42-1500000000.grid",
+        ] {
+            assert!(Snapshot::parse(dir.join(name)).is_none(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn prune() {
+        let dir = env::temp_dir().join(format!("evomata-prune-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let name = |cycle: u64, day: u64, second: u64| {
+            format!("cycle-{:012}-{}.grid", cycle, day * SECONDS_PER_DAY + second)
+        };
+        let others = ["notes.txt", "cycle-1.grid"];
+        let pruned = [name(1, 1, 10), name(2, 1, 20)];
+        // The two newest, and the newest of the two most recent days.
+        let kept = [name(3, 2, 10), name(4, 3, 10), name(5, 3, 20)];
+        let mut expected: Vec<String> = others.iter().map(|s| s.to_string()).collect();
+        expected.extend(kept.iter().cloned());
+        expected.sort();
+        for file in expected.iter().chain(&pruned) {
+            File::create(dir.join(file)).unwrap();
+        }
+
+        let retention = Retention {
+            keep_last: 2,
+            keep_daily: 2,
+        };
+        let snapshots = Snapshots::new(dir.clone(), retention, Compression::None);
+        snapshots.prune().unwrap();
+        let mut left: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        left.sort();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(left, expected);
+    }
+
+    #[test]
+    fn same_cycle_in_the_same_second_is_numbered() {
+        let dir = env::temp_dir().join(format!("evomata-same-second-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut rng = Isaac64Rng::from_seed(&[9]);
+        let mut g = Grid::new(
+            2,
+            2,
+            0.04,
+            0.0,
+            500,
+            10000,
+            0,
+            5,
+            2100,
+            0.5,
+            0.5,
+            Boundary::Wall,
+            FluidParams::default(),
+            InstructionSet::default(),
+            &mut rng,
+        );
+        g.cycle = 7;
+        // Snapshots of the cycle for every second the save could happen in, one already numbered.
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        for time in now..now + 5 {
+            File::create(dir.join(format!("cycle-000000000007-{}.grid.gz", time))).unwrap();
+            File::create(dir.join(format!("cycle-000000000007-{}-2.grid", time))).unwrap();
+        }
+        let retention = Retention {
+            keep_last: 20,
+            keep_daily: 0,
+        };
+        let snapshots = Snapshots::new(dir.clone(), retention, Compression::None);
+        let saved = Snapshot::parse(snapshots.save(&g).unwrap()).unwrap();
+        let list = snapshots.list().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!((saved.cycle, saved.copy), (7, Some(3)));
+        assert_eq!(list.len(), 11);
+        assert!(list.iter().all(|s| s.cycle == 7));
+        assert_eq!(list.iter().filter(|s| s.time == saved.time).count(), 3);
+    }
+}
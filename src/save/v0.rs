@@ -0,0 +1,193 @@
+//! Headerless state files, which were raw bincode dumps of the grid.
+//!
+//! These types are frozen copies of the layout at the time and must not change, and the same goes
+//! for the other version modules. Several of them kept their layout through later versions and are
+//! shared by those modules. Some of them are only read to skip over their bytes, hence the
+//! `dead_code` allowance.
+
+#![allow(dead_code)]
+
+use bincode;
+use cell::brain;
+use fluid;
+
+use std::io::Read;
+
+#[derive(Deserialize)]
+pub struct Grid {
+    pub spawning: bool,
+    pub width: usize,
+    pub height: usize,
+    pub consumption: f64,
+    pub spawn_rate: f64,
+    pub inhale_minimum: usize,
+    pub inhale_cap: usize,
+    pub movement_cost: usize,
+    pub divide_cost: usize,
+    pub explode_requirement: usize,
+    pub death_release_coefficient: f64,
+    pub explode_amount: f64,
+    pub tiles: Vec<Hex>,
+}
+
+/// Tiles kept this layout up to and including version 4.
+#[derive(Deserialize)]
+pub struct Hex {
+    pub solution: Solution,
+    pub cell: Option<Box<Cell>>,
+    // Decisions and deltas only live for one cycle, so they are read and passed on unused.
+    pub decision: Option<Decision>,
+    pub delta: Delta,
+}
+
+/// Solutions have kept this layout in every version.
+#[derive(Deserialize)]
+pub struct Solution {
+    fluids: [f64; 8],
+    coefficients: [[f64; 8]; 6],
+    diffuse: [f64; 8],
+}
+
+impl Solution {
+    pub fn upgrade(self) -> fluid::Solution {
+        let mut solution = fluid::Solution::new(self.fluids, self.coefficients);
+        solution.diffuse = self.diffuse;
+        solution
+    }
+}
+
+/// Cells kept this layout up to and including version 5.
+#[derive(Deserialize)]
+pub struct Cell {
+    pub inhale: usize,
+    pub suicide: bool,
+    pub brain: Brain,
+    pub turn: usize,
+}
+
+/// Brains have kept this layout in every version, though older ones have fewer outputs.
+#[derive(Deserialize)]
+pub struct Brain {
+    program: Program,
+    memory: [f64; 4],
+}
+
+#[derive(Deserialize)]
+struct Program {
+    program: Vec<Op>,
+    mutate_lambda: usize,
+    crossover_points: usize,
+    inputs: usize,
+    outputs: usize,
+}
+
+#[derive(Deserialize)]
+struct Op {
+    instruction: Ins,
+    first: usize,
+    second: usize,
+}
+
+/// Brains were only built from the first 16 instructions before version 7, which added the rest.
+#[derive(Clone, Copy, Deserialize)]
+pub enum Ins {
+    Nop,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Grt,
+    Les,
+    And,
+    Or,
+    Pow,
+    Exp,
+    Log,
+    Ln,
+    Sin,
+    Sqt,
+    Abs,
+    Min,
+    Max,
+    Tanh,
+    Cos,
+    If,
+    Neg,
+    Pdiv,
+    Plog,
+}
+
+impl Ins {
+    /// Instructions have always been numbered the same way.
+    pub fn upgrade(self) -> brain::Ins {
+        brain::INSTRUCTIONS[self as usize]
+    }
+}
+
+impl Brain {
+    pub fn upgrade(self) -> brain::Brain {
+        let old = self.program;
+        let program = brain::Program {
+            program: old.program
+                .into_iter()
+                .map(|op| {
+                    brain::Op {
+                        instruction: op.instruction.upgrade(),
+                        first: op.first,
+                        second: op.second,
+                    }
+                })
+                .collect(),
+            mutate_lambda: old.mutate_lambda,
+            crossover_points: old.crossover_points,
+            inputs: old.inputs,
+            outputs: old.outputs,
+        };
+        let mut brain = brain::Brain::from_parts(program, self.memory);
+        brain.upgrade();
+        brain
+    }
+}
+
+#[derive(Deserialize)]
+enum Direction {
+    UpRight,
+    UpLeft,
+    Left,
+    DownLeft,
+    DownRight,
+    Right,
+}
+
+#[derive(Deserialize)]
+enum Choice {
+    Divide { mate: Direction, spawn: Direction },
+    Move(Direction),
+    Explode(bool),
+    Suicide,
+    Nothing,
+}
+
+#[derive(Deserialize)]
+pub struct Decision {
+    choice: Choice,
+    coefficients: [[f64; 8]; 6],
+}
+
+#[derive(Deserialize)]
+struct Mate {
+    mate: (usize, usize),
+    source: (usize, usize),
+}
+
+#[derive(Deserialize)]
+pub struct Delta {
+    movement_attempts: Vec<(usize, usize)>,
+    mate_attempts: Vec<Mate>,
+}
+
+/// Reads a whole headerless file.
+pub fn read<R: Read>(r: &mut R) -> Result<Grid, super::Error> {
+    bincode::deserialize_from(r, bincode::Infinite).map_err(super::Error::NotASave)
+}
@@ -0,0 +1,142 @@
+//! Version 1 files, which stored the grid without its cycle count.
+
+use bincode;
+use fluid;
+use super::v0;
+
+use std::io::Read;
+
+/// The header parameters of versions 1 to 3. They are only read to skip over them.
+#[derive(Deserialize)]
+#[allow(dead_code)]
+pub struct Parameters {
+    width: usize,
+    height: usize,
+    consumption: f64,
+    spawn_rate: f64,
+    inhale_minimum: usize,
+    inhale_cap: usize,
+    movement_cost: usize,
+    divide_cost: usize,
+    explode_requirement: usize,
+    death_release_coefficient: f64,
+    explode_amount: f64,
+    fluid: FluidParams,
+}
+
+/// Fluid parameters have kept this layout in every version since.
+#[derive(Deserialize)]
+pub struct FluidParams {
+    accuracy: f64,
+    timestep: f64,
+    feed_rate: f64,
+    kill_rate: f64,
+    food_diffusion: f64,
+    a_diffusion: f64,
+    b_diffusion: f64,
+    kill_fluid_diffusion: f64,
+    signal_fluid_diffusion: f64,
+    relative_cell_diffusion: [f64; 8],
+    kill_fluid_normal: f64,
+    kill_fluid_decay: f64,
+    kill_fluid_upper_threshold: f64,
+    kill_fluid_lower_threshold: f64,
+    signal_fluid_production: f64,
+    signal_fluid_decay: f64,
+    b_food_rate: f64,
+}
+
+impl FluidParams {
+    /// What the fluid model was hardcoded to before it had parameters.
+    fn original() -> FluidParams {
+        FluidParams {
+            accuracy: 10.0,
+            timestep: 0.2,
+            feed_rate: 0.029,
+            kill_rate: 0.057,
+            food_diffusion: 0.0004,
+            a_diffusion: 1.0,
+            b_diffusion: 0.5,
+            kill_fluid_diffusion: 4.0,
+            signal_fluid_diffusion: 0.06,
+            relative_cell_diffusion: [0.5, 0.5, 0.5, 0.75, 1.0, 1.0, 1.0, 1.0],
+            kill_fluid_normal: 0.05,
+            kill_fluid_decay: 0.15,
+            kill_fluid_upper_threshold: 0.052,
+            kill_fluid_lower_threshold: 0.048,
+            signal_fluid_production: 0.5,
+            signal_fluid_decay: 0.3,
+            b_food_rate: 0.0002,
+        }
+    }
+
+    pub fn upgrade(self) -> fluid::FluidParams {
+        fluid::FluidParams {
+            accuracy: self.accuracy,
+            timestep: self.timestep,
+            feed_rate: self.feed_rate,
+            kill_rate: self.kill_rate,
+            food_diffusion: self.food_diffusion,
+            a_diffusion: self.a_diffusion,
+            b_diffusion: self.b_diffusion,
+            kill_fluid_diffusion: self.kill_fluid_diffusion,
+            signal_fluid_diffusion: self.signal_fluid_diffusion,
+            relative_cell_diffusion: self.relative_cell_diffusion,
+            kill_fluid_normal: self.kill_fluid_normal,
+            kill_fluid_decay: self.kill_fluid_decay,
+            kill_fluid_upper_threshold: self.kill_fluid_upper_threshold,
+            kill_fluid_lower_threshold: self.kill_fluid_lower_threshold,
+            signal_fluid_production: self.signal_fluid_production,
+            signal_fluid_decay: self.signal_fluid_decay,
+            b_food_rate: self.b_food_rate,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Grid {
+    pub spawning: bool,
+    pub width: usize,
+    pub height: usize,
+    pub consumption: f64,
+    pub spawn_rate: f64,
+    pub inhale_minimum: usize,
+    pub inhale_cap: usize,
+    pub movement_cost: usize,
+    pub divide_cost: usize,
+    pub explode_requirement: usize,
+    pub death_release_coefficient: f64,
+    pub explode_amount: f64,
+    pub fluid: FluidParams,
+    pub tiles: Vec<v0::Hex>,
+}
+
+impl From<v0::Grid> for Grid {
+    fn from(old: v0::Grid) -> Self {
+        Grid {
+            spawning: old.spawning,
+            width: old.width,
+            height: old.height,
+            consumption: old.consumption,
+            spawn_rate: old.spawn_rate,
+            inhale_minimum: old.inhale_minimum,
+            inhale_cap: old.inhale_cap,
+            movement_cost: old.movement_cost,
+            divide_cost: old.divide_cost,
+            explode_requirement: old.explode_requirement,
+            death_release_coefficient: old.death_release_coefficient,
+            explode_amount: old.explode_amount,
+            fluid: FluidParams::original(),
+            tiles: old.tiles,
+        }
+    }
+}
+
+/// Reads the rest of a file of this version or an older one after its header.
+pub fn read<R: Read>(version: u32, r: &mut R) -> Result<Grid, super::Error> {
+    if version < 1 {
+        return v0::read(r).map(Grid::from);
+    }
+    let _: Parameters = bincode::deserialize_from(r, bincode::Infinite)?;
+    Ok(bincode::deserialize_from(r, bincode::Infinite)?)
+}
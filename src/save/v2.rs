@@ -0,0 +1,57 @@
+//! Version 2 files, which stored the grid without the seed of its random number generator.
+
+use bincode;
+use super::{v0, v1};
+
+use std::io::Read;
+
+#[derive(Deserialize)]
+pub struct Grid {
+    pub spawning: bool,
+    pub width: usize,
+    pub height: usize,
+    pub consumption: f64,
+    pub spawn_rate: f64,
+    pub inhale_minimum: usize,
+    pub inhale_cap: usize,
+    pub movement_cost: usize,
+    pub divide_cost: usize,
+    pub explode_requirement: usize,
+    pub death_release_coefficient: f64,
+    pub explode_amount: f64,
+    pub fluid: v1::FluidParams,
+    pub cycle: u64,
+    pub tiles: Vec<v0::Hex>,
+}
+
+impl From<v1::Grid> for Grid {
+    fn from(old: v1::Grid) -> Self {
+        Grid {
+            spawning: old.spawning,
+            width: old.width,
+            height: old.height,
+            consumption: old.consumption,
+            spawn_rate: old.spawn_rate,
+            inhale_minimum: old.inhale_minimum,
+            inhale_cap: old.inhale_cap,
+            movement_cost: old.movement_cost,
+            divide_cost: old.divide_cost,
+            explode_requirement: old.explode_requirement,
+            death_release_coefficient: old.death_release_coefficient,
+            explode_amount: old.explode_amount,
+            fluid: old.fluid,
+            // The count wasn't kept, so start over.
+            cycle: 0,
+            tiles: old.tiles,
+        }
+    }
+}
+
+/// Reads the rest of a file of this version or an older one after its header.
+pub fn read<R: Read>(version: u32, r: &mut R) -> Result<Grid, super::Error> {
+    if version < 2 {
+        return v1::read(version, r).map(Grid::from);
+    }
+    let _: v1::Parameters = bincode::deserialize_from(r, bincode::Infinite)?;
+    Ok(bincode::deserialize_from(r, bincode::Infinite)?)
+}
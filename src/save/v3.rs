@@ -0,0 +1,59 @@
+//! Version 3 files, which had no boundary setting because the grid was always a torus.
+
+use bincode;
+use super::{v0, v1, v2};
+
+use std::io::Read;
+
+#[derive(Deserialize)]
+pub struct Grid {
+    pub spawning: bool,
+    pub width: usize,
+    pub height: usize,
+    pub consumption: f64,
+    pub spawn_rate: f64,
+    pub inhale_minimum: usize,
+    pub inhale_cap: usize,
+    pub movement_cost: usize,
+    pub divide_cost: usize,
+    pub explode_requirement: usize,
+    pub death_release_coefficient: f64,
+    pub explode_amount: f64,
+    pub fluid: v1::FluidParams,
+    pub cycle: u64,
+    pub seed: [u64; 4],
+    pub tiles: Vec<v0::Hex>,
+}
+
+impl From<v2::Grid> for Grid {
+    fn from(old: v2::Grid) -> Self {
+        Grid {
+            spawning: old.spawning,
+            width: old.width,
+            height: old.height,
+            consumption: old.consumption,
+            spawn_rate: old.spawn_rate,
+            inhale_minimum: old.inhale_minimum,
+            inhale_cap: old.inhale_cap,
+            movement_cost: old.movement_cost,
+            divide_cost: old.divide_cost,
+            explode_requirement: old.explode_requirement,
+            death_release_coefficient: old.death_release_coefficient,
+            explode_amount: old.explode_amount,
+            fluid: old.fluid,
+            cycle: old.cycle,
+            // Derive a seed so that grids saved at different cycles don't share their future.
+            seed: [0, 0, 0, old.cycle],
+            tiles: old.tiles,
+        }
+    }
+}
+
+/// Reads the rest of a file of this version or an older one after its header.
+pub fn read<R: Read>(version: u32, r: &mut R) -> Result<Grid, super::Error> {
+    if version < 3 {
+        return v2::read(version, r).map(Grid::from);
+    }
+    let _: v1::Parameters = bincode::deserialize_from(r, bincode::Infinite)?;
+    Ok(bincode::deserialize_from(r, bincode::Infinite)?)
+}
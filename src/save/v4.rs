@@ -0,0 +1,101 @@
+//! Version 4 files, which had no terrain.
+
+use bincode;
+use grid;
+use super::{v0, v1, v3};
+
+use std::io::Read;
+
+/// The header parameters of versions 4 to 6. They are only read to skip over them.
+#[derive(Deserialize)]
+#[allow(dead_code)]
+pub struct Parameters {
+    width: usize,
+    height: usize,
+    consumption: f64,
+    spawn_rate: f64,
+    inhale_minimum: usize,
+    inhale_cap: usize,
+    movement_cost: usize,
+    divide_cost: usize,
+    explode_requirement: usize,
+    death_release_coefficient: f64,
+    explode_amount: f64,
+    boundary: Boundary,
+    fluid: v1::FluidParams,
+}
+
+/// Boundaries have kept this layout in every version since.
+#[derive(Deserialize)]
+pub enum Boundary {
+    Toroidal,
+    Wall,
+    Absorbing,
+    Reflecting,
+}
+
+impl Boundary {
+    pub fn upgrade(self) -> grid::Boundary {
+        match self {
+            Boundary::Toroidal => grid::Boundary::Toroidal,
+            Boundary::Wall => grid::Boundary::Wall,
+            Boundary::Absorbing => grid::Boundary::Absorbing,
+            Boundary::Reflecting => grid::Boundary::Reflecting,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Grid {
+    pub spawning: bool,
+    pub width: usize,
+    pub height: usize,
+    pub consumption: f64,
+    pub spawn_rate: f64,
+    pub inhale_minimum: usize,
+    pub inhale_cap: usize,
+    pub movement_cost: usize,
+    pub divide_cost: usize,
+    pub explode_requirement: usize,
+    pub death_release_coefficient: f64,
+    pub explode_amount: f64,
+    pub boundary: Boundary,
+    pub fluid: v1::FluidParams,
+    pub cycle: u64,
+    pub seed: [u64; 4],
+    pub tiles: Vec<v0::Hex>,
+}
+
+impl From<v3::Grid> for Grid {
+    fn from(old: v3::Grid) -> Self {
+        Grid {
+            spawning: old.spawning,
+            width: old.width,
+            height: old.height,
+            consumption: old.consumption,
+            spawn_rate: old.spawn_rate,
+            inhale_minimum: old.inhale_minimum,
+            inhale_cap: old.inhale_cap,
+            movement_cost: old.movement_cost,
+            divide_cost: old.divide_cost,
+            explode_requirement: old.explode_requirement,
+            death_release_coefficient: old.death_release_coefficient,
+            explode_amount: old.explode_amount,
+            // The grid was always a torus.
+            boundary: Boundary::Toroidal,
+            fluid: old.fluid,
+            cycle: old.cycle,
+            seed: old.seed,
+            tiles: old.tiles,
+        }
+    }
+}
+
+/// Reads the rest of a file of this version or an older one after its header.
+pub fn read<R: Read>(version: u32, r: &mut R) -> Result<Grid, super::Error> {
+    if version < 4 {
+        return v3::read(version, r).map(Grid::from);
+    }
+    let _: Parameters = bincode::deserialize_from(r, bincode::Infinite)?;
+    Ok(bincode::deserialize_from(r, bincode::Infinite)?)
+}
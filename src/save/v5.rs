@@ -0,0 +1,103 @@
+//! Version 5 files, whose cells had no identity.
+
+use bincode;
+use terrain;
+use super::{v0, v1, v4};
+
+use std::io::Read;
+
+#[derive(Deserialize)]
+pub struct Grid {
+    pub spawning: bool,
+    pub width: usize,
+    pub height: usize,
+    pub consumption: f64,
+    pub spawn_rate: f64,
+    pub inhale_minimum: usize,
+    pub inhale_cap: usize,
+    pub movement_cost: usize,
+    pub divide_cost: usize,
+    pub explode_requirement: usize,
+    pub death_release_coefficient: f64,
+    pub explode_amount: f64,
+    pub boundary: v4::Boundary,
+    pub fluid: v1::FluidParams,
+    pub cycle: u64,
+    pub seed: [u64; 4],
+    pub tiles: Vec<Hex>,
+}
+
+/// Tiles kept this layout up to and including version 5.
+#[derive(Deserialize)]
+pub struct Hex {
+    pub solution: v0::Solution,
+    pub cell: Option<Box<v0::Cell>>,
+    pub decision: Option<v0::Decision>,
+    pub delta: v0::Delta,
+    pub terrain: Terrain,
+}
+
+/// Terrain has kept this layout in every version since.
+#[derive(Clone, Copy, Deserialize)]
+pub enum Terrain {
+    Open,
+    Rock,
+    Membrane,
+    Soil,
+}
+
+impl Terrain {
+    pub fn upgrade(self) -> terrain::Terrain {
+        match self {
+            Terrain::Open => terrain::Terrain::Open,
+            Terrain::Rock => terrain::Terrain::Rock,
+            Terrain::Membrane => terrain::Terrain::Membrane,
+            Terrain::Soil => terrain::Terrain::Soil,
+        }
+    }
+}
+
+impl From<v4::Grid> for Grid {
+    fn from(old: v4::Grid) -> Self {
+        Grid {
+            spawning: old.spawning,
+            width: old.width,
+            height: old.height,
+            consumption: old.consumption,
+            spawn_rate: old.spawn_rate,
+            inhale_minimum: old.inhale_minimum,
+            inhale_cap: old.inhale_cap,
+            movement_cost: old.movement_cost,
+            divide_cost: old.divide_cost,
+            explode_requirement: old.explode_requirement,
+            death_release_coefficient: old.death_release_coefficient,
+            explode_amount: old.explode_amount,
+            boundary: old.boundary,
+            fluid: old.fluid,
+            cycle: old.cycle,
+            seed: old.seed,
+            // All ground was open.
+            tiles: old.tiles
+                .into_iter()
+                .map(|hex| {
+                    Hex {
+                        solution: hex.solution,
+                        cell: hex.cell,
+                        decision: hex.decision,
+                        delta: hex.delta,
+                        terrain: Terrain::Open,
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Reads the rest of a file of this version or an older one after its header.
+pub fn read<R: Read>(version: u32, r: &mut R) -> Result<Grid, super::Error> {
+    if version < 5 {
+        return v4::read(version, r).map(Grid::from);
+    }
+    let _: v4::Parameters = bincode::deserialize_from(r, bincode::Infinite)?;
+    Ok(bincode::deserialize_from(r, bincode::Infinite)?)
+}
@@ -0,0 +1,141 @@
+//! Version 6 files, which had no instruction set.
+
+use bincode;
+use cell;
+use super::{v0, v1, v4, v5};
+
+use std::io::Read;
+
+#[derive(Deserialize)]
+pub struct Grid {
+    pub spawning: bool,
+    pub width: usize,
+    pub height: usize,
+    pub consumption: f64,
+    pub spawn_rate: f64,
+    pub inhale_minimum: usize,
+    pub inhale_cap: usize,
+    pub movement_cost: usize,
+    pub divide_cost: usize,
+    pub explode_requirement: usize,
+    pub death_release_coefficient: f64,
+    pub explode_amount: f64,
+    pub boundary: v4::Boundary,
+    pub fluid: v1::FluidParams,
+    pub cycle: u64,
+    pub seed: [u64; 4],
+    pub next_id: u64,
+    pub tiles: Vec<Hex>,
+}
+
+/// Tiles kept this layout from version 6 on.
+#[derive(Deserialize)]
+#[allow(dead_code)]
+pub struct Hex {
+    pub solution: v0::Solution,
+    pub cell: Option<Box<Cell>>,
+    // Decisions are taken out during every cycle, so saved tiles never have one.
+    pub decision: Option<v0::Decision>,
+    pub delta: v0::Delta,
+    pub terrain: v5::Terrain,
+}
+
+/// Cells kept this layout from version 6 on.
+#[derive(Deserialize)]
+pub struct Cell {
+    pub inhale: usize,
+    pub suicide: bool,
+    pub brain: v0::Brain,
+    pub turn: usize,
+    pub lineage: Lineage,
+}
+
+/// Lineages have kept this layout in every version since.
+#[derive(Deserialize)]
+pub struct Lineage {
+    id: u64,
+    parents: Parents,
+    birth: u64,
+}
+
+#[derive(Deserialize)]
+enum Parents {
+    None,
+    Divided(u64),
+    Mated(u64, u64),
+}
+
+impl Lineage {
+    pub fn upgrade(self) -> cell::Lineage {
+        cell::Lineage {
+            id: self.id,
+            parents: match self.parents {
+                Parents::None => cell::Parents::None,
+                Parents::Divided(parent) => cell::Parents::Divided(parent),
+                Parents::Mated(a, b) => cell::Parents::Mated(a, b),
+            },
+            birth: self.birth,
+        }
+    }
+}
+
+impl From<v5::Grid> for Grid {
+    fn from(old: v5::Grid) -> Self {
+        // Number the cells in order as if they were all born when the grid was saved.
+        let (cycle, mut next_id) = (old.cycle, 0);
+        let tiles = old.tiles
+            .into_iter()
+            .map(|hex| {
+                Hex {
+                    solution: hex.solution,
+                    cell: hex.cell.map(|c| {
+                        next_id += 1;
+                        Box::new(Cell {
+                            inhale: c.inhale,
+                            suicide: c.suicide,
+                            brain: c.brain,
+                            turn: c.turn,
+                            lineage: Lineage {
+                                id: next_id - 1,
+                                parents: Parents::None,
+                                birth: cycle,
+                            },
+                        })
+                    }),
+                    decision: hex.decision,
+                    delta: hex.delta,
+                    terrain: hex.terrain,
+                }
+            })
+            .collect();
+        Grid {
+            spawning: old.spawning,
+            width: old.width,
+            height: old.height,
+            consumption: old.consumption,
+            spawn_rate: old.spawn_rate,
+            inhale_minimum: old.inhale_minimum,
+            inhale_cap: old.inhale_cap,
+            movement_cost: old.movement_cost,
+            divide_cost: old.divide_cost,
+            explode_requirement: old.explode_requirement,
+            death_release_coefficient: old.death_release_coefficient,
+            explode_amount: old.explode_amount,
+            boundary: old.boundary,
+            fluid: old.fluid,
+            cycle: old.cycle,
+            seed: old.seed,
+            next_id: next_id,
+            tiles: tiles,
+        }
+    }
+}
+
+/// Reads the rest of a file of this version or an older one after its header.
+pub fn read<R: Read>(version: u32, r: &mut R) -> Result<Grid, super::Error> {
+    if version < 6 {
+        return v5::read(version, r).map(Grid::from);
+    }
+    let _: v4::Parameters = bincode::deserialize_from(r, bincode::Infinite)?;
+    Ok(bincode::deserialize_from(r, bincode::Infinite)?)
+}
@@ -0,0 +1,122 @@
+//! Version 7 files, which had no conflict policy.
+
+use bincode;
+use cell::brain;
+use super::{v0, v1, v4, v6};
+
+use std::io::Read;
+
+/// The header parameters of version 7. They are only read to skip over them.
+#[derive(Deserialize)]
+#[allow(dead_code)]
+pub struct Parameters {
+    width: usize,
+    height: usize,
+    consumption: f64,
+    spawn_rate: f64,
+    inhale_minimum: usize,
+    inhale_cap: usize,
+    movement_cost: usize,
+    divide_cost: usize,
+    explode_requirement: usize,
+    death_release_coefficient: f64,
+    explode_amount: f64,
+    boundary: v4::Boundary,
+    fluid: v1::FluidParams,
+    instructions: InstructionSet,
+}
+
+/// Instruction sets have kept this layout in every version since.
+#[derive(Deserialize)]
+pub struct InstructionSet {
+    weights: Vec<(v0::Ins, f64)>,
+}
+
+impl InstructionSet {
+    /// The set brains were built from before it could be changed.
+    fn original() -> InstructionSet {
+        let ins = [
+            v0::Ins::Nop,
+            v0::Ins::Add,
+            v0::Ins::Sub,
+            v0::Ins::Mul,
+            v0::Ins::Div,
+            v0::Ins::Mod,
+            v0::Ins::Grt,
+            v0::Ins::Les,
+            v0::Ins::And,
+            v0::Ins::Or,
+            v0::Ins::Pow,
+            v0::Ins::Exp,
+            v0::Ins::Log,
+            v0::Ins::Ln,
+            v0::Ins::Sin,
+            v0::Ins::Sqt,
+        ];
+        InstructionSet { weights: ins.iter().map(|&i| (i, 1.0)).collect() }
+    }
+
+    pub fn upgrade(self) -> Result<brain::InstructionSet, super::Error> {
+        brain::InstructionSet::new(self.weights.into_iter().map(|(i, w)| (i.upgrade(), w)))
+            .map_err(super::Error::Corrupt)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Grid {
+    pub spawning: bool,
+    pub width: usize,
+    pub height: usize,
+    pub consumption: f64,
+    pub spawn_rate: f64,
+    pub inhale_minimum: usize,
+    pub inhale_cap: usize,
+    pub movement_cost: usize,
+    pub divide_cost: usize,
+    pub explode_requirement: usize,
+    pub death_release_coefficient: f64,
+    pub explode_amount: f64,
+    pub boundary: v4::Boundary,
+    pub fluid: v1::FluidParams,
+    pub instructions: InstructionSet,
+    pub cycle: u64,
+    pub seed: [u64; 4],
+    pub next_id: u64,
+    pub tiles: Vec<v6::Hex>,
+}
+
+impl From<v6::Grid> for Grid {
+    fn from(old: v6::Grid) -> Self {
+        Grid {
+            spawning: old.spawning,
+            width: old.width,
+            height: old.height,
+            consumption: old.consumption,
+            spawn_rate: old.spawn_rate,
+            inhale_minimum: old.inhale_minimum,
+            inhale_cap: old.inhale_cap,
+            movement_cost: old.movement_cost,
+            divide_cost: old.divide_cost,
+            explode_requirement: old.explode_requirement,
+            death_release_coefficient: old.death_release_coefficient,
+            explode_amount: old.explode_amount,
+            boundary: old.boundary,
+            fluid: old.fluid,
+            // Brains were only ever built from the original instructions.
+            instructions: InstructionSet::original(),
+            cycle: old.cycle,
+            seed: old.seed,
+            next_id: old.next_id,
+            tiles: old.tiles,
+        }
+    }
+}
+
+/// Reads the rest of a file of this version or an older one after its header.
+pub fn read<R: Read>(version: u32, r: &mut R) -> Result<Grid, super::Error> {
+    if version < 7 {
+        return v6::read(version, r).map(Grid::from);
+    }
+    let _: Parameters = bincode::deserialize_from(r, bincode::Infinite)?;
+    Ok(bincode::deserialize_from(r, bincode::Infinite)?)
+}
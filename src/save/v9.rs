@@ -0,0 +1,91 @@
+//! Version 9 files, whose mates never had to consent.
+
+use bincode;
+use super::{v1, v4, v6, v7, v8};
+
+use std::io::Read;
+
+/// The header parameters of version 9. They are only read to skip over them.
+#[derive(Deserialize)]
+#[allow(dead_code)]
+pub struct Parameters {
+    width: usize,
+    height: usize,
+    consumption: f64,
+    spawn_rate: f64,
+    inhale_minimum: usize,
+    inhale_cap: usize,
+    movement_cost: usize,
+    divide_cost: usize,
+    explode_requirement: usize,
+    death_release_coefficient: f64,
+    explode_amount: f64,
+    boundary: v4::Boundary,
+    fluid: v1::FluidParams,
+    instructions: v7::InstructionSet,
+    conflicts: v8::Conflicts,
+    recycle_costs: bool,
+}
+
+#[derive(Deserialize)]
+pub struct Grid {
+    pub spawning: bool,
+    pub width: usize,
+    pub height: usize,
+    pub consumption: f64,
+    pub spawn_rate: f64,
+    pub inhale_minimum: usize,
+    pub inhale_cap: usize,
+    pub movement_cost: usize,
+    pub divide_cost: usize,
+    pub explode_requirement: usize,
+    pub death_release_coefficient: f64,
+    pub explode_amount: f64,
+    pub boundary: v4::Boundary,
+    pub fluid: v1::FluidParams,
+    pub instructions: v7::InstructionSet,
+    pub conflicts: v8::Conflicts,
+    pub recycle_costs: bool,
+    pub cycle: u64,
+    pub seed: [u64; 4],
+    pub next_id: u64,
+    pub tiles: Vec<v6::Hex>,
+}
+
+impl From<v8::Grid> for Grid {
+    fn from(old: v8::Grid) -> Self {
+        Grid {
+            spawning: old.spawning,
+            width: old.width,
+            height: old.height,
+            consumption: old.consumption,
+            spawn_rate: old.spawn_rate,
+            inhale_minimum: old.inhale_minimum,
+            inhale_cap: old.inhale_cap,
+            movement_cost: old.movement_cost,
+            divide_cost: old.divide_cost,
+            explode_requirement: old.explode_requirement,
+            death_release_coefficient: old.death_release_coefficient,
+            explode_amount: old.explode_amount,
+            boundary: old.boundary,
+            fluid: old.fluid,
+            instructions: old.instructions,
+            conflicts: old.conflicts,
+            // Costs were always lost.
+            recycle_costs: false,
+            cycle: old.cycle,
+            seed: old.seed,
+            next_id: old.next_id,
+            tiles: old.tiles,
+        }
+    }
+}
+
+/// Reads the rest of a file of this version or an older one after its header.
+pub fn read<R: Read>(version: u32, r: &mut R) -> Result<Grid, super::Error> {
+    if version < 9 {
+        return v8::read(version, r).map(Grid::from);
+    }
+    let _: Parameters = bincode::deserialize_from(r, bincode::Infinite)?;
+    Ok(bincode::deserialize_from(r, bincode::Infinite)?)
+}
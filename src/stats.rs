@@ -0,0 +1,410 @@
+//! Time series of population statistics for plotting.
+//!
+//! Every `every` cycles a `Sample` of the grid is appended to a file as a CSV row or a JSON line,
+//! chosen by the file's extension. Counts of births and deaths and the flows of energy cover the
+//! cycles since the previous sample, as do the counts of group moves and torn bonds.
+
+use events::DeathCause;
+use grid::{Counters, Grid};
+use serde_json;
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+const CSV_HEADER: &str = "cycle,population,spawned,divided,mated,deaths,died_suicide,\
+                          died_kill_high,died_kill_low,died_inhale_minimum,died_starvation,\
+                          died_left_world,died_buried,inhale_mean,inhale_p10,inhale_median,\
+                          inhale_p90,food,a,b,kill_variance,energy,energy_produced,energy_fed,\
+                          energy_spawned,energy_released,energy_recycled,energy_wasted,\
+                          energy_burned,energy_moved,energy_births,energy_conflicts,energy_died,\
+                          energy_lost,groups,grouped,largest_group,mean_group_size,kin_bonds,\
+                          group_moves,torn";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Sample {
+    pub cycle: u64,
+    pub population: usize,
+    pub spawned: u64,
+    pub divided: u64,
+    pub mated: u64,
+    pub deaths: u64,
+    pub died_suicide: u64,
+    /// Killed by kill fluid above the upper threshold.
+    pub died_kill_high: u64,
+    /// Killed by kill fluid below the lower threshold.
+    pub died_kill_low: u64,
+    pub died_inhale_minimum: u64,
+    pub died_starvation: u64,
+    /// Moved over an absorbing edge.
+    pub died_left_world: u64,
+    /// On a tile that terrain made impassable.
+    pub died_buried: u64,
+    pub inhale_mean: f64,
+    pub inhale_p10: usize,
+    pub inhale_median: usize,
+    pub inhale_p90: usize,
+    /// Total food (fluid 0) over all tiles.
+    pub food: f64,
+    /// Total chemical A over all tiles.
+    pub a: f64,
+    /// Total chemical B over all tiles.
+    pub b: f64,
+    /// Variance of kill fluid between tiles.
+    pub kill_variance: f64,
+    /// Total energy over all tiles, see `Grid::energy`.
+    pub energy: f64,
+    /// The rest are the flows of energy in the `Ledger` of the same names.
+    pub energy_produced: f64,
+    pub energy_fed: f64,
+    pub energy_spawned: f64,
+    pub energy_released: f64,
+    pub energy_recycled: f64,
+    pub energy_wasted: f64,
+    pub energy_burned: f64,
+    pub energy_moved: f64,
+    pub energy_births: f64,
+    pub energy_conflicts: f64,
+    pub energy_died: f64,
+    pub energy_lost: f64,
+    /// Groups of more than one cell joined by bonds.
+    pub groups: usize,
+    /// Cells in those groups.
+    pub grouped: usize,
+    pub largest_group: usize,
+    pub mean_group_size: f64,
+    /// The fraction of bonds that join a parent and its child or two cells with a parent in
+    /// common.
+    pub kin_bonds: f64,
+    /// Groups that moved together.
+    pub group_moves: u64,
+    /// Bonds torn by cells moving away from each other.
+    pub torn: u64,
+}
+
+impl Sample {
+    /// Takes a sample of the grid. `since` holds the counters and population of the grid at the
+    /// previous sample.
+    fn new(grid: &Grid, since: &Counters, population: usize) -> Sample {
+        let mut inhales: Vec<usize> = grid.tiles
+            .iter()
+            .filter_map(|t| t.cell.as_ref().map(|c| c.inhale))
+            .collect();
+        inhales.sort();
+        let percentile = |p: usize| if inhales.is_empty() {
+            0
+        } else {
+            inhales[(inhales.len() - 1) * p / 100]
+        };
+        let inhale_mean = if inhales.is_empty() {
+            0.0
+        } else {
+            inhales.iter().map(|&i| i as f64).sum::<f64>() / inhales.len() as f64
+        };
+
+        let mut totals = [0.0; 3];
+        let mut kill_sum = 0.0;
+        let mut kill_square_sum = 0.0;
+        for t in &grid.tiles {
+            for (total, fluid) in totals.iter_mut().zip(&t.solution.fluids) {
+                *total += fluid;
+            }
+            let kill = t.solution.fluids[3];
+            kill_sum += kill;
+            kill_square_sum += kill * kill;
+        }
+        let n = grid.tiles.len() as f64;
+        let kill_mean = kill_sum / n;
+
+        let sizes: Vec<usize> = grid.groups()
+            .members
+            .iter()
+            .map(|m| m.len())
+            .filter(|&n| n > 1)
+            .collect();
+        let grouped: usize = sizes.iter().sum();
+        let bonds = grid.bonds();
+        let lineage = |i: usize| grid.tiles[i].cell.as_ref().unwrap().lineage;
+        let kin = bonds.iter().filter(|&&(a, b)| lineage(a).kin(&lineage(b))).count();
+
+        let spawned = grid.counters.spawned - since.spawned;
+        let divided = grid.counters.divided - since.divided;
+        let mated = grid.counters.mated - since.mated;
+        let flows = grid.counters.energy.since(&since.energy);
+        let died = |cause: DeathCause| {
+            grid.counters.deaths[cause as usize] - since.deaths[cause as usize]
+        };
+        Sample {
+            cycle: grid.cycle,
+            population: inhales.len(),
+            spawned: spawned,
+            divided: divided,
+            mated: mated,
+            // Whatever isn't here any more out of what was here or was born since has died.
+            deaths: (population as u64 + spawned + divided + mated).saturating_sub(
+                inhales.len() as u64,
+            ),
+            died_suicide: died(DeathCause::Suicide),
+            died_kill_high: died(DeathCause::KillHigh),
+            died_kill_low: died(DeathCause::KillLow),
+            died_inhale_minimum: died(DeathCause::InhaleMinimum),
+            died_starvation: died(DeathCause::Starvation),
+            died_left_world: died(DeathCause::LeftWorld),
+            died_buried: died(DeathCause::Buried),
+            inhale_mean: inhale_mean,
+            inhale_p10: percentile(10),
+            inhale_median: percentile(50),
+            inhale_p90: percentile(90),
+            food: totals[0],
+            a: totals[1],
+            b: totals[2],
+            kill_variance: kill_square_sum / n - kill_mean * kill_mean,
+            energy: grid.energy(),
+            energy_produced: flows.produced,
+            energy_fed: flows.fed,
+            energy_spawned: flows.spawned,
+            energy_released: flows.released,
+            energy_recycled: flows.recycled,
+            energy_wasted: flows.wasted,
+            energy_burned: flows.burned,
+            energy_moved: flows.moved,
+            energy_births: flows.births,
+            energy_conflicts: flows.conflicts,
+            energy_died: flows.died,
+            energy_lost: flows.lost,
+            groups: sizes.len(),
+            grouped: grouped,
+            largest_group: sizes.iter().cloned().max().unwrap_or(0),
+            mean_group_size: if sizes.is_empty() {
+                0.0
+            } else {
+                grouped as f64 / sizes.len() as f64
+            },
+            kin_bonds: if bonds.is_empty() {
+                0.0
+            } else {
+                kin as f64 / bonds.len() as f64
+            },
+            group_moves: grid.counters.group_moves - since.group_moves,
+            torn: grid.counters.torn - since.torn,
+        }
+    }
+
+    fn write_csv<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        writeln!(
+            w,
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},\
+             {},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            self.cycle,
+            self.population,
+            self.spawned,
+            self.divided,
+            self.mated,
+            self.deaths,
+            self.died_suicide,
+            self.died_kill_high,
+            self.died_kill_low,
+            self.died_inhale_minimum,
+            self.died_starvation,
+            self.died_left_world,
+            self.died_buried,
+            self.inhale_mean,
+            self.inhale_p10,
+            self.inhale_median,
+            self.inhale_p90,
+            self.food,
+            self.a,
+            self.b,
+            self.kill_variance,
+            self.energy,
+            self.energy_produced,
+            self.energy_fed,
+            self.energy_spawned,
+            self.energy_released,
+            self.energy_recycled,
+            self.energy_wasted,
+            self.energy_burned,
+            self.energy_moved,
+            self.energy_births,
+            self.energy_conflicts,
+            self.energy_died,
+            self.energy_lost,
+            self.groups,
+            self.grouped,
+            self.largest_group,
+            self.mean_group_size,
+            self.kin_bonds,
+            self.group_moves,
+            self.torn
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    JsonLines,
+}
+
+impl Format {
+    /// CSV for files ending in `.csv` and JSON Lines for anything else.
+    pub fn from_path(path: &Path) -> Format {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("csv") => Format::Csv,
+            _ => Format::JsonLines,
+        }
+    }
+}
+
+/// Appends samples of a running grid to a file.
+pub struct Recorder {
+    file: BufWriter<File>,
+    format: Format,
+    every: u64,
+    counters: Counters,
+    population: usize,
+}
+
+impl Recorder {
+    /// Opens `path` for appending samples of `grid` taken every `every` cycles.
+    ///
+    /// A CSV file that already has rows under a different header is refused, since its columns
+    /// would no longer line up. JSON lines name their fields, so they can be mixed.
+    pub fn open<P: AsRef<Path>>(path: P, every: u64, grid: &Grid) -> io::Result<Recorder> {
+        let path = path.as_ref();
+        let file = OpenOptions::new().read(true).append(true).create(true).open(path)?;
+        let empty = file.metadata()?.len() == 0;
+        if !empty && Format::from_path(path) == Format::Csv {
+            let mut header = String::new();
+            BufReader::new(&file).read_line(&mut header)?;
+            if header.trim_end() != CSV_HEADER {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "its columns differ from the ones this build records, so move it away or \
+                     record to another file",
+                ));
+            }
+        }
+        let mut recorder = Recorder {
+            file: BufWriter::new(file),
+            format: Format::from_path(path),
+            every: every,
+            counters: grid.counters.clone(),
+            population: grid.population(),
+        };
+        if empty && recorder.format == Format::Csv {
+            writeln!(recorder.file, "{}", CSV_HEADER)?;
+        }
+        Ok(recorder)
+    }
+
+    /// Writes a sample if one is due on the grid's current cycle. Call this after every cycle.
+    pub fn update(&mut self, grid: &Grid) -> io::Result<()> {
+        if self.every == 0 || grid.cycle % self.every != 0 {
+            return Ok(());
+        }
+        let sample = Sample::new(grid, &self.counters, self.population);
+        match self.format {
+            Format::Csv => sample.write_csv(&mut self.file)?,
+            Format::JsonLines => {
+                serde_json::to_writer(&mut self.file, &sample).map_err(io::Error::from)?;
+                writeln!(self.file)?;
+            }
+        }
+        self.file.flush()?;
+        self.counters = grid.counters.clone();
+        self.population = sample.population;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cell::brain::InstructionSet;
+    use fluid::FluidParams;
+    use grid::Boundary;
+    use rand::{Isaac64Rng, SeedableRng};
+    use std::env;
+    use std::fs;
+    use std::io::Read;
+    use std::process;
+
+    fn grid() -> Grid {
+        let mut rng = Isaac64Rng::from_seed(&[13]);
+        Grid::new(
+            4,
+            3,
+            0.04,
+            1.0,
+            500,
+            10000,
+            10,
+            50,
+            2100,
+            0.5,
+            0.5,
+            Boundary::Wall,
+            FluidParams::default(),
+            InstructionSet::default(),
+            &mut rng,
+        )
+    }
+
+    /// Records `cycles` cycles of `g` to `path`, sampling every other cycle, and returns the
+    /// lines of the file.
+    fn record(g: &mut Grid, path: &Path, cycles: usize) -> Vec<String> {
+        let mut recorder = Recorder::open(path, 2, g).unwrap();
+        for _ in 0..cycles {
+            g.cycle();
+            recorder.update(g).unwrap();
+        }
+        let mut contents = String::new();
+        File::open(path).unwrap().read_to_string(&mut contents).unwrap();
+        contents.lines().map(String::from).collect()
+    }
+
+    #[test]
+    fn csv_rows_line_up_with_the_header() {
+        let path = env::temp_dir().join(format!("evomata-stats-{}.csv", process::id()));
+        let _ = fs::remove_file(&path);
+        let mut g = grid();
+        let lines = record(&mut g, &path, 7);
+        assert_eq!(lines.len(), 1 + 3);
+        assert_eq!(lines[0], CSV_HEADER);
+        let columns = CSV_HEADER.split(',').count();
+        assert!(lines.iter().all(|l| l.split(',').count() == columns));
+        let cycles: Vec<&str> = lines[1..].iter().map(|l| l.split(',').next().unwrap()).collect();
+        assert_eq!(cycles, ["2", "4", "6"]);
+
+        // Resuming appends under the same header.
+        let lines = record(&mut g, &path, 2);
+        assert_eq!(lines.len(), 1 + 4);
+        assert_eq!(lines.iter().filter(|&l| l == CSV_HEADER).count(), 1);
+
+        // A file with other columns is left alone.
+        File::create(&path).unwrap().write_all(b"cycle,population\n1,2\n").unwrap();
+        assert!(Recorder::open(&path, 2, &g).is_err());
+        let mut contents = String::new();
+        File::open(&path).unwrap().read_to_string(&mut contents).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(contents, "cycle,population\n1,2\n");
+    }
+
+    #[test]
+    fn json_lines_hold_every_field() {
+        let path = env::temp_dir().join(format!("evomata-stats-{}.jsonl", process::id()));
+        let _ = fs::remove_file(&path);
+        let lines = record(&mut grid(), &path, 6);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(lines.len(), 3);
+        for line in &lines {
+            let sample: serde_json::Value = serde_json::from_str(line).unwrap();
+            let fields = sample.as_object().unwrap();
+            assert_eq!(fields.len(), CSV_HEADER.split(',').count());
+            for column in CSV_HEADER.split(',') {
+                assert!(fields.contains_key(column), "{}", column);
+            }
+        }
+    }
+}
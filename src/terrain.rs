@@ -0,0 +1,270 @@
+//! The ground each hex sits on.
+//!
+//! Terrain is drawn as a PNG mask where every pixel is matched to the terrain with the closest
+//! color in `MASK_COLORS`, so black is rock, white is open ground, blue is membrane and green is
+//! soil. Transparent pixels are open ground. The mask is stretched to cover the whole grid, so a
+//! small drawing can be used for a large grid.
+
+use png;
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+const MASK_COLORS: [([u8; 3], Terrain); 4] = [
+    ([255, 255, 255], Terrain::Open),
+    ([0, 0, 0], Terrain::Rock),
+    ([0, 0, 255], Terrain::Membrane),
+    ([0, 255, 0], Terrain::Soil),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Terrain {
+    Open,
+    /// Nothing moves through rock and nothing grows on it.
+    Rock,
+    /// Lets fluids through slowly, but not cells.
+    Membrane,
+    /// Open ground where chemical B produces extra food.
+    Soil,
+}
+
+impl Default for Terrain {
+    fn default() -> Self {
+        Terrain::Open
+    }
+}
+
+impl Terrain {
+    /// Multiplies the diffusion coefficients between this hex and its neighbors. Between two
+    /// different terrains the smaller one applies.
+    pub fn diffusion(self) -> f64 {
+        match self {
+            Terrain::Open | Terrain::Soil => 1.0,
+            Terrain::Rock => 0.0,
+            Terrain::Membrane => 0.25,
+        }
+    }
+
+    /// Multiplies `FluidParams::b_food_rate`.
+    pub fn food_rate(self) -> f64 {
+        match self {
+            Terrain::Open | Terrain::Membrane => 1.0,
+            Terrain::Rock => 0.0,
+            Terrain::Soil => 2.0,
+        }
+    }
+
+    /// Whether cells can move or spawn onto it.
+    pub fn passable(self) -> bool {
+        match self {
+            Terrain::Open | Terrain::Soil => true,
+            Terrain::Rock | Terrain::Membrane => false,
+        }
+    }
+
+    /// Added to the color of the hex.
+    pub fn color(self) -> [f32; 3] {
+        match self {
+            Terrain::Open => [0.0, 0.0, 0.0],
+            Terrain::Rock => [0.35, 0.35, 0.35],
+            Terrain::Membrane => [0.1, 0.1, 0.3],
+            Terrain::Soil => [0.2, 0.1, 0.0],
+        }
+    }
+
+    fn from_pixel(pixel: [u8; 4]) -> Terrain {
+        if pixel[3] < 128 {
+            return Terrain::Open;
+        }
+        let distance = |color: &[u8; 3]| -> u32 {
+            (0..3)
+                .map(|i| {
+                    let d = pixel[i] as i32 - color[i] as i32;
+                    (d * d) as u32
+                })
+                .sum()
+        };
+        MASK_COLORS
+            .iter()
+            .min_by_key(|&(color, _)| distance(color))
+            .map(|&(_, terrain)| terrain)
+            .unwrap()
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Png(png::DecodingError),
+    /// The image has a color type the mask can't be read from.
+    Unsupported(png::ColorType),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => write!(f, "{}", e),
+            Error::Png(ref e) => write!(f, "{}", e),
+            Error::Unsupported(t) => write!(f, "unsupported PNG color type {:?}", t),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<png::DecodingError> for Error {
+    fn from(e: png::DecodingError) -> Self {
+        Error::Png(e)
+    }
+}
+
+/// Reads a PNG mask stretched over a grid of the given size, returning the terrain of each tile
+/// in the same order as `Grid::tiles`.
+pub fn load_mask<P: AsRef<Path>>(
+    path: P,
+    width: usize,
+    height: usize,
+) -> Result<Vec<Terrain>, Error> {
+    read_mask(File::open(path)?, width, height)
+}
+
+fn read_mask<R: Read>(r: R, width: usize, height: usize) -> Result<Vec<Terrain>, Error> {
+    let (info, mut reader) = png::Decoder::new(r).read_info()?;
+    let mut buf = vec![0; info.buffer_size()];
+    reader.next_frame(&mut buf)?;
+
+    // Palettes are expanded and 16 bit channels stripped by the decoder, leaving a byte per
+    // channel.
+    let channels = match info.color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::RGB => 3,
+        png::ColorType::RGBA => 4,
+        t => return Err(Error::Unsupported(t)),
+    };
+    let pixel = |x: usize, y: usize| -> [u8; 4] {
+        let p = &buf[y * info.line_size + x * channels..];
+        match channels {
+            1 => [p[0], p[0], p[0], 255],
+            2 => [p[0], p[0], p[0], p[1]],
+            3 => [p[0], p[1], p[2], 255],
+            _ => [p[0], p[1], p[2], p[3]],
+        }
+    };
+
+    let (mask_width, mask_height) = (info.width as usize, info.height as usize);
+    let mut terrain = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            terrain.push(Terrain::from_pixel(
+                pixel(x * mask_width / width, y * mask_height / height),
+            ));
+        }
+    }
+    Ok(terrain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use png::HasParameters;
+
+    fn png(
+        width: u32,
+        height: u32,
+        color: png::ColorType,
+        depth: png::BitDepth,
+        palette: Option<&[u8]>,
+        data: &[u8],
+    ) -> Vec<u8> {
+        let mut out = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut out, width, height);
+            encoder.set(color).set(depth);
+            let mut writer = encoder.write_header().unwrap();
+            if let Some(palette) = palette {
+                writer.write_chunk(*b"PLTE", palette).unwrap();
+            }
+            writer.write_image_data(data).unwrap();
+        }
+        out
+    }
+
+    #[test]
+    fn pixels_match_the_nearest_color() {
+        use self::Terrain::*;
+        let rgba = [
+            [250, 240, 255, 255],
+            [40, 20, 30, 255],
+            [30, 10, 200, 255],
+            [60, 180, 90, 255],
+            // Transparent pixels are open whatever their color.
+            [0, 0, 0, 100],
+            [0, 0, 255, 0],
+        ];
+        let expected = [Open, Rock, Membrane, Soil, Open, Open];
+        for (pixel, &terrain) in rgba.iter().zip(&expected) {
+            assert_eq!(Terrain::from_pixel(*pixel), terrain, "{:?}", pixel);
+        }
+    }
+
+    #[test]
+    fn mask_is_stretched_over_the_grid() {
+        use self::Terrain::*;
+        // Rock and membrane over soil and open ground.
+        let data = [0, 0, 0, 0, 0, 255, 0, 255, 0, 255, 255, 255];
+        let mask = png(2, 2, png::ColorType::RGB, png::BitDepth::Eight, None, &data);
+        let terrain = read_mask(&mask[..], 4, 3).unwrap();
+        assert_eq!(
+            terrain,
+            [
+                Rock, Rock, Membrane, Membrane,
+                Rock, Rock, Membrane, Membrane,
+                Soil, Soil, Open, Open,
+            ]
+        );
+        // Shrunk, every tile gets the pixel its corner falls on.
+        assert_eq!(read_mask(&mask[..], 1, 1).unwrap(), [Rock]);
+    }
+
+    #[test]
+    fn decoder_transforms_leave_a_byte_per_channel() {
+        use self::Terrain::*;
+        // Palettes are expanded.
+        let palette = [0, 0, 255, 0, 255, 0];
+        let mask = png(
+            3,
+            1,
+            png::ColorType::Indexed,
+            png::BitDepth::Eight,
+            Some(&palette),
+            &[1, 0, 1],
+        );
+        assert_eq!(read_mask(&mask[..], 3, 1).unwrap(), [Soil, Membrane, Soil]);
+        // 16 bit channels are stripped to their high byte.
+        let mask = png(
+            2,
+            1,
+            png::ColorType::Grayscale,
+            png::BitDepth::Sixteen,
+            None,
+            &[0xff, 0x00, 0x00, 0xff],
+        );
+        assert_eq!(read_mask(&mask[..], 2, 1).unwrap(), [Open, Rock]);
+        let mask = png(
+            2,
+            1,
+            png::ColorType::GrayscaleAlpha,
+            png::BitDepth::Eight,
+            None,
+            &[0, 255, 0, 0],
+        );
+        assert_eq!(read_mask(&mask[..], 2, 1).unwrap(), [Rock, Open]);
+    }
+}